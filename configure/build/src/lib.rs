@@ -1,6 +1,8 @@
 pub use configure_options as profile;
 pub use profile::Profile;
 
+use std::path::PathBuf;
+
 const PKG_DIR: &str = env!("CARGO_MANIFEST_DIR");
 
 pub struct Config {
@@ -30,16 +32,29 @@ impl Config {
     }
     pub fn cfg(&self) -> &Self {
         println!("cargo::rustc-cfg=target_machine={:?}", self.profile.machine.cfg());
+        println!("cargo::rustc-check-cfg=cfg(target_boot, values(\"firmware\", \"sbi\"))");
+        println!("cargo::rustc-cfg=target_boot={:?}", self.profile.boot.cfg());
         println!("cargo::rustc-check-cfg=cfg(target_device, values({}))", profile::Device::all().join(", "));
         for device in &self.profile.device {
             println!("cargo::rustc-cfg=target_device={:?}", device.cfg());
         }
+        println!("cargo::rustc-env=BLUEMETAL_MACHINE={}", self.profile.machine.cfg());
+        let devices: Vec<_> = self.profile.device.iter().map(|device| device.cfg()).collect();
+        println!("cargo::rustc-env=BLUEMETAL_DEVICES={}", devices.join(","));
+        println!("cargo::rustc-env=BLUEMETAL_LOG={}", self.profile.log.filter());
+        println!("cargo::rustc-env=BLUEMETAL_LOG_COLOUR={}", self.profile.log.colour);
+        if let Some(memory) = &self.profile.memory {
+            println!("cargo::rustc-env=BLUEMETAL_MEMORY_BASE={:#x}", memory.base);
+            println!("cargo::rustc-env=BLUEMETAL_MEMORY_SIZE={:#x}", memory.size);
+        }
         self
     }
     pub fn bin(&self) -> &Self {
         let linker_script = &self.profile.linker_script;
         println!("cargo::rerun-if-changed={PKG_DIR}/link/{linker_script}");
         println!("cargo::rustc-link-arg-bins=-T{PKG_DIR}/link/{linker_script}");
+        // the space the configure tool fills with the symbol table
+        println!("cargo::rustc-link-arg-bins=--defsym=_symbols_size={:#x}", self.profile.symbols.size);
         self
     }
     pub fn library(&self, name: &str, paths: &[&str]) -> &Self {
@@ -49,4 +64,24 @@ impl Config {
         }
         self
     }
+    /// Link the assembly in `paths` into a static user program with
+    /// `link/user.ld`, returning its path in `OUT_DIR`.
+    pub fn executable(&self, name: &str, paths: &[&str]) -> PathBuf {
+        let out_dir = std::env::var_os("OUT_DIR").expect("OUT_DIR is not set");
+        let path = PathBuf::from(out_dir).join(name);
+        let status = self.build.get_compiler().to_command()
+            .args(["-nostdlib", "-static", "-fuse-ld=lld"])
+            .arg(format!("-Wl,-T{PKG_DIR}/link/user.ld"))
+            .arg("-o")
+            .arg(&path)
+            .args(paths)
+            .status()
+            .expect("failed to run the compiler");
+        assert!(status.success(), "failed to link {name}");
+        println!("cargo::rerun-if-changed={PKG_DIR}/link/user.ld");
+        for path in paths {
+            println!("cargo::rerun-if-changed={path:?}");
+        }
+        path
+    }
 }
@@ -1,3 +1,5 @@
+mod symbols;
+
 use std::{os::unix::process::CommandExt, path::{Path, PathBuf}, process::ExitCode};
 
 use clap::{Parser, Subcommand};
@@ -32,45 +34,57 @@ fn main() -> std::process::ExitCode {
 
     match args.command {
         Command::Build {  } => {
-            build(&path, &profile);
+            build(&path, &profile)
         },
         Command::Run {  } => {
-            run(&path, &profile);
+            run(&path, &profile)
         },
         Command::CargoRunner { path } => {
-            cargo_runner(&profile, &path);
+            cargo_runner(&profile, &path)
         },
     }
-    ExitCode::SUCCESS
 }
 
-fn build(path: &Path, profile: &Profile) {
+fn build(path: &Path, profile: &Profile) -> ExitCode {
     use std::process::Command;
 
-//build-std = ["core", "compiler_builtins"]
+//build-std = ["core", "compiler_builtins", "alloc"]
 //build-std-features = ["compiler-builtins-mem", "panic-unwind"]
     let mut command = Command::new("cargo");
     command.arg("+nightly")
         .arg("build")
         .arg(format!("--target=configure/build/target/{}", profile.target))
-        .arg("-Zbuild-std=core,compiler_builtins")
+        .arg("-Zbuild-std=core,compiler_builtins,alloc")
         .arg("-Zbuild-std-features=compiler-builtins-mem,panic-unwind")
         .arg("--package=bluemetal")
         .env("BLUEMETAL_PROFILE", path);
     println!("command: {command:?}");
-    let error = command.exec();
-    panic!("failed to run cargo: {error}");
+    match command.status() {
+        Ok(status) if status.success() => {},
+        Ok(_) => return ExitCode::FAILURE,
+        Err(error) => panic!("failed to run cargo: {error}"),
+    }
+    // the image cargo just built, named after the target specification
+    let target = profile.target.to_string();
+    let target = target.trim_end_matches(".json");
+    let target_dir = std::env::var_os("CARGO_TARGET_DIR").unwrap_or("target".into());
+    let image: PathBuf = [target_dir.as_ref(), target.as_ref(), Path::new("debug/bluemetal")].iter().collect();
+    if embed_symbols(profile, &image) {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
 }
-fn run(path: &Path, profile: &Profile) {
+fn run(path: &Path, profile: &Profile) -> ! {
     use std::process::Command;
 
-//build-std = ["core", "compiler_builtins"]
+//build-std = ["core", "compiler_builtins", "alloc"]
 //build-std-features = ["compiler-builtins-mem", "panic-unwind"]
     let mut command = Command::new("cargo");
     command.arg("+nightly")
         .arg("run")
         .arg(format!("--target=configure/build/target/{}", profile.target))
-        .arg("-Zbuild-std=core,compiler_builtins")
+        .arg("-Zbuild-std=core,compiler_builtins,alloc")
         .arg("-Zbuild-std-features=compiler-builtins-mem,panic-unwind")
         .arg("--package=bluemetal")
         .env("BLUEMETAL_PROFILE", path);
@@ -78,8 +92,11 @@ fn run(path: &Path, profile: &Profile) {
     let error = command.exec();
     panic!("failed to run cargo: {error}");
 }
-fn cargo_runner(profile: &Profile, path: &Path) {
+fn cargo_runner(profile: &Profile, path: &Path) -> ExitCode {
     use std::process::Command;
+    if !embed_symbols(profile, path) {
+        return ExitCode::FAILURE;
+    }
     let args = profile.runner.as_slice();
     let Some(program) = args.get(0) else {
         panic!("no runner provided for this profile");
@@ -92,5 +109,20 @@ fn cargo_runner(profile: &Profile, path: &Path) {
             command.arg(arg);
         }
     }
-    command.exec();
+    let error = command.exec();
+    panic!("failed to run {program}: {error}");
+}
+/// Fill the symbol table of the kernel image at `path`, reporting whether it
+/// fit.
+fn embed_symbols(profile: &Profile, path: &Path) -> bool {
+    match symbols::embed(path, profile.symbols.lines) {
+        Ok((used, reserved)) => {
+            println!("symbols: {used:#x} of {reserved:#x} bytes");
+            true
+        },
+        Err(error) => {
+            eprintln!("failed to embed symbols in {path:?}: {error}");
+            false
+        },
+    }
 }
@@ -0,0 +1,305 @@
+//! Embedding a symbol table in the kernel image, so that backtraces can name
+//! the functions they pass through.
+//!
+//! The linker scripts reserve the `.symbols` section, which is filled in after
+//! linking with a table of the following, all little-endian:
+//!
+//! | Field   | Contents                                                    |
+//! |---------|-------------------------------------------------------------|
+//! | header  | `b"SYMS"`, then u32 counts of symbols, lines and files, then the u64 base address |
+//! | symbols | `{ address: u32, size: u32, name: u32 }`, sorted by address |
+//! | lines   | `{ address: u32, file: u16, line: u16 }`, sorted by address |
+//! | files   | `{ name: u32 }`                                             |
+//! | strings | a u16 length, then the bytes                                |
+//!
+//! Addresses are offsets from the base address, and names are offsets into
+//! the strings. A symbol without a size extends to the next one. A line of 0
+//! marks addresses without line information, and there are no lines unless
+//! the profile asks for them.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use object::{Object, ObjectSection, ObjectSymbol, SectionKind, SymbolKind};
+
+const MAGIC: &[u8; 4] = b"SYMS";
+const HEADER_SIZE: usize = 24;
+const SYMBOL_SIZE: usize = 12;
+const LINE_SIZE: usize = 8;
+const FILE_SIZE: usize = 4;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Elf(object::Error),
+    Dwarf(gimli::Error),
+    /// The image has no `.symbols` section to fill.
+    NoSection,
+    /// An address is too far from the lowest symbol to be stored.
+    OutOfRange(u64),
+    /// The table does not fit in the section.
+    TooLarge { needed: usize, reserved: usize },
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "failed to access the image: {e}"),
+            Self::Elf(e) => write!(f, "failed to parse the image: {e}"),
+            Self::Dwarf(e) => write!(f, "failed to parse the debug information: {e}"),
+            Self::NoSection => write!(f, "the image has no .symbols section"),
+            Self::OutOfRange(address) => write!(f, "address {address:#x} is out of range of the table"),
+            Self::TooLarge { needed, reserved } => write!(
+                f,
+                "the table needs {needed:#x} bytes but {reserved:#x} are reserved, \
+                raise `symbols.size` in the profile",
+            ),
+        }
+    }
+}
+impl std::error::Error for Error {}
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+impl From<object::Error> for Error {
+    fn from(e: object::Error) -> Self {
+        Self::Elf(e)
+    }
+}
+impl From<gimli::Error> for Error {
+    fn from(e: gimli::Error) -> Self {
+        Self::Dwarf(e)
+    }
+}
+
+struct Symbol<'a> {
+    address: u64,
+    size: u64,
+    name: &'a str,
+}
+
+/// Where the line information changes, with the file index and line, or None
+/// past the end of a sequence.
+type Line = (u64, Option<(usize, u64)>);
+
+/// Fill the `.symbols` section of the ELF image at `path`, with line
+/// information if `lines` is set, returning the bytes used and reserved.
+pub fn embed(path: &Path, lines: bool) -> Result<(usize, usize), Error> {
+    let mut image = std::fs::read(path)?;
+    let (offset, reserved, table) = {
+        let file = object::File::parse(&*image)?;
+        let section = file.section_by_name(".symbols").ok_or(Error::NoSection)?;
+        let (offset, reserved) = section.file_range().ok_or(Error::NoSection)?;
+        let symbols = symbols(&file);
+        let (files, lines) = if lines { self::lines(&file)? } else { Default::default() };
+        (offset as usize, reserved as usize, encode(&symbols, &files, &lines)?)
+    };
+    if table.len() > reserved {
+        return Err(Error::TooLarge { needed: table.len(), reserved });
+    }
+    let section = &mut image[offset..offset + reserved];
+    section.fill(0);
+    section[..table.len()].copy_from_slice(&table);
+    std::fs::write(path, image)?;
+    Ok((table.len(), reserved))
+}
+
+/// The functions and code labels of the image, one per address.
+fn symbols<'a>(file: &object::File<'a>) -> Vec<Symbol<'a>> {
+    let in_text = |symbol: &object::Symbol| {
+        let section = symbol.section_index().and_then(|index| file.section_by_index(index).ok());
+        section.is_some_and(|section| section.kind() == SectionKind::Text)
+    };
+    let mut symbols: Vec<_> = file.symbols()
+        .filter(|symbol| symbol.is_definition() && in_text(symbol))
+        .filter(|symbol| matches!(symbol.kind(), SymbolKind::Text | SymbolKind::Label))
+        .filter_map(|symbol| {
+            let name = symbol.name().ok()?;
+            // assembler temporaries and mapping symbols name nothing useful
+            if name.is_empty() || name.starts_with(".L") || name.starts_with('$') {
+                return None;
+            }
+            // prefer functions with sizes, then global symbols
+            let rank = (symbol.kind() != SymbolKind::Text, symbol.size() == 0, symbol.is_local());
+            Some((rank, Symbol { address: symbol.address(), size: symbol.size(), name: strip_hash(name) }))
+        })
+        .collect();
+    symbols.sort_by(|(a_rank, a), (b_rank, b)| a.address.cmp(&b.address).then(a_rank.cmp(b_rank)));
+    symbols.dedup_by_key(|(_, symbol)| symbol.address);
+    symbols.into_iter().map(|(_, symbol)| symbol).collect()
+}
+
+/// Remove the hash that legacy Rust mangling appends to every path, which
+/// takes space without helping to read a backtrace, along with the `E` that
+/// ends the path.
+fn strip_hash(name: &str) -> &str {
+    const HASH: usize = "17h0123456789abcdefE".len();
+    let is_hash = |hash: &str| {
+        hash.starts_with("17h") && hash.ends_with('E') && hash[3..HASH - 1].bytes().all(|b| b.is_ascii_hexdigit())
+    };
+    match name.len().checked_sub(HASH) {
+        Some(start) if name.starts_with("_ZN") && name.is_char_boundary(start) && is_hash(&name[start..]) => &name[..start],
+        _ => name,
+    }
+}
+
+/// The source files and the rows of the line programs of the image.
+fn lines(file: &object::File) -> Result<(Vec<String>, Vec<Line>), Error> {
+    let endian = if file.is_little_endian() { gimli::RunTimeEndian::Little } else { gimli::RunTimeEndian::Big };
+    let load = |id: gimli::SectionId| -> Result<Cow<[u8]>, gimli::Error> {
+        let data = file.section_by_name(id.name()).and_then(|section| section.uncompressed_data().ok());
+        Ok(data.unwrap_or(Cow::Borrowed(&[])))
+    };
+    let sections = gimli::DwarfSections::load(load)?;
+    let dwarf = sections.borrow(|section| gimli::EndianSlice::new(section, endian));
+    let root = std::env::current_dir()?;
+
+    let mut files = Vec::new();
+    let mut indices = HashMap::new();
+    let mut lines = Vec::new();
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let Some(program) = unit.line_program.clone() else {
+            continue;
+        };
+        let mut rows = program.rows();
+        while let Some((header, row)) = rows.next_row()? {
+            if row.end_sequence() {
+                lines.push((row.address(), None));
+                continue;
+            }
+            let Some(entry) = row.file(header) else {
+                continue;
+            };
+            // each part is relative to the one before unless it is absolute
+            let mut path = PathBuf::new();
+            if let Some(directory) = unit.comp_dir {
+                path.push(&*directory.to_string_lossy());
+            }
+            if let Some(directory) = entry.directory(header) {
+                path.push(&*dwarf.attr_string(&unit, directory)?.to_string_lossy());
+            }
+            path.push(&*dwarf.attr_string(&unit, entry.path_name())?.to_string_lossy());
+            let path = shorten(&path, &root);
+            let index = *indices.entry(path.clone()).or_insert_with(|| {
+                files.push(path);
+                files.len() - 1
+            });
+            let line = row.line().map_or(0, |line| line.get());
+            lines.push((row.address(), Some((index, line))));
+        }
+    }
+    // the end of a sequence gives way to a sequence starting at its address
+    lines.sort_by_key(|&(address, line)| (address, line.is_some()));
+    lines.dedup_by(|next, previous| {
+        if next.0 == previous.0 {
+            *previous = *next;
+            true
+        } else {
+            next.1 == previous.1
+        }
+    });
+    Ok((files, lines))
+}
+
+/// A path relative to the workspace or, for the standard library, to the
+/// Rust source tree.
+fn shorten(path: &Path, root: &Path) -> String {
+    let path = path.strip_prefix(root).unwrap_or(path).to_string_lossy();
+    match path.find("/library/") {
+        Some(index) => path[index + 1..].into(),
+        None => path.into(),
+    }
+}
+
+fn encode(symbols: &[Symbol], files: &[String], lines: &[Line]) -> Result<Vec<u8>, Error> {
+    let base = symbols.first().map_or(0, |symbol| symbol.address);
+    let offset = |address: u64| {
+        address.checked_sub(base).and_then(|offset| u32::try_from(offset).ok()).ok_or(Error::OutOfRange(address))
+    };
+    let mut strings = Strings::default();
+    // sequences of code removed by the linker start at 0
+    let lines: Vec<_> = lines.iter().filter(|(address, _)| *address >= base).collect();
+
+    let mut table = Vec::new();
+    table.extend_from_slice(MAGIC);
+    table.extend_from_slice(&(symbols.len() as u32).to_le_bytes());
+    table.extend_from_slice(&(lines.len() as u32).to_le_bytes());
+    table.extend_from_slice(&(files.len() as u32).to_le_bytes());
+    table.extend_from_slice(&base.to_le_bytes());
+    for symbol in symbols {
+        table.extend_from_slice(&offset(symbol.address)?.to_le_bytes());
+        table.extend_from_slice(&u32::try_from(symbol.size).unwrap_or(0).to_le_bytes());
+        table.extend_from_slice(&strings.add(symbol.name).to_le_bytes());
+    }
+    for &&(address, line) in &lines {
+        let (file, line) = line.map_or((0, 0), |(file, line)| (file, line));
+        let file = u16::try_from(file).map_err(|_| Error::OutOfRange(address))?;
+        let line = u16::try_from(line).unwrap_or(u16::MAX);
+        table.extend_from_slice(&offset(address)?.to_le_bytes());
+        table.extend_from_slice(&file.to_le_bytes());
+        table.extend_from_slice(&line.to_le_bytes());
+    }
+    for file in files {
+        table.extend_from_slice(&strings.add(file).to_le_bytes());
+    }
+    let expected = HEADER_SIZE + symbols.len() * SYMBOL_SIZE + lines.len() * LINE_SIZE + files.len() * FILE_SIZE;
+    assert_eq!(table.len(), expected, "symbol table layout is inconsistent");
+    table.extend_from_slice(&strings.data);
+    Ok(table)
+}
+
+/// The strings of the table, each stored once.
+#[derive(Default)]
+struct Strings {
+    data: Vec<u8>,
+    offsets: HashMap<String, u32>,
+}
+impl Strings {
+    fn add(&mut self, string: &str) -> u32 {
+        if let Some(&offset) = self.offsets.get(string) {
+            return offset;
+        }
+        let offset = self.data.len() as u32;
+        // longer strings are cut short rather than failing the build
+        let bytes = &string.as_bytes()[..string.len().min(u16::MAX as usize)];
+        self.data.extend_from_slice(&(bytes.len() as u16).to_le_bytes());
+        self.data.extend_from_slice(bytes);
+        self.offsets.insert(string.into(), offset);
+        offset
+    }
+}
+
+#[cfg(test)]
+#[path = "../../../crates/panic/src/demangle.rs"]
+mod demangle;
+
+#[cfg(test)]
+mod tests {
+    use super::demangle::Demangle;
+    use super::strip_hash;
+
+    #[test]
+    fn stripped_names_demangle() {
+        let name = "_ZN4core9panicking5panic17h0123456789abcdefE";
+        assert_eq!(strip_hash(name), "_ZN4core9panicking5panic");
+        assert_eq!(Demangle(strip_hash(name)).to_string(), "core::panicking::panic");
+        assert_eq!(Demangle(name).to_string(), "core::panicking::panic");
+
+        let name = "_ZN62_$LT$core..char..EscapeDebug$u20$as$u20$core..fmt..Display$GT$3fmt17h1c5d6d004087ba29E";
+        assert_eq!(
+            Demangle(strip_hash(name)).to_string(),
+            "<core::char::EscapeDebug as core::fmt::Display>::fmt",
+        );
+    }
+    #[test]
+    fn other_names_are_kept() {
+        for name in ["_start", "_ZN4core3fmt5write", "_RNvCs1234_7mycrate3foo", "_ZN3foo17hnothexnothexnoE"] {
+            assert_eq!(strip_hash(name), name);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::{fmt::Display, path::PathBuf};
+use std::{collections::BTreeMap, fmt::Display, path::PathBuf};
 
 use serde::Deserialize;
 
@@ -37,12 +37,23 @@ pub fn from_str(profile: &str) -> Result<Profile, Error> {
 pub struct Profile {
     pub machine: Machine,
     pub target: Target,
+    /// How the kernel is entered.
+    #[serde(default)]
+    pub boot: Boot,
     #[serde(rename = "linker-script")]
     pub linker_script: String,
     #[serde(default)]
     pub device: Vec<Device>,
     /// Compiler options for `cc`.
     pub compiler: Option<Compiler>,
+    /// Physical memory to use if the device tree does not describe any.
+    pub memory: Option<Memory>,
+    /// The symbol table embedded in the kernel for backtraces.
+    #[serde(default)]
+    pub symbols: Symbols,
+    /// Which kernel log records are kept and how they are printed.
+    #[serde(default)]
+    pub log: Log,
     pub runner: Vec<String>,
 }
 
@@ -62,6 +73,25 @@ impl Machine {
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub enum Boot {
+    /// In machine mode as the firmware, dropping to supervisor mode itself.
+    #[default]
+    #[serde(rename = "firmware")]
+    Firmware,
+    /// In supervisor mode from SBI firmware such as OpenSBI.
+    #[serde(rename = "sbi")]
+    Sbi,
+}
+impl Boot {
+    pub fn cfg(self) -> &'static str {
+        match self {
+            Self::Firmware => "firmware",
+            Self::Sbi => "sbi",
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub enum Target {
     #[serde(rename = "builtin")]
@@ -108,6 +138,91 @@ devices!{
     Uart16550 = "uart16550",
 }
 
+#[derive(Debug, Deserialize)]
+pub struct Memory {
+    pub base: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Symbols {
+    /// The bytes reserved for the table in the kernel image, which the build
+    /// fails if the table does not fit.
+    pub size: u64,
+    /// Whether to include the source file and line of every address.
+    pub lines: bool,
+}
+impl Default for Symbols {
+    fn default() -> Self {
+        Self {
+            size: 0x10_0000,
+            lines: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Log {
+    /// The most detailed level logged by modules without a level of their
+    /// own.
+    pub level: LogLevel,
+    /// The levels of modules and their submodules, by path such as
+    /// `thread::scheduler`.
+    pub modules: BTreeMap<String, LogLevel>,
+    /// Whether to colour the level of each record with ANSI escapes.
+    pub colour: bool,
+}
+impl Log {
+    /// The filter passed to the kernel: the default level, then
+    /// `module=level` for each module, separated by commas.
+    pub fn filter(&self) -> String {
+        let mut filter = self.level.cfg().to_string();
+        for (module, level) in &self.modules {
+            filter.push_str(&format!(",{module}={}", level.cfg()));
+        }
+        filter
+    }
+}
+impl Default for Log {
+    fn default() -> Self {
+        Self {
+            level: LogLevel::Info,
+            modules: BTreeMap::new(),
+            colour: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub enum LogLevel {
+    #[serde(rename = "off")]
+    Off,
+    #[serde(rename = "error")]
+    Error,
+    #[serde(rename = "warn")]
+    Warn,
+    #[serde(rename = "info")]
+    Info,
+    #[serde(rename = "debug")]
+    Debug,
+    #[serde(rename = "trace")]
+    Trace,
+}
+impl LogLevel {
+    pub fn cfg(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename = "compiler")]
 pub struct Compiler {
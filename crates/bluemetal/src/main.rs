@@ -4,9 +4,36 @@
 extern crate init;
 use ::serial::prelude::*;
 
+mod monitor;
+mod power;
+
 #[no_mangle]
-fn bluemetal(hart_id: usize) -> ! {
+fn bluemetal(hart_id: usize, fdt: Option<fdt::Fdt<'static>>) -> ! {
     println!("Hello, Hart {hart_id}!");
+    if fdt.is_none() {
+        ::log::warn!("no device tree was found");
+    }
+    let memory = ::memory::frame::stats();
+    println!(
+        "{} MiB of {} MiB physical memory free",
+        (memory.free * ::memory::FRAME_SIZE) >> 20,
+        (memory.total * ::memory::FRAME_SIZE) >> 20,
+    );
+    println!("{} harts online", ::hart::count());
+
+    // the console interrupt is routed to this hart
+    ::thread::Builder::new()
+        .name("monitor")
+        .hart(hart_id)
+        .spawn(move || monitor::run(hart_id, fdt))
+        .expect("failed to spawn the monitor");
+    ::thread::idle()
+}
 
-    todo!();
+/// The entry point of every hart but the boot hart, which run threads once
+/// online.
+#[no_mangle]
+fn bluemetal_hart(hart_id: usize) -> ! {
+    println!("Hello, Hart {hart_id}!");
+    ::thread::idle()
 }
@@ -0,0 +1,450 @@
+//! An interactive monitor for inspecting the machine over the console.
+
+use core::fmt;
+use core::panic::AssertUnwindSafe;
+use ::serial::prelude::*;
+
+const PROMPT: &str = "bluemetal> ";
+
+/// Run the monitor on the console forever.
+pub fn run(hart_id: usize, fdt: Option<fdt::Fdt<'static>>) -> ! {
+    println!("Bluemetal monitor on hart {hart_id}. Type `help` for a list of commands.");
+    let mut buffer = [0; 256];
+    loop {
+        print!("{PROMPT}");
+        let line = match ::serial::read_line(&mut buffer) {
+            Ok(line) => line,
+            Err(::serial::ReadLineError::Interrupted) => continue,
+            Err(::serial::ReadLineError::NoDevice) => panic!("the monitor requires a console"),
+        };
+        let mut args = line.split_ascii_whitespace();
+        let Some(name) = args.next() else {
+            continue;
+        };
+        let Some(command) = COMMANDS.iter().find(|command| command.name == name) else {
+            println!("unknown command `{name}`, try `help`");
+            continue;
+        };
+        let mut args = Args { line, args, hart_id, fdt };
+        // a command that panics only ends the command
+        match ::panic::catch_unwind(AssertUnwindSafe(|| (command.run)(&mut args))) {
+            Ok(Ok(())) => {},
+            Ok(Err(Error::Usage)) => println!("usage: {} {}", command.name, command.usage),
+            Ok(Err(e)) => println!("{}: {e}", command.name),
+            Err(_) => println!("{}: panicked", command.name),
+        }
+    }
+}
+
+struct Command {
+    name: &'static str,
+    usage: &'static str,
+    help: &'static str,
+    run: for<'a> fn(&mut Args<'a>) -> Result<(), Error<'a>>,
+}
+const COMMANDS: &[Command] = &[
+    Command { name: "help", usage: "", help: "List the available commands", run: help },
+    Command { name: "md", usage: "<address> [length]", help: "Dump memory as hex and ASCII", run: memory_dump },
+    Command { name: "mem", usage: "", help: "Show physical memory and heap usage", run: memory },
+    Command { name: "vtop", usage: "<address>", help: "Translate a kernel virtual address", run: translate },
+    Command { name: "csr", usage: "<name> [value]", help: "Read or write a control and status register", run: csr },
+    Command { name: "harts", usage: "", help: "List the harts", run: harts },
+    Command { name: "irq", usage: "", help: "List the registered external interrupts", run: interrupts },
+    Command { name: "uptime", usage: "", help: "Show the time since boot and the timer", run: uptime },
+    Command { name: "dmesg", usage: "[count]", help: "Show the last kernel log records", run: dmesg },
+    Command { name: "threads", usage: "", help: "List the kernel threads", run: threads },
+    Command { name: "sleep", usage: "<milliseconds>", help: "Sleep the monitor thread", run: sleep },
+    Command { name: "run", usage: "[program]", help: "Run an embedded program, or list them", run: run_program },
+    Command { name: "ps", usage: "", help: "List the running processes", run: processes },
+    Command { name: "devices", usage: "", help: "Show the machine and devices from the profile", run: devices },
+    Command { name: "sbi", usage: "", help: "Show the SBI firmware and its extensions", run: sbi },
+    Command { name: "dt", usage: "[path]", help: "Show a device tree node and its children", run: device_tree },
+    Command { name: "panic", usage: "[message]", help: "Panic, unwinding back to the monitor", run: panic },
+    Command { name: "reboot", usage: "", help: "Reset the machine", run: reboot },
+    Command { name: "poweroff", usage: "", help: "Power off the machine", run: poweroff },
+];
+
+struct Args<'a> {
+    line: &'a str,
+    args: core::str::SplitAsciiWhitespace<'a>,
+    hart_id: usize,
+    fdt: Option<fdt::Fdt<'static>>,
+}
+impl<'a> Args<'a> {
+    fn next(&mut self) -> Option<&'a str> {
+        self.args.next()
+    }
+    /// The remainder of the line, starting from the next argument.
+    fn rest(&mut self) -> Option<&'a str> {
+        let next = self.next()?;
+        let offset = next.as_ptr() as usize - self.line.as_ptr() as usize;
+        Some(self.line[offset..].trim_end())
+    }
+    fn number(&mut self) -> Result<Option<usize>, Error<'a>> {
+        let Some(arg) = self.next() else {
+            return Ok(None);
+        };
+        let number = match arg.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16),
+            None => arg.parse(),
+        };
+        number.map(Some).map_err(|_| Error::InvalidNumber(arg))
+    }
+    fn end(&mut self) -> Result<(), Error<'a>> {
+        match self.next() {
+            Some(_) => Err(Error::Usage),
+            None => Ok(()),
+        }
+    }
+}
+
+enum Error<'a> {
+    Usage,
+    InvalidNumber(&'a str),
+    UnknownCsr(&'a str),
+    ReadOnlyCsr(&'a str),
+    UnknownNode(&'a str),
+    UnknownProgram(&'a str),
+    Process(::process::Error),
+    NoDeviceTree,
+    Unsupported,
+}
+impl<'a> fmt::Display for Error<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Usage => write!(f, "invalid arguments"),
+            Self::InvalidNumber(arg) => write!(f, "invalid number `{arg}`"),
+            Self::UnknownCsr(name) => write!(f, "unknown CSR `{name}`"),
+            Self::ReadOnlyCsr(name) => write!(f, "CSR `{name}` is read-only"),
+            Self::UnknownNode(path) => write!(f, "no device tree node `{path}`"),
+            Self::UnknownProgram(name) => write!(f, "no program `{name}`"),
+            Self::Process(e) => e.fmt(f),
+            Self::NoDeviceTree => write!(f, "no device tree was passed to the kernel"),
+            Self::Unsupported => write!(f, "not supported on this machine"),
+        }
+    }
+}
+
+fn help<'a>(args: &mut Args<'a>) -> Result<(), Error<'a>> {
+    args.end()?;
+    for command in COMMANDS {
+        let width = command.name.len() + 1 + command.usage.len();
+        println!("  {} {}{:pad$} {}", command.name, command.usage, "", command.help, pad = 28usize.saturating_sub(width));
+    }
+    Ok(())
+}
+
+fn memory_dump<'a>(args: &mut Args<'a>) -> Result<(), Error<'a>> {
+    let address = args.number()?.ok_or(Error::Usage)?;
+    let length = args.number()?.unwrap_or(64);
+    args.end()?;
+    let start = address;
+    let end = start.saturating_add(length);
+    let mut line = start & !0xf;
+    // the page last checked, and whether it can be read
+    let mut page = None;
+    while line < end {
+        print!("{line:016x}  ");
+        let mut ascii = [b' '; 16];
+        for (i, c) in ascii.iter_mut().enumerate() {
+            let address = line + i;
+            if address < start || address >= end {
+                print!("   ");
+                continue;
+            }
+            let base = address & !(::memory::FRAME_SIZE - 1);
+            let readable = match page {
+                Some((checked, readable)) if checked == base => readable,
+                _ => {
+                    let readable = is_readable(base);
+                    page = Some((base, readable));
+                    readable
+                },
+            };
+            if !readable {
+                print!("?? ");
+                continue;
+            }
+            let byte = unsafe { (address as *const u8).read_volatile() };
+            *c = if byte.is_ascii_graphic() || byte == b' ' { byte } else { b'.' };
+            print!("{byte:02x} ");
+        }
+        // Safety: only ASCII was written into the buffer
+        println!(" |{}|", unsafe { core::str::from_utf8_unchecked(&ascii) });
+        // the last line ends at the top of the address space
+        let Some(next) = line.checked_add(16) else {
+            break;
+        };
+        line = next;
+    }
+    Ok(())
+}
+
+/// Whether the page at `address` is mapped to physical memory.
+///
+/// Other pages are shown as `??` rather than read, as the monitor cannot
+/// recover from a fault, and reading a device can change its state.
+fn is_readable(address: usize) -> bool {
+    let Some((physical, flags)) = ::memory::paging::translate(address) else {
+        return false;
+    };
+    let mut memory = false;
+    ::memory::frame::for_each_region(|region, _| memory |= region.contains(physical));
+    memory && flags.contains(::memory::paging::Flags::READ)
+}
+
+fn memory<'a>(args: &mut Args<'a>) -> Result<(), Error<'a>> {
+    args.end()?;
+    let kernel = ::memory::kernel();
+    println!("kernel: {:#x}..{:#x}", kernel.start, kernel.end);
+    ::memory::frame::for_each_region(|region, stats| {
+        println!(
+            "  {:#x}..{:#x}: {} of {} frames free",
+            region.start, region.end, stats.free, stats.total,
+        );
+    });
+    let heap = ::heap::stats();
+    println!("heap: {} of {} bytes used", heap.used, heap.size);
+    Ok(())
+}
+
+fn translate<'a>(args: &mut Args<'a>) -> Result<(), Error<'a>> {
+    let address = args.number()?.ok_or(Error::Usage)?;
+    args.end()?;
+    match ::memory::paging::translate(address) {
+        Some((physical, flags)) => println!("{address:#x} -> {physical:#x} ({flags:?})"),
+        None => println!("{address:#x} is not mapped"),
+    }
+    Ok(())
+}
+
+fn csr<'a>(args: &mut Args<'a>) -> Result<(), Error<'a>> {
+    let name = args.next().ok_or(Error::Usage)?;
+    let value = args.number()?;
+    args.end()?;
+    let Some(csr) = CSRS.iter().find(|csr| csr.name == name) else {
+        return Err(Error::UnknownCsr(name));
+    };
+    match value {
+        None => println!("{name} = 0x{:016x}", (csr.read)()),
+        Some(value) => {
+            let write = csr.write.ok_or(Error::ReadOnlyCsr(name))?;
+            write(value);
+            println!("{name} = 0x{:016x}", (csr.read)());
+        }
+    }
+    Ok(())
+}
+
+fn harts<'a>(args: &mut Args<'a>) -> Result<(), Error<'a>> {
+    args.end()?;
+    let cpus = args.fdt.and_then(|fdt| fdt.find_node("/cpus"));
+    let Some(cpus) = cpus else {
+        for hart in ::hart::online() {
+            let state = if hart == args.hart_id { "running the monitor" } else { "idle" };
+            println!("  hart {hart}: {state}");
+        }
+        return Ok(());
+    };
+    for cpu in cpus.children().filter(|node| node.base_name() == "cpu") {
+        let Some(hart) = cpu.reg().next().map(|reg| reg.address as usize) else {
+            continue;
+        };
+        let isa = cpu.property("riscv,isa").and_then(|p| p.as_str()).unwrap_or("unknown");
+        let state = if hart == args.hart_id {
+            "running the monitor"
+        } else if ::hart::is_online(hart) {
+            "idle"
+        } else if cpu.is_enabled() {
+            "offline"
+        } else {
+            "disabled"
+        };
+        println!("  hart {hart}: {isa}, {state}");
+    }
+    Ok(())
+}
+
+fn interrupts<'a>(args: &mut Args<'a>) -> Result<(), Error<'a>> {
+    args.end()?;
+    println!("PLIC sources: {}", ::interrupt::plic::sources());
+    for irq in ::interrupt::registered() {
+        println!(
+            "  irq {}: hart {} (context {}), priority {}, handled {} times",
+            irq.irq, irq.hart, ::interrupt::plic::supervisor_context(irq.hart), irq.priority, irq.count,
+        );
+    }
+    Ok(())
+}
+
+fn uptime<'a>(args: &mut Args<'a>) -> Result<(), Error<'a>> {
+    args.end()?;
+    let uptime = ::time::uptime();
+    println!("up {}.{:06}s", uptime.as_secs(), uptime.subsec_micros());
+    println!("timebase: {} Hz, interrupts: {}", ::time::frequency(), ::time::backend());
+    Ok(())
+}
+
+fn dmesg<'a>(args: &mut Args<'a>) -> Result<(), Error<'a>> {
+    let count = args.number()?.unwrap_or(usize::MAX);
+    args.end()?;
+    ::logger::for_each(count, |record| println!("{record}"));
+    Ok(())
+}
+
+fn threads<'a>(args: &mut Args<'a>) -> Result<(), Error<'a>> {
+    args.end()?;
+    for thread in ::thread::threads() {
+        println!(
+            "  thread {}: {}, hart {}, {:?} priority, {}",
+            thread.id(), thread.name().unwrap_or("unnamed"), thread.hart(), thread.priority(), thread.state(),
+        );
+    }
+    Ok(())
+}
+
+fn sleep<'a>(args: &mut Args<'a>) -> Result<(), Error<'a>> {
+    let milliseconds = args.number()?.ok_or(Error::Usage)?;
+    args.end()?;
+    let start = ::time::Instant::now();
+    ::thread::sleep(::time::Duration::from_millis(milliseconds as u64));
+    let slept = start.elapsed();
+    println!("slept for {}.{:06}s", slept.as_secs(), slept.subsec_micros());
+    Ok(())
+}
+
+fn run_program<'a>(args: &mut Args<'a>) -> Result<(), Error<'a>> {
+    let Some(name) = args.next() else {
+        for (name, image) in ::process::PROGRAMS {
+            println!("  {name}: {} bytes", image.len());
+        }
+        return Ok(());
+    };
+    args.end()?;
+    let image = ::process::program(name).ok_or(Error::UnknownProgram(name))?;
+    let process = ::process::spawn(name, image).map_err(Error::Process)?;
+    let status = process.wait();
+    println!("process {} {status}", process.pid());
+    Ok(())
+}
+
+fn processes<'a>(args: &mut Args<'a>) -> Result<(), Error<'a>> {
+    args.end()?;
+    for process in ::process::processes() {
+        println!("  process {}: {}", process.pid(), process.name());
+    }
+    Ok(())
+}
+
+fn devices<'a>(args: &mut Args<'a>) -> Result<(), Error<'a>> {
+    args.end()?;
+    println!("machine: {}", env!("BLUEMETAL_MACHINE"));
+    for device in env!("BLUEMETAL_DEVICES").split(',').filter(|device| !device.is_empty()) {
+        println!("  {device}");
+    }
+    let console = ::serial::global().lock().device().map(|device| device.irq());
+    match console {
+        None => println!("console: none"),
+        Some(None) => println!("console: polled"),
+        Some(Some(irq)) => println!("console: irq {irq}"),
+    }
+    Ok(())
+}
+
+fn sbi<'a>(args: &mut Args<'a>) -> Result<(), Error<'a>> {
+    use ::sbi::base;
+    args.end()?;
+    let Ok(version) = base::spec_version() else {
+        println!("no SBI firmware");
+        return Ok(());
+    };
+    let id = base::impl_id().unwrap_or(usize::MAX);
+    let name = base::impl_name(id).unwrap_or("unknown");
+    let impl_version = base::impl_version().unwrap_or(0);
+    println!("SBI v{}.{}: {name} ({id:#x}) version {impl_version:#x}", version.major, version.minor);
+    const EXTENSIONS: &[(&str, usize)] = &[
+        ("TIME", ::sbi::time::EID),
+        ("IPI", ::sbi::ipi::EID),
+        ("RFENCE", ::sbi::rfence::EID),
+        ("HSM", ::sbi::hsm::EID),
+        ("SRST", ::sbi::srst::EID),
+        ("DBCN", ::sbi::dbcn::EID),
+    ];
+    for (name, eid) in EXTENSIONS {
+        let available = if base::probe_extension(*eid) { "available" } else { "missing" };
+        println!("  {name}: {available}");
+    }
+    Ok(())
+}
+
+fn device_tree<'a>(args: &mut Args<'a>) -> Result<(), Error<'a>> {
+    let path = args.next().unwrap_or("/");
+    args.end()?;
+    let fdt = args.fdt.ok_or(Error::NoDeviceTree)?;
+    let node = fdt.find_node(path).ok_or(Error::UnknownNode(path))?;
+    println!("{} {{", if node.name().is_empty() { "/" } else { node.name() });
+    for property in node.properties() {
+        println!("    {property:?};");
+    }
+    for child in node.children() {
+        println!("    {} {{ ... }};", child.name());
+    }
+    println!("}};");
+    Ok(())
+}
+
+fn panic<'a>(args: &mut Args<'a>) -> Result<(), Error<'a>> {
+    let message = args.rest().unwrap_or("test panic from the monitor");
+    panic!("{message}");
+}
+
+fn reboot<'a>(args: &mut Args<'a>) -> Result<(), Error<'a>> {
+    args.end()?;
+    crate::power::reboot();
+    Err(Error::Unsupported)
+}
+
+fn poweroff<'a>(args: &mut Args<'a>) -> Result<(), Error<'a>> {
+    args.end()?;
+    crate::power::poweroff();
+    Err(Error::Unsupported)
+}
+
+struct Csr {
+    name: &'static str,
+    read: fn() -> usize,
+    write: Option<fn(usize)>,
+}
+macro_rules! csrs {
+    ($($name:ident $(: $write:ident)?,)*) => {
+        &[
+            $(Csr {
+                name: stringify!($name),
+                read: || {
+                    let value;
+                    unsafe { core::arch::asm!(concat!("csrr {}, ", stringify!($name)), out(reg) value) };
+                    value
+                },
+                write: csrs!(@write $name $($write)?),
+            },)*
+        ]
+    };
+    (@write $name:ident write) => {
+        Some(|value| unsafe { core::arch::asm!(concat!("csrw ", stringify!($name), ", {}"), in(reg) value) })
+    };
+    (@write $name:ident) => { None };
+}
+const CSRS: &[Csr] = csrs! {
+    sstatus: write,
+    sie: write,
+    stvec: write,
+    sscratch: write,
+    sepc: write,
+    scause: write,
+    stval: write,
+    sip: write,
+    satp: write,
+    time,
+    cycle,
+    instret,
+};
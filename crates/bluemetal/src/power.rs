@@ -0,0 +1,17 @@
+//! Machine reset and power off.
+
+use sbi::srst::{system_reset, ResetReason, ResetType};
+
+/// Reset the machine.
+///
+/// Only returns if the machine cannot be reset.
+pub fn reboot() {
+    system_reset(ResetType::ColdReboot, ResetReason::None);
+}
+
+/// Power off the machine.
+///
+/// Only returns if the machine cannot be powered off.
+pub fn poweroff() {
+    system_reset(ResetType::Shutdown, ResetReason::None);
+}
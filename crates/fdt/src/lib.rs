@@ -0,0 +1,550 @@
+#![no_std]
+//! A flattened device tree parser.
+//!
+//! Reads the device tree blob passed to the kernel by firmware or QEMU without
+//! allocating.
+
+use core::fmt;
+
+const MAGIC: u32 = 0xd00d_feed;
+/// The oldest version with a backwards compatible layout.
+const LAST_COMPATIBLE_VERSION: u32 = 16;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/// The deepest node nesting supported when walking the whole tree.
+const MAX_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The blob does not start with the device tree magic number.
+    BadMagic,
+    /// The blob is not compatible with version 17.
+    BadVersion,
+    /// A block or token extends beyond the end of the blob.
+    Truncated,
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "invalid device tree magic"),
+            Self::BadVersion => write!(f, "unsupported device tree version"),
+            Self::Truncated => write!(f, "truncated device tree"),
+        }
+    }
+}
+
+/// A flattened device tree.
+#[derive(Clone, Copy)]
+pub struct Fdt<'a> {
+    data: &'a [u8],
+    structs: &'a [u8],
+    strings: &'a [u8],
+    reservations: &'a [u8],
+    boot_cpu: u32,
+}
+impl<'a> Fdt<'a> {
+    /// Parse a device tree blob in memory.
+    ///
+    /// # Safety
+    /// `ptr` must point to a device tree blob that is valid for reads for its
+    /// total size, and remains valid and unchanged for `'a`.
+    pub unsafe fn from_ptr(ptr: *const u8) -> Result<Self, Error> {
+        if ptr.is_null() {
+            return Err(Error::BadMagic);
+        }
+        let header = core::slice::from_raw_parts(ptr, 8);
+        if be32(header, 0) != Some(MAGIC) {
+            return Err(Error::BadMagic);
+        }
+        let size = be32(header, 4).ok_or(Error::Truncated)? as usize;
+        Self::from_bytes(core::slice::from_raw_parts(ptr, size))
+    }
+    /// Parse a device tree blob.
+    pub fn from_bytes(data: &'a [u8]) -> Result<Self, Error> {
+        let field = |i: usize| be32(data, i * 4).ok_or(Error::Truncated);
+        if field(0)? != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        let total_size = field(1)? as usize;
+        let data = data.get(..total_size).ok_or(Error::Truncated)?;
+        if field(6)? > LAST_COMPATIBLE_VERSION {
+            return Err(Error::BadVersion);
+        }
+        let block = |offset: u32, size: usize| {
+            data.get(offset as usize..)
+                .and_then(|block| block.get(..size.min(block.len())))
+                .ok_or(Error::Truncated)
+        };
+        Ok(Self {
+            data,
+            structs: block(field(2)?, field(9)? as usize)?,
+            strings: block(field(3)?, field(8)? as usize)?,
+            reservations: block(field(4)?, usize::MAX)?,
+            boot_cpu: field(7)?,
+        })
+    }
+    /// The address of the blob in memory.
+    pub fn as_ptr(&self) -> *const u8 {
+        self.data.as_ptr()
+    }
+    /// The total size of the blob in bytes.
+    pub fn total_size(&self) -> usize {
+        self.data.len()
+    }
+    /// The physical ID of the boot CPU.
+    pub fn boot_cpu(&self) -> u32 {
+        self.boot_cpu
+    }
+    /// Regions of memory reserved by the memory reservation block.
+    pub fn reservations(&self) -> impl Iterator<Item = Region> + 'a {
+        self.reservations.chunks_exact(16)
+            .map(|entry| Region {
+                address: be64(entry, 0).unwrap_or(0),
+                size: be64(entry, 8),
+            })
+            .take_while(|region| region.address != 0 || region.size != Some(0))
+    }
+    pub fn root(&self) -> Node<'a> {
+        let mut offset = 0;
+        while let Some((Token::Nop, next)) = self.token(offset) {
+            offset = next;
+        }
+        Node {
+            fdt: *self,
+            offset,
+            name: "",
+            parent_cells: Cells::DEFAULT,
+        }
+    }
+    /// Every node in the tree, depth first from the root.
+    pub fn nodes(&self) -> Nodes<'a> {
+        Nodes {
+            fdt: *self,
+            offset: self.root().offset,
+            depth: 0,
+            cells: [Cells::DEFAULT; MAX_DEPTH + 1],
+        }
+    }
+    /// Find a node by its full path, such as `/soc/serial@10000000`, or by an
+    /// alias from `/aliases`.
+    ///
+    /// A unit address may be omitted from a node name if it is unambiguous.
+    pub fn find_node(&self, path: &str) -> Option<Node<'a>> {
+        let path = match path.strip_prefix('/') {
+            Some(path) => path,
+            None => {
+                let (alias, rest) = path.split_once('/').unwrap_or((path, ""));
+                let alias = self.find_node("/aliases")?.property(alias)?.as_str()?;
+                let node = self.find_node(alias)?;
+                return rest.split('/').filter(|name| !name.is_empty())
+                    .try_fold(node, |node, name| node.child(name));
+            }
+        };
+        path.split('/').filter(|name| !name.is_empty())
+            .try_fold(self.root(), |node, name| node.child(name))
+    }
+    /// Find the node with a `phandle` property of `phandle`.
+    pub fn find_phandle(&self, phandle: u32) -> Option<Node<'a>> {
+        self.nodes().find(|node| node.phandle() == Some(phandle))
+    }
+    /// All nodes compatible with `compatible`.
+    pub fn find_compatible<'c>(&self, compatible: &'c str) -> impl Iterator<Item = Node<'a>> + 'c where 'a: 'c {
+        self.nodes().filter(move |node| node.is_compatible(compatible))
+    }
+    /// The `/chosen` node.
+    pub fn chosen(&self) -> Option<Node<'a>> {
+        self.find_node("/chosen")
+    }
+    /// The node referred to by `/chosen/stdout-path`, and its options.
+    pub fn stdout(&self) -> Option<(Node<'a>, Option<&'a str>)> {
+        let path = self.chosen()?.property("stdout-path")?.as_str()?;
+        let (path, options) = match path.split_once(':') {
+            Some((path, options)) => (path, Some(options)),
+            None => (path, None),
+        };
+        Some((self.find_node(path)?, options))
+    }
+    /// All `/memory` nodes.
+    pub fn memory(&self) -> impl Iterator<Item = Node<'a>> + 'a {
+        self.root().children().filter(|node| {
+            node.property("device_type").and_then(|p| p.as_str()) == Some("memory")
+                || node.base_name() == "memory"
+        })
+    }
+
+    fn token(&self, offset: usize) -> Option<(Token<'a>, usize)> {
+        let token = be32(self.structs, offset)?;
+        let offset = offset + 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = cstr(self.structs.get(offset..)?)?;
+                Some((Token::BeginNode(name), align4(offset + name.len() + 1)))
+            },
+            FDT_END_NODE => Some((Token::EndNode, offset)),
+            FDT_PROP => {
+                let len = be32(self.structs, offset)? as usize;
+                let name_offset = be32(self.structs, offset + 4)? as usize;
+                let value = self.structs.get(offset + 8..offset + 8 + len)?;
+                let name = cstr(self.strings.get(name_offset..)?)?;
+                Some((Token::Property(Property { name, value }), align4(offset + 8 + len)))
+            },
+            FDT_NOP => Some((Token::Nop, offset)),
+            FDT_END => Some((Token::End, offset)),
+            _ => None,
+        }
+    }
+}
+impl<'a> fmt::Debug for Fdt<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Fdt")
+            .field("address", &self.data.as_ptr())
+            .field("size", &self.data.len())
+            .finish()
+    }
+}
+
+enum Token<'a> {
+    BeginNode(&'a str),
+    EndNode,
+    Property(Property<'a>),
+    Nop,
+    End,
+}
+
+/// The number of cells used by addresses and sizes in `reg` properties.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cells {
+    pub address: u32,
+    pub size: u32,
+}
+impl Cells {
+    /// The values assumed when `#address-cells` and `#size-cells` are absent.
+    const DEFAULT: Self = Self { address: 2, size: 1 };
+}
+
+/// A region of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub address: u64,
+    /// The size of the region, if the node has a size cell.
+    pub size: Option<u64>,
+}
+
+#[derive(Clone, Copy)]
+pub struct Node<'a> {
+    fdt: Fdt<'a>,
+    /// Offset of the node's begin token in the structure block.
+    offset: usize,
+    name: &'a str,
+    /// The cells of the parent, which apply to this node's `reg` property.
+    parent_cells: Cells,
+}
+impl<'a> Node<'a> {
+    /// The full node name, including any unit address.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+    /// The node name without a unit address.
+    pub fn base_name(&self) -> &'a str {
+        self.name.split_once('@').map_or(self.name, |(name, _)| name)
+    }
+    pub fn properties(&self) -> Properties<'a> {
+        let offset = match self.fdt.token(self.offset) {
+            Some((Token::BeginNode(_), offset)) => offset,
+            _ => self.fdt.structs.len(),
+        };
+        Properties { fdt: self.fdt, offset }
+    }
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|property| property.name == name)
+    }
+    pub fn children(&self) -> Children<'a> {
+        let mut properties = self.properties();
+        for _ in &mut properties {}
+        Children {
+            fdt: self.fdt,
+            offset: properties.offset,
+            cells: self.cells(),
+        }
+    }
+    /// Find a child by name, with or without its unit address.
+    pub fn child(&self, name: &str) -> Option<Node<'a>> {
+        let mut children = self.children();
+        if name.contains('@') {
+            children.find(|child| child.name == name)
+        } else {
+            children.find(|child| child.base_name() == name)
+        }
+    }
+    /// The cells that apply to children of this node.
+    pub fn cells(&self) -> Cells {
+        let cells = |name, default| self.property(name).and_then(|p| p.as_u32()).unwrap_or(default);
+        Cells {
+            address: cells("#address-cells", Cells::DEFAULT.address),
+            size: cells("#size-cells", Cells::DEFAULT.size),
+        }
+    }
+    pub fn compatible(&self) -> impl Iterator<Item = &'a str> {
+        self.property("compatible").into_iter().flat_map(|p| p.as_strings())
+    }
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.compatible().any(|c| c == compatible)
+    }
+    pub fn phandle(&self) -> Option<u32> {
+        self.property("phandle")
+            .or_else(|| self.property("linux,phandle"))
+            .and_then(|p| p.as_u32())
+    }
+    /// Whether the `status` property is absent or `okay`.
+    pub fn is_enabled(&self) -> bool {
+        match self.property("status").and_then(|p| p.as_str()) {
+            None | Some("okay" | "ok") => true,
+            Some(_) => false,
+        }
+    }
+    /// The regions in the `reg` property.
+    pub fn reg(&self) -> impl Iterator<Item = Region> + 'a {
+        let Cells { address, size } = self.parent_cells;
+        let (address, size) = (address as usize, size as usize);
+        let value = self.property("reg").map_or(&[][..], |p| p.value);
+        let stride = (address + size) * 4;
+        value.chunks_exact(stride.max(4))
+            .filter(move |_| stride != 0)
+            .map(move |entry| Region {
+                address: cells(&entry[..address * 4]),
+                size: (size != 0).then(|| cells(&entry[address * 4..])),
+            })
+    }
+    /// The `interrupts` property as single-cell interrupt specifiers.
+    pub fn interrupts(&self) -> impl Iterator<Item = u32> + 'a {
+        self.property("interrupts").into_iter().flat_map(|p| p.as_u32s())
+    }
+}
+impl<'a> fmt::Debug for Node<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Node")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Property<'a> {
+    pub name: &'a str,
+    pub value: &'a [u8],
+}
+impl<'a> Property<'a> {
+    pub fn as_u32(&self) -> Option<u32> {
+        match self.value.len() {
+            4 => be32(self.value, 0),
+            _ => None,
+        }
+    }
+    /// A one or two cell value.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.value.len() {
+            4 => be32(self.value, 0).map(u64::from),
+            8 => be64(self.value, 0),
+            _ => None,
+        }
+    }
+    pub fn as_u32s(&self) -> impl Iterator<Item = u32> + 'a {
+        self.value.chunks_exact(4).map(|cell| u32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]]))
+    }
+    /// A single null-terminated string.
+    pub fn as_str(&self) -> Option<&'a str> {
+        let string = cstr(self.value)?;
+        (string.len() + 1 == self.value.len()).then_some(string)
+    }
+    /// A list of null-terminated strings.
+    pub fn as_strings(&self) -> impl Iterator<Item = &'a str> + 'a {
+        let value = self.value.strip_suffix(&[0]).unwrap_or(&[]);
+        value.split(|&b| b == 0).filter_map(|s| core::str::from_utf8(s).ok())
+    }
+}
+impl<'a> fmt::Debug for Property<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} = ", self.name)?;
+        if self.value.is_empty() {
+            return write!(f, "true");
+        }
+        if self.value == [0] {
+            return write!(f, "\"\"");
+        }
+        let printable = self.value.last() == Some(&0)
+            && self.value[..self.value.len() - 1].iter().all(|&b| b == 0 || b.is_ascii_graphic() || b == b' ')
+            && self.value[0] != 0;
+        if printable {
+            let mut strings = self.as_strings();
+            if let Some(first) = strings.next() {
+                write!(f, "{first:?}")?;
+            }
+            for string in strings {
+                write!(f, ", {string:?}")?;
+            }
+            Ok(())
+        } else if self.value.len().is_multiple_of(4) {
+            write!(f, "<")?;
+            for (i, cell) in self.as_u32s().enumerate() {
+                if i != 0 {
+                    write!(f, " ")?;
+                }
+                write!(f, "{cell:#x}")?;
+            }
+            write!(f, ">")
+        } else {
+            write!(f, "[")?;
+            for (i, byte) in self.value.iter().enumerate() {
+                if i != 0 {
+                    write!(f, " ")?;
+                }
+                write!(f, "{byte:02x}")?;
+            }
+            write!(f, "]")
+        }
+    }
+}
+
+/// The properties of a node.
+pub struct Properties<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+}
+impl<'a> Iterator for Properties<'a> {
+    type Item = Property<'a>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.fdt.token(self.offset)? {
+                (Token::Property(property), next) => {
+                    self.offset = next;
+                    return Some(property);
+                },
+                (Token::Nop, next) => self.offset = next,
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// The direct children of a node.
+pub struct Children<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+    cells: Cells,
+}
+impl<'a> Iterator for Children<'a> {
+    type Item = Node<'a>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.fdt.token(self.offset)? {
+                (Token::BeginNode(name), _) => {
+                    let node = Node {
+                        fdt: self.fdt,
+                        offset: self.offset,
+                        name,
+                        parent_cells: self.cells,
+                    };
+                    self.offset = skip_node(&self.fdt, self.offset)?;
+                    return Some(node);
+                },
+                (Token::Nop, next) => self.offset = next,
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// Every node in the tree, depth first.
+pub struct Nodes<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+    depth: usize,
+    /// The cells of the nodes on the path to the current node.
+    cells: [Cells; MAX_DEPTH + 1],
+}
+impl<'a> Iterator for Nodes<'a> {
+    type Item = Node<'a>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.fdt.token(self.offset)? {
+                (Token::BeginNode(name), next) => {
+                    let parent_cells = match self.depth {
+                        0 => Cells::DEFAULT,
+                        depth => self.cells[depth - 1],
+                    };
+                    let node = Node {
+                        fdt: self.fdt,
+                        offset: self.offset,
+                        name,
+                        parent_cells,
+                    };
+                    if self.depth > MAX_DEPTH {
+                        // too deep to track, so skip the subtree
+                        self.offset = skip_node(&self.fdt, self.offset)?;
+                        continue;
+                    }
+                    self.cells[self.depth] = node.cells();
+                    self.depth += 1;
+                    self.offset = next;
+                    return Some(node);
+                },
+                (Token::EndNode, next) => {
+                    self.depth = self.depth.checked_sub(1)?;
+                    self.offset = next;
+                },
+                (Token::Property(_) | Token::Nop, next) => self.offset = next,
+                (Token::End, _) => return None,
+            }
+        }
+    }
+}
+
+/// Find the offset after the end of the node beginning at `offset`.
+fn skip_node(fdt: &Fdt, mut offset: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    loop {
+        let (token, next) = fdt.token(offset)?;
+        offset = next;
+        match token {
+            Token::BeginNode(_) => depth += 1,
+            Token::EndNode => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(offset);
+                }
+            },
+            Token::End => return None,
+            Token::Property(_) | Token::Nop => (),
+        }
+    }
+}
+
+#[inline]
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+fn be32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+fn be64(data: &[u8], offset: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset + 8)?;
+    Some(u64::from_be_bytes(bytes.try_into().ok()?))
+}
+/// Read big-endian cells into an integer, keeping the least significant 64
+/// bits.
+fn cells(data: &[u8]) -> u64 {
+    data.chunks_exact(4).fold(0, |value, cell| {
+        value.wrapping_shl(32) | u32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]]) as u64
+    })
+}
+/// A null-terminated UTF-8 string at the start of `data`.
+fn cstr(data: &[u8]) -> Option<&str> {
+    let len = data.iter().position(|&b| b == 0)?;
+    core::str::from_utf8(&data[..len]).ok()
+}
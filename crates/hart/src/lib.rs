@@ -0,0 +1,155 @@
+#![no_std]
+//! Harts: their stacks, per-hart data and which of them are running.
+//!
+//! The thread pointer of every hart holds its hart id, which indexes its
+//! stack and its entry in each [`PerHart`].
+
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// The most harts the kernel runs on. Harts with a higher id are never
+/// started.
+///
+/// Kept in sync with the `.stack` section of the linker scripts.
+pub const MAX_HARTS: usize = 8;
+/// The unmapped page below each stack that catches overflows.
+pub const GUARD_SIZE: usize = 0x1000;
+pub const STACK_SIZE: usize = 0x10000;
+/// The distance between the stacks of consecutive harts, kept in sync with
+/// `_hart_stack_end`.
+pub const STACK_STRIDE: usize = GUARD_SIZE + STACK_SIZE;
+/// The size of each machine-mode trap stack.
+pub const MACHINE_STACK_SIZE: usize = 0x1000;
+/// The size of each trap stack, on which `_trap` reports an overflow of the
+/// stack it interrupted, kept in sync with the linker scripts.
+pub const TRAP_STACK_SIZE: usize = 0x4000;
+
+/// A value for each hart.
+pub struct PerHart<T>([T; MAX_HARTS]);
+impl<T> PerHart<T> {
+    pub const fn new(values: [T; MAX_HARTS]) -> Self {
+        Self(values)
+    }
+    /// The value of the current hart.
+    pub fn get(&self) -> &T {
+        &self.0[id()]
+    }
+    /// The value of `hart_id`.
+    ///
+    /// # Panics
+    /// Panics if `hart_id` is not less than [`MAX_HARTS`].
+    pub fn of(&self, hart_id: usize) -> &T {
+        &self.0[hart_id]
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.0.iter().enumerate()
+    }
+}
+
+static BOOT: AtomicUsize = AtomicUsize::new(0);
+static ONLINE: PerHart<AtomicBool> = PerHart::new([const { AtomicBool::new(false) }; MAX_HARTS]);
+
+/// Record `hart_id` as the boot hart, and online.
+pub fn init(hart_id: usize) {
+    BOOT.store(hart_id, Ordering::Relaxed);
+    set_online(hart_id);
+}
+/// The hart that booted the kernel.
+pub fn boot_id() -> usize {
+    BOOT.load(Ordering::Relaxed)
+}
+/// Record that `hart_id` is running the kernel.
+pub fn set_online(hart_id: usize) {
+    ONLINE.of(hart_id).store(true, Ordering::Release);
+}
+/// Record that `hart_id` has stopped running the kernel.
+pub fn set_offline(hart_id: usize) {
+    ONLINE.of(hart_id).store(false, Ordering::Release);
+}
+pub fn is_online(hart_id: usize) -> bool {
+    hart_id < MAX_HARTS && ONLINE.of(hart_id).load(Ordering::Acquire)
+}
+/// The harts running the kernel.
+pub fn online() -> impl Iterator<Item = usize> {
+    ONLINE.iter().filter(|(_, online)| online.load(Ordering::Acquire)).map(|(hart, _)| hart)
+}
+pub fn count() -> usize {
+    online().count()
+}
+
+/// The stack of `hart_id`.
+pub fn stack(hart_id: usize) -> Range<usize> {
+    let start = stacks() + hart_id * STACK_STRIDE + GUARD_SIZE;
+    start..start + STACK_SIZE
+}
+/// The guard page below the stack of `hart_id`.
+pub fn guard(hart_id: usize) -> Range<usize> {
+    let start = stacks() + hart_id * STACK_STRIDE;
+    start..start + GUARD_SIZE
+}
+/// The trap stack of `hart_id`.
+pub fn trap_stack(hart_id: usize) -> Range<usize> {
+    extern "C" {
+        static _trap_stacks: u8;
+    }
+    let start = core::ptr::addr_of!(_trap_stacks) as usize + hart_id * TRAP_STACK_SIZE;
+    start..start + TRAP_STACK_SIZE
+}
+/// The machine-mode trap stacks of every hart, in one naturally aligned
+/// region.
+pub fn machine_stacks() -> Range<usize> {
+    extern "C" {
+        static _machine_stacks: u8;
+    }
+    let start = core::ptr::addr_of!(_machine_stacks) as usize;
+    start..start + MAX_HARTS * MACHINE_STACK_SIZE
+}
+/// The start of the stacks of every hart, each above its guard page.
+pub fn stacks() -> usize {
+    extern "C" {
+        static _hart_stacks: u8;
+    }
+    core::ptr::addr_of!(_hart_stacks) as usize
+}
+
+/// What `_trap` reaches through `sscratch` to check the interrupted stack
+/// before saving anything on it.
+///
+/// Kept in sync with `_trap`.
+#[repr(C)]
+pub struct TrapScratch {
+    /// The lowest address of the stack the hart runs on.
+    limit: AtomicUsize,
+    /// The top of the hart's trap stack.
+    stack: AtomicUsize,
+    /// Holds a register while `_trap` checks the stack.
+    spill: AtomicUsize,
+}
+
+static TRAP_SCRATCH: PerHart<TrapScratch> = PerHart::new([const {
+    TrapScratch { limit: AtomicUsize::new(0), stack: AtomicUsize::new(0), spill: AtomicUsize::new(0) }
+}; MAX_HARTS]);
+
+/// The [`TrapScratch`] of the current hart, running on its boot stack.
+pub fn trap_scratch() -> &'static TrapScratch {
+    let scratch = TRAP_SCRATCH.get();
+    scratch.limit.store(stack(id()).start, Ordering::Relaxed);
+    scratch.stack.store(trap_stack(id()).end, Ordering::Relaxed);
+    scratch
+}
+/// Record that the current hart now runs on a stack starting at `limit`,
+/// below which `_trap` takes the stack pointer to have overflowed.
+pub fn set_stack_limit(limit: usize) {
+    TRAP_SCRATCH.get().limit.store(limit, Ordering::Relaxed);
+}
+
+/// The current hart, which the kernel keeps in the thread pointer.
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+#[inline]
+pub fn id() -> usize {
+    let hart_id;
+    unsafe { core::arch::asm!("mv {}, tp", out(reg) hart_id) };
+    hart_id
+}
+#[cfg(not(any(target_arch = "riscv64", target_arch = "riscv32")))]
+pub fn id() -> usize { 0 }
@@ -0,0 +1,4 @@
+fn main() {
+    configure::Config::load()
+        .cfg();
+}
@@ -0,0 +1,72 @@
+#![no_std]
+//! The kernel heap.
+//!
+//! Small allocations are served from slabs of fixed size objects, and
+//! everything else from a linked list of free blocks. Both grow by taking
+//! frames from the physical frame allocator, and never give them back.
+
+extern crate alloc;
+
+mod list;
+mod slab;
+
+use core::{alloc::{GlobalAlloc, Layout}, ptr};
+use sync::IrqSafeSpinLock;
+
+#[global_allocator]
+static HEAP: Heap = Heap::new();
+
+/// Heap usage in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Memory taken from the frame allocator.
+    pub size: usize,
+    /// Memory handed out, including rounding to the slab or block size.
+    pub used: usize,
+}
+
+/// Current heap usage.
+pub fn stats() -> Stats {
+    let inner = HEAP.inner.lock();
+    Stats {
+        size: inner.slabs.size() + inner.list.size(),
+        used: inner.slabs.used() + inner.list.used(),
+    }
+}
+
+struct Heap {
+    inner: IrqSafeSpinLock<HeapInner>,
+}
+struct HeapInner {
+    slabs: slab::Slabs,
+    list: list::List,
+}
+// Safety: the heap only hands out memory it owns, under its lock
+unsafe impl Send for HeapInner {}
+impl Heap {
+    const fn new() -> Self {
+        Self {
+            inner: IrqSafeSpinLock::new(HeapInner {
+                slabs: slab::Slabs::new(),
+                list: list::List::new(),
+            }),
+        }
+    }
+}
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut inner = self.inner.lock();
+        let allocation = match slab::class(layout) {
+            Some(class) => inner.slabs.allocate(class),
+            None => inner.list.allocate(layout),
+        };
+        allocation.map_or(ptr::null_mut(), |allocation| allocation.as_ptr())
+    }
+    unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+        let mut inner = self.inner.lock();
+        match slab::class(layout) {
+            Some(class) => inner.slabs.free(pointer, class),
+            None => inner.list.free(pointer, layout),
+        }
+    }
+}
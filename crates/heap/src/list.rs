@@ -0,0 +1,135 @@
+//! A first fit allocator over an address ordered list of free blocks, for
+//! allocations too large for a slab.
+//!
+//! Block sizes and addresses are multiples of [`BLOCK_ALIGN`], so whatever is
+//! left over around an allocation is always large enough to be a free block.
+
+use core::{alloc::Layout, mem::size_of, ptr::NonNull};
+use memory::{frame, FRAME_SIZE};
+
+const BLOCK_ALIGN: usize = 16;
+/// The fewest frames to take from the frame allocator when growing.
+const GROW_FRAMES: usize = 16;
+
+/// A free block, linked to the next free block by address.
+struct Block {
+    size: usize,
+    next: Option<NonNull<Block>>,
+}
+const _: () = assert!(size_of::<Block>() <= BLOCK_ALIGN);
+
+pub struct List {
+    head: Option<NonNull<Block>>,
+    size: usize,
+    used: usize,
+}
+impl List {
+    pub const fn new() -> Self {
+        Self {
+            head: None,
+            size: 0,
+            used: 0,
+        }
+    }
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    pub fn used(&self) -> usize {
+        self.used
+    }
+    pub fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let (size, align) = block_layout(layout);
+        if let Some(allocation) = self.find(size, align) {
+            return Some(allocation);
+        }
+        let frames = (size + align).div_ceil(FRAME_SIZE).max(GROW_FRAMES);
+        let start = frame::allocate_contiguous(frames)?;
+        self.size += frames * FRAME_SIZE;
+        // Safety: the frames were just allocated and are only used by the heap
+        unsafe { self.insert(start, frames * FRAME_SIZE) };
+        self.find(size, align)
+    }
+    /// # Safety
+    /// `pointer` must have been allocated with `layout` and not yet freed.
+    pub unsafe fn free(&mut self, pointer: *mut u8, layout: Layout) {
+        let (size, _) = block_layout(layout);
+        self.used -= size;
+        self.insert(pointer as usize, size);
+    }
+    /// Take `size` bytes aligned to `align` from the first block that fits.
+    fn find(&mut self, size: usize, align: usize) -> Option<NonNull<u8>> {
+        let mut link = &mut self.head;
+        while let Some(mut block) = *link {
+            // Safety: every block in the list is free memory owned by the heap
+            let (block_size, next) = unsafe { (block.as_ref().size, block.as_ref().next) };
+            let block_start = block.as_ptr() as usize;
+            let start = align_up(block_start, align);
+            let end = start.checked_add(size)?;
+            if end > block_start + block_size {
+                // Safety: as above
+                link = unsafe { &mut block.as_mut().next };
+                continue;
+            }
+            *link = next;
+            // Safety: the remainders are free, as they came from this block
+            unsafe {
+                self.insert(end, block_start + block_size - end);
+                self.insert(block_start, start - block_start);
+            }
+            self.used += size;
+            return NonNull::new(start as *mut u8);
+        }
+        None
+    }
+    /// Add a free block at `start`, merging it with its neighbours.
+    ///
+    /// # Safety
+    /// The block must be unused memory owned by the heap.
+    unsafe fn insert(&mut self, start: usize, size: usize) {
+        if size == 0 {
+            return;
+        }
+        let end = start + size;
+        // find the last block before the new one
+        let mut previous: Option<NonNull<Block>> = None;
+        let mut next = self.head;
+        while let Some(block) = next {
+            if block.as_ptr() as usize > start {
+                break;
+            }
+            previous = Some(block);
+            next = block.as_ref().next;
+        }
+        let mut block = NonNull::new_unchecked(start as *mut Block);
+        block.as_ptr().write(Block { size, next });
+        // merge with the next block
+        if let Some(next) = next.filter(|next| next.as_ptr() as usize == end) {
+            let next = next.as_ref();
+            block.as_mut().size += next.size;
+            block.as_mut().next = next.next;
+        }
+        match previous {
+            Some(mut previous) => {
+                let previous = previous.as_mut();
+                if previous as *mut Block as usize + previous.size == start {
+                    previous.size += block.as_ref().size;
+                    previous.next = block.as_ref().next;
+                } else {
+                    previous.next = Some(block);
+                }
+            },
+            None => self.head = Some(block),
+        }
+    }
+}
+
+/// The size and alignment of the block that holds `layout`.
+fn block_layout(layout: Layout) -> (usize, usize) {
+    let size = align_up(layout.size().max(1), BLOCK_ALIGN);
+    (size, layout.align().max(BLOCK_ALIGN))
+}
+
+#[inline]
+const fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
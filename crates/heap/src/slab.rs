@@ -0,0 +1,84 @@
+//! Slabs of fixed size objects for small allocations.
+//!
+//! Each size class is a power of two, and its slabs are whole frames, so every
+//! object is aligned to its size.
+
+use core::{alloc::Layout, ptr::NonNull};
+use memory::{frame, FrameSize, FRAME_SIZE};
+
+/// The smallest object, which must fit a free list link.
+const MIN_SIZE: usize = 16;
+/// The largest object served from a slab.
+const MAX_SIZE: usize = 2048;
+const CLASSES: usize = (MAX_SIZE / MIN_SIZE).trailing_zeros() as usize + 1;
+
+/// The size class that serves `layout`, or `None` if it is too large.
+pub fn class(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align()).max(MIN_SIZE).next_power_of_two();
+    if size > MAX_SIZE {
+        return None;
+    }
+    Some((size / MIN_SIZE).trailing_zeros() as usize)
+}
+const fn class_size(class: usize) -> usize {
+    MIN_SIZE << class
+}
+
+/// A free object, linked to the next free object of its class.
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+pub struct Slabs {
+    free: [Option<NonNull<FreeObject>>; CLASSES],
+    /// The number of frames taken for slabs.
+    frames: usize,
+    used: usize,
+}
+impl Slabs {
+    pub const fn new() -> Self {
+        Self {
+            free: [None; CLASSES],
+            frames: 0,
+            used: 0,
+        }
+    }
+    pub fn size(&self) -> usize {
+        self.frames * FRAME_SIZE
+    }
+    pub fn used(&self) -> usize {
+        self.used
+    }
+    pub fn allocate(&mut self, class: usize) -> Option<NonNull<u8>> {
+        if self.free[class].is_none() {
+            self.grow(class)?;
+        }
+        let object = self.free[class]?;
+        // Safety: free objects are owned by the slab and hold a valid link
+        self.free[class] = unsafe { object.as_ref().next };
+        self.used += class_size(class);
+        Some(object.cast())
+    }
+    /// # Safety
+    /// `pointer` must have been allocated from `class` and not yet freed.
+    pub unsafe fn free(&mut self, pointer: *mut u8, class: usize) {
+        let object = pointer.cast::<FreeObject>();
+        object.write(FreeObject { next: self.free[class] });
+        self.free[class] = NonNull::new(object);
+        self.used -= class_size(class);
+    }
+    /// Carve a new frame into free objects of `class`.
+    fn grow(&mut self, class: usize) -> Option<()> {
+        let frame = frame::allocate(FrameSize::Size4K)?;
+        self.frames += 1;
+        let size = class_size(class);
+        for object in (frame..frame + FRAME_SIZE).step_by(size).rev() {
+            let object = object as *mut FreeObject;
+            // Safety: the frame was just allocated and is only used by this
+            // slab
+            unsafe { object.write(FreeObject { next: self.free[class] }) };
+            self.free[class] = NonNull::new(object);
+        }
+        Some(())
+    }
+}
@@ -2,11 +2,20 @@ fn main() {
     let config = configure::Config::load();
     config.cfg();
 
-    use configure::profile::Machine;
-    match config.profile().machine {
-        Machine::QemuVirt | Machine::SifiveU540 => {
+    use configure::profile::{Boot, Machine};
+    match (config.profile().machine, config.profile().boot) {
+        (Machine::QemuVirt | Machine::SifiveU540, Boot::Firmware) => {
             config.library("rt", &[
                 "src/riscv/init.s",
+                "src/riscv/hart.s",
+                "src/riscv/machine.s",
+                "src/riscv/trap.s",
+            ]);
+        },
+        (Machine::QemuVirt | Machine::SifiveU540, Boot::Sbi) => {
+            config.library("rt", &[
+                "src/riscv/sbi.s",
+                "src/riscv/hart.s",
                 "src/riscv/trap.s",
             ]);
         },
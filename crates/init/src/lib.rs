@@ -3,17 +3,38 @@
 //!
 //! This crate handles the early-boot process before entering the kernel.
 
+extern crate heap;
 extern crate panic;
 
+#[cfg(target_boot = "firmware")]
+mod machine;
+mod smp;
 mod trap;
 
 extern "Rust" {
     /// The main entry point to the kernel.
-    fn bluemetal(hart_id: usize) -> !;
+    fn bluemetal(hart_id: usize, fdt: Option<fdt::Fdt<'static>>) -> !;
 }
 
+/// The supervisor-mode entry point, from `_init` in machine mode.
 #[no_mangle]
-extern "C" fn init(hart_id: usize) -> ! {
-    ::serial::init();
-    unsafe { bluemetal(hart_id) }
+extern "C" fn init(hart_id: usize, dtb: *const u8) -> ! {
+    // Safety: the device tree passed in by firmware is never modified
+    let fdt = unsafe { fdt::Fdt::from_ptr(dtb) }.ok();
+    ::hart::init(hart_id);
+    ::serial::init(fdt.as_ref());
+    ::logger::init();
+    ::memory::init(fdt.as_ref());
+    trap::init();
+    ::time::init(hart_id, fdt.as_ref());
+    ::interrupt::init(fdt.as_ref());
+    ::interrupt::init_hart(hart_id);
+    ::ipi::init();
+    ::ipi::init_hart();
+    ::thread::init_hart();
+    if let Err(e) = ::serial::enable_interrupts() {
+        ::log::warn!("console stays polled: {e}");
+    }
+    smp::start(hart_id, fdt.as_ref());
+    unsafe { bluemetal(hart_id, fdt) }
 }
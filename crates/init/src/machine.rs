@@ -0,0 +1,293 @@
+//! The machine-mode layer beneath the kernel.
+//!
+//! Delegates traps and interrupts to supervisor mode, opens physical memory to
+//! it with PMP, and handles the traps that still reach machine mode, including
+//! a minimal SBI for the kernel. The SBI timer drives the CLINT, forwarding its
+//! machine timer interrupt to the kernel as a supervisor timer interrupt, and
+//! SBI IPIs raise the CLINT machine software interrupt of each target hart,
+//! forwarded the same way.
+//!
+//! Every hart but the first to boot parks in machine mode until the kernel
+//! starts it with the SBI hart state management extension.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use hart::PerHart;
+use riscv::trap::{Cause, Exception, Interrupt, TrapFrame};
+use sbi::{base, hsm, ipi, srst, time, Error};
+
+const MSIE: usize = 1 << 3;
+const MTIE: usize = 1 << 7;
+const SSIP: usize = 1 << 1;
+const STIP: usize = 1 << 5;
+
+/// Whether the hart has Sstc, so the kernel and the SBI timer use `stimecmp`.
+static SSTC: AtomicBool = AtomicBool::new(false);
+
+/// The [`hsm::HartState`] of each hart plus 1, or 0 for harts that never
+/// reached machine mode.
+///
+/// Parked harts use these before the boot hart zeroes bss, so they are kept
+/// in `.data`.
+#[link_section = ".data.hsm"]
+static HART_STATES: PerHart<AtomicUsize> = PerHart::new([const { AtomicUsize::new(0) }; hart::MAX_HARTS]);
+/// The supervisor-mode entry point of each starting hart.
+#[link_section = ".data.hsm"]
+static START: PerHart<AtomicUsize> = PerHart::new([const { AtomicUsize::new(0) }; hart::MAX_HARTS]);
+/// The value for `a1` at the entry point of each starting hart.
+#[link_section = ".data.hsm"]
+static OPAQUE: PerHart<AtomicUsize> = PerHart::new([const { AtomicUsize::new(0) }; hart::MAX_HARTS]);
+
+const STARTED: usize = 1;
+const STOPPED: usize = 2;
+const START_PENDING: usize = 3;
+
+/// Configure machine mode before dropping to the kernel in supervisor mode.
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+#[no_mangle]
+extern "C" fn machine_init(hart_id: usize, dtb: *const u8) {
+    extern "C" {
+        fn _trap_early_panic();
+    }
+    // every exception but environment calls to machine mode, which are
+    // handled here
+    const MEDELEG: usize = 1 << 0 // instruction address misaligned
+        | 1 << 1 // instruction access fault
+        | 1 << 2 // illegal instruction
+        | 1 << 3 // breakpoint
+        | 1 << 4 // load address misaligned
+        | 1 << 5 // load access fault
+        | 1 << 6 // store address misaligned
+        | 1 << 7 // store access fault
+        | 1 << 8 // environment call from user mode
+        | 1 << 12 // instruction page fault
+        | 1 << 13 // load page fault
+        | 1 << 15; // store page fault
+    // supervisor software, timer and external interrupts
+    const MIDELEG: usize = 1 << 1 | 1 << 5 | 1 << 9;
+    // cycle, time and instret
+    const MCOUNTEREN: usize = 0b111;
+
+    // PMP entry 0 hides the machine-mode stacks from lower privilege modes,
+    // and entry 1 opens the rest of the address space to them
+    const PMP_NAPOT: usize = 0b11 << 3;
+    const PMP_RWX: usize = 0b111;
+    let machine_stacks = hart::machine_stacks();
+    let pmpaddr0 = (machine_stacks.start | (machine_stacks.len() / 2 - 1)) >> 2;
+    let pmpaddr1 = usize::MAX;
+    let pmpcfg0 = PMP_NAPOT | (PMP_NAPOT | PMP_RWX) << 8;
+
+    // Safety: the device tree passed in by firmware is never modified
+    let fdt = unsafe { fdt::Fdt::from_ptr(dtb) }.ok();
+    ::time::clint::init(fdt.as_ref());
+    if fdt.as_ref().is_some_and(|fdt| ::time::has_sstc(fdt, hart_id)) {
+        // menvcfg.STCE
+        const STCE: usize = 1 << 63;
+        unsafe { core::arch::asm!("csrs menvcfg, {}", in(reg) STCE) };
+        SSTC.store(true, Ordering::Relaxed);
+    }
+
+    unsafe {
+        core::arch::asm!(
+            "csrw medeleg, {medeleg}",
+            "csrw mideleg, {mideleg}",
+            "csrw mcounteren, {mcounteren}",
+            "csrw pmpaddr0, {pmpaddr0}",
+            "csrw pmpaddr1, {pmpaddr1}",
+            "csrw pmpcfg0, {pmpcfg0}",
+            "csrw satp, zero",
+            "csrw stvec, {stvec}",
+            medeleg = in(reg) MEDELEG,
+            mideleg = in(reg) MIDELEG,
+            mcounteren = in(reg) MCOUNTEREN,
+            pmpaddr0 = in(reg) pmpaddr0,
+            pmpaddr1 = in(reg) pmpaddr1,
+            pmpcfg0 = in(reg) pmpcfg0,
+            stvec = in(reg) _trap_early_panic,
+        );
+    }
+    // IPIs
+    unsafe { core::arch::asm!("csrs mie, {}", in(reg) MSIE) };
+    HART_STATES.of(hart_id).store(STARTED, Ordering::Release);
+}
+
+/// Wait for the kernel to start a hart other than the boot hart, then enter
+/// it in supervisor mode.
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+#[no_mangle]
+extern "C" fn machine_park(hart_id: usize, dtb: *const u8) -> ! {
+    // a software interrupt wakes the hart, without trapping as machine
+    // interrupts stay disabled
+    unsafe { core::arch::asm!("csrs mie, {}", in(reg) MSIE) };
+    HART_STATES.of(hart_id).store(STOPPED, Ordering::Release);
+    while HART_STATES.of(hart_id).load(Ordering::Acquire) != START_PENDING {
+        unsafe { core::arch::asm!("wfi") };
+    }
+    unsafe { ::time::clint::set_msip(hart_id, false) };
+    machine_init(hart_id, dtb);
+
+    let start = START.of(hart_id).load(Ordering::Relaxed);
+    let opaque = OPAQUE.of(hart_id).load(Ordering::Relaxed);
+    // Safety: the kernel passes an entry point for supervisor mode
+    unsafe {
+        core::arch::asm!(
+            "csrw mepc, {start}",
+            "mret",
+            start = in(reg) start,
+            in("a0") hart_id,
+            in("a1") opaque,
+            options(noreturn),
+        );
+    }
+}
+
+/// Handle a trap taken to machine mode.
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+#[no_mangle]
+extern "C" fn machine_trap(frame: &mut TrapFrame) {
+    match frame.cause() {
+        Cause::Exception(Exception::SupervisorEcall) => {
+            let x = &mut frame.x;
+            let args = [x[10], x[11], x[12], x[13], x[14], x[15]];
+            let (error, value) = match sbi_call(x[TrapFrame::A7], x[TrapFrame::A6], args) {
+                Ok(value) => (0, value),
+                Err(e) => (e.code(), 0),
+            };
+            x[TrapFrame::A0] = error as usize;
+            x[TrapFrame::A1] = value;
+            frame.pc += 4;
+        },
+        // hand the interrupt to the kernel until it sets the next deadline
+        Cause::Interrupt(Interrupt::MachineTimer) => unsafe {
+            core::arch::asm!(
+                "csrc mie, {mtie}",
+                "csrs mip, {stip}",
+                mtie = in(reg) MTIE,
+                stip = in(reg) STIP,
+            );
+        },
+        // hand an IPI to the kernel
+        Cause::Interrupt(Interrupt::MachineSoftware) => unsafe {
+            let hart_id;
+            core::arch::asm!("csrr {}, mhartid", out(reg) hart_id);
+            ::time::clint::set_msip(hart_id, false);
+            core::arch::asm!("csrs mip, {}", in(reg) SSIP);
+        },
+        _ => crate::trap::trap_early_panic(frame),
+    }
+}
+
+/// Handle function `fid` of SBI extension `eid`.
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+fn sbi_call(eid: usize, fid: usize, args: [usize; 6]) -> Result<usize, Error> {
+    /// SBI v2.0
+    const SPEC_VERSION: base::Version = base::Version { major: 2, minor: 0 };
+    /// Not a registered implementation.
+    const IMPL_ID: usize = 0x626c_7565;
+    /// The SiFive test device, which QEMU provides on both supported machines.
+    const TEST: *mut u32 = 0x10_0000 as *mut u32;
+
+    macro_rules! csr {
+        ($csr:literal) => {{
+            let value;
+            unsafe { core::arch::asm!(concat!("csrr {}, ", $csr), out(reg) value) };
+            Ok(value)
+        }};
+    }
+    match (eid, fid) {
+        (base::EID, 0) => Ok(SPEC_VERSION.raw()),
+        (base::EID, 1) => Ok(IMPL_ID),
+        (base::EID, 2) => Ok(1),
+        (base::EID, 3) => {
+            Ok(matches!(args[0], base::EID | time::EID | ipi::EID | hsm::EID | srst::EID) as usize)
+        },
+        (base::EID, 4) => csr!("mvendorid"),
+        (base::EID, 5) => csr!("marchid"),
+        (base::EID, 6) => csr!("mimpid"),
+        (time::EID, 0) => {
+            set_timer(args[0] as u64);
+            Ok(0)
+        },
+        (ipi::EID, 0) => send_ipi(args[0], args[1]),
+        (hsm::EID, 0) => hart_start(args[0], args[1], args[2]),
+        (hsm::EID, 2) => hart_status(args[0]),
+        (srst::EID, 0) => {
+            let value = match args[0] {
+                0 => 0x5555,
+                1 | 2 => 0x7777,
+                _ => return Err(Error::InvalidParam),
+            };
+            unsafe { TEST.write_volatile(value) };
+            Err(Error::Failed)
+        },
+        _ => Err(Error::NotSupported),
+    }
+}
+
+/// Release parked `hart_id` to enter supervisor mode at `start`.
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+fn hart_start(hart_id: usize, start: usize, opaque: usize) -> Result<usize, Error> {
+    if hart_id >= hart::MAX_HARTS {
+        return Err(Error::InvalidParam);
+    }
+    START.of(hart_id).store(start, Ordering::Relaxed);
+    OPAQUE.of(hart_id).store(opaque, Ordering::Relaxed);
+    match HART_STATES.of(hart_id).compare_exchange(STOPPED, START_PENDING, Ordering::AcqRel, Ordering::Acquire) {
+        Ok(_) => {
+            unsafe { ::time::clint::set_msip(hart_id, true) };
+            Ok(0)
+        },
+        Err(0) => Err(Error::InvalidParam),
+        Err(_) => Err(Error::AlreadyAvailable),
+    }
+}
+
+/// Raise a supervisor software interrupt on each started hart in the mask of
+/// harts from `base`, or on every started hart if `base` is `usize::MAX`.
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+fn send_ipi(mask: usize, base: usize) -> Result<usize, Error> {
+    let selected = |hart: usize| {
+        base == usize::MAX || hart.checked_sub(base)
+            .is_some_and(|bit| bit < usize::BITS as usize && mask >> bit & 1 != 0)
+    };
+    let targets = HART_STATES.iter().filter(|&(hart, _)| selected(hart));
+    for (hart, state) in targets {
+        if state.load(Ordering::Acquire) == STARTED {
+            unsafe { ::time::clint::set_msip(hart, true) };
+        }
+    }
+    Ok(0)
+}
+
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+fn hart_status(hart_id: usize) -> Result<usize, Error> {
+    if hart_id >= hart::MAX_HARTS {
+        return Err(Error::InvalidParam);
+    }
+    match HART_STATES.of(hart_id).load(Ordering::Acquire) {
+        0 => Err(Error::InvalidParam),
+        state => Ok(state - 1),
+    }
+}
+
+/// Raise a supervisor timer interrupt once `time` reaches `deadline`, clearing
+/// any pending one.
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+fn set_timer(deadline: u64) {
+    if SSTC.load(Ordering::Relaxed) {
+        // stimecmp
+        unsafe { core::arch::asm!("csrw 0x14d, {}", in(reg) deadline) };
+        return;
+    }
+    let hart_id;
+    unsafe { core::arch::asm!("csrr {}, mhartid", out(reg) hart_id) };
+    unsafe {
+        ::time::clint::set_mtimecmp(hart_id, deadline);
+        core::arch::asm!(
+            "csrc mip, {stip}",
+            "csrs mie, {mtie}",
+            stip = in(reg) STIP,
+            mtie = in(reg) MTIE,
+        );
+    }
+}
@@ -0,0 +1,54 @@
+.section .text, "ax", %progbits
+
+// Kept in sync with `hart::MAX_HARTS` and `hart::STACK_STRIDE`.
+.equ MAX_HARTS, 8
+.equ HART_STACK_STRIDE, 0x1000 + 0x10000
+
+// The top of the stack of hart `a0`, returned in `a0`, or 0 if the hart is
+// beyond `MAX_HARTS`.
+// Needs no stack and only clobbers t0.
+.global _hart_stack_end
+_hart_stack_end:
+    li t0, MAX_HARTS
+    bgeu a0, t0, 1f
+    addi a0, a0, 1
+    li t0, HART_STACK_STRIDE
+    mul a0, a0, t0
+    lla t0, _hart_stacks
+    add a0, a0, t0
+    ret
+1:
+    li a0, 0
+    ret
+
+// Supervisor-mode entry point for secondary harts, from SBI `hart_start`.
+.global _start_hart
+_start_hart:
+    // a0 holds hart_id and a1 holds the opaque value, until `hart_init()`
+
+    // the kernel finds its hart_id in the thread pointer
+    mv tp, a0
+
+    // set early trap vector
+    la t0, _trap_hart_panic
+    csrw stvec, t0
+
+    // disable interrupts
+    csrw sie, zero
+    csrci sstatus, 0b10
+
+    // set stack pointer, and the early trap vector's stack
+    call _hart_stack_end
+    beqz a0, _hang
+    mv sp, a0
+    csrw sscratch, a0
+    mv a0, tp
+
+    // set global pointer
+.option push
+.option norelax
+    la  gp, __global_pointer$
+.option pop
+
+    // hart_init(hart_id: a0, opaque: a1) -> !
+    j hart_init
@@ -4,16 +4,36 @@
 .global _init
 _init:
     // save hart_id in a0 until `init()`
+    // a1 holds the device tree address from the previous boot stage
     csrr a0, mhartid
-    // use only 1 hart
-    bnez a0, _hang
 
-    // set early trap vector
-    la t0, _trap_early_panic
+    // only harts with supervisor mode can run the kernel
+    csrr t0, misa
+    // misa.S
+    li t1, 1 << 18
+    and t0, t0, t1
+    beqz t0, _hang
+
+    // the kernel finds its hart_id in the thread pointer
+    mv tp, a0
+
+    // harts without a stack never run the kernel
+    call _hart_stack_end
+    beqz a0, _hang
+    mv a0, tp
+
+    // set machine-mode trap vector and this hart's 0x1000 byte stack for it,
+    // kept in sync with `hart::MACHINE_STACK_SIZE`
+    addi t0, a0, 1
+    slli t0, t0, 12
+    lla t1, _machine_stacks
+    add t0, t0, t1
+    csrw mscratch, t0
+    la t0, _machine_trap
     csrw mtvec, t0
 
-    // set machine-mode memory access and machine mode interrupt enable
-    li t0, (0b11 << 11) | (0b1 < 7) | (0b1 << 3)
+    // return to supervisor mode with `mret`
+    li t0, 0b01 << 11
     csrw mstatus, t0
 
     // disable interrupts
@@ -22,6 +42,13 @@ _init:
     // clear interrupts
     csrw mip, zero
 
+    // boot on 1 hart, the first to get here, and park the others until the
+    // kernel starts them
+    lla t0, _boot_lottery
+    li t1, 1
+    amoswap.w t1, t1, (t0)
+    bnez t1, _park
+
     // zero out bss
     lla t0, _bss_start
     lla t1, _bss_end
@@ -31,9 +58,41 @@ _init:
     addi t0, t0, 8
     bltu t0, t1, 1b
 2:
+    lla t0, _bss_cleared
+    li t1, 1
+    sw t1, 0(t0)
+
+    // set stack pointer, and the early trap vector's stack
+    call _hart_stack_end
+    mv sp, a0
+    csrw sscratch, a0
+    mv a0, tp
+
+    // set global pointer
+.option push
+.option norelax
+    la  gp, __global_pointer$
+.option pop
+
+    // machine_init(hart_id: a0, dtb: a1)
+    mv s0, a0
+    mv s1, a1
+    call machine_init
+    mv a0, s0
+    mv a1, s1
+
+    // drop to supervisor mode on a fresh stack
+    // init(hart_id: a0, dtb: a1) -> !
+    csrr sp, sscratch
+    la t0, init
+    csrw mepc, t0
+    mret
 
-    // set stack pointer
-    lla sp, _stack_end
+// Wait in machine mode for the kernel to start this hart with SBI
+// `hart_start`.
+_park:
+    // run on the machine-mode stack, as the kernel's is not ready
+    csrr sp, mscratch
 
     // set global pointer
 .option push
@@ -41,6 +100,11 @@ _init:
     la  gp, __global_pointer$
 .option pop
 
-    // init(hart_id: a0) -> !
-    j init
+    // machine_park(hart_id: a0, dtb: a1) -> !
+    j machine_park
 
+.section .data
+.align 2
+// Set by the first hart to boot.
+_boot_lottery:
+    .word 0
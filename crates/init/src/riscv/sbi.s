@@ -0,0 +1,89 @@
+.section .entry, "ax", %progbits
+# Supervisor-mode entry point for RISC-V, from SBI firmware
+
+.global _init
+_init:
+    // a0 holds hart_id and a1 holds the device tree address, until `init()`
+
+    // the kernel finds its hart_id in the thread pointer
+    mv tp, a0
+
+    // firmware boots on a hart of its choosing, which hands over to a hart
+    // with a stack if it has none
+    call _hart_stack_end
+    beqz a0, _hand_over
+    mv a0, tp
+
+    // use only 1 hart, the first to get here
+    lla t0, _boot_lottery
+    li t1, 1
+    amoswap.w t1, t1, (t0)
+    bnez t1, _hang
+
+    // set early trap vector
+    la t0, _trap_early_panic
+    csrw stvec, t0
+
+    // disable interrupts
+    csrw sie, zero
+    csrci sstatus, 0b10
+
+    // set stack pointer, and the early trap vector's stack
+    call _hart_stack_end
+    mv sp, a0
+    csrw sscratch, a0
+    mv a0, tp
+
+    // zero out bss
+    lla t0, _bss_start
+    lla t1, _bss_end
+    bgeu t0, t1, 2f
+1:
+    sd zero, 0(t0)
+    addi t0, t0, 8
+    bltu t0, t1, 1b
+2:
+    lla t0, _bss_cleared
+    li t1, 1
+    sw t1, 0(t0)
+
+    // set global pointer
+.option push
+.option norelax
+    la  gp, __global_pointer$
+.option pop
+
+    // init(hart_id: a0, dtb: a1) -> !
+    j init
+
+// Start the first hart with a stack at `_init` in place of this one, then
+// stop this one.
+_hand_over:
+    mv s0, a1
+    li s1, 0
+1:
+    mv a0, s1
+    call _hart_stack_end
+    beqz a0, _hang
+    // sbi_hart_start(hart_id: a0, start: a1, opaque: a2), which fails for
+    // harts that do not exist
+    mv a0, s1
+    la a1, _init
+    mv a2, s0
+    li a7, 0x48534D
+    li a6, 0
+    ecall
+    addi s1, s1, 1
+    bnez a0, 1b
+
+    // sbi_hart_stop()
+    li a7, 0x48534D
+    li a6, 1
+    ecall
+    j _hang
+
+.section .data
+.align 2
+// Set by the first hart to boot.
+_boot_lottery:
+    .word 0
@@ -1,15 +1,76 @@
 .section .text, "ax", %progbits
 
+// Save x1 and x3 to x31 in the `TrapFrame` at `sp`, leaving the stack pointer
+// to the caller.
+.macro save_registers
+    sd x1, 1 * 8(sp)
+    sd x3, 3 * 8(sp)
+    sd x4, 4 * 8(sp)
+    sd x5, 5 * 8(sp)
+    sd x6, 6 * 8(sp)
+    sd x7, 7 * 8(sp)
+    sd x8, 8 * 8(sp)
+    sd x9, 9 * 8(sp)
+    sd x10, 10 * 8(sp)
+    sd x11, 11 * 8(sp)
+    sd x12, 12 * 8(sp)
+    sd x13, 13 * 8(sp)
+    sd x14, 14 * 8(sp)
+    sd x15, 15 * 8(sp)
+    sd x16, 16 * 8(sp)
+    sd x17, 17 * 8(sp)
+    sd x18, 18 * 8(sp)
+    sd x19, 19 * 8(sp)
+    sd x20, 20 * 8(sp)
+    sd x21, 21 * 8(sp)
+    sd x22, 22 * 8(sp)
+    sd x23, 23 * 8(sp)
+    sd x24, 24 * 8(sp)
+    sd x25, 25 * 8(sp)
+    sd x26, 26 * 8(sp)
+    sd x27, 27 * 8(sp)
+    sd x28, 28 * 8(sp)
+    sd x29, 29 * 8(sp)
+    sd x30, 30 * 8(sp)
+    sd x31, 31 * 8(sp)
+.endm
+
+// Save the trap CSRs in the `TrapFrame` at `sp`.
+.macro save_csrs
+    csrr t0, sepc
+    sd t0, 32 * 8(sp)
+    csrr t0, sstatus
+    sd t0, 33 * 8(sp)
+    csrr t0, scause
+    sd t0, 34 * 8(sp)
+    csrr t0, stval
+    sd t0, 35 * 8(sp)
+.endm
+
+// Save a `TrapFrame` over the hart's initial stack, whose top is in
+// `sscratch`, as it is probably broken anyway.
+.macro save_early_frame
+    // disable interrupts
+    csrw sie, zero
+
+    csrrw sp, sscratch, sp
+    addi sp, sp, -36 * 8
+    save_registers
+    csrr t0, sscratch
+    sd t0, 2 * 8(sp)
+    save_csrs
+.endm
+
 // Initial trap vector for diagnosing early boot issues.
 .align 4
 .global _trap_early_panic
 _trap_early_panic:
-    // reset over the old initial stack as it is probably broken anyway
-
-    // disable interrupts
-    csrw mie, zero
+    save_early_frame
 
-    // zero out bss
+    // zero out bss, unless `_init` already has, as it holds the kernel log
+    lla t0, _bss_cleared
+    lw t0, 0(t0)
+    bnez t0, 2f
     lla t0, _bss_start
     lla t1, _bss_end
     bgeu t0, t1, 2f
@@ -18,19 +79,131 @@ _trap_early_panic:
     addi t0, t0, 8
     bltu t0, t1, 1b
 2:
-    // reset stack
-    lla sp, _stack_end
+    j _trap_early_report
+
+// Initial trap vector of secondary harts, which leaves bss to the kernel
+// already running on the boot hart.
+.align 4
+.global _trap_hart_panic
+_trap_hart_panic:
+    save_early_frame
+
+_trap_early_report:
+.option push
+.option norelax
+    la gp, __global_pointer$
+.option pop
 
     // prevent an infinite kernel panic loop
-    la a0, _hang
-    csrw mtvec, a0
+    la t0, _hang
+    csrw stvec, t0
 
-    csrr a0, mepc
-    csrr a1, mcause
+    // trap_early_panic(frame: a0) -> !
+    mv a0, sp
     j trap_early_panic
 
+// Offsets into a `TrapScratch`, kept in sync with the hart crate.
+.equ SCRATCH_LIMIT, 0 * 8
+.equ SCRATCH_STACK, 1 * 8
+.equ SCRATCH_SPILL, 2 * 8
+
+// Trap vector once the kernel is initialised, with the hart's `TrapScratch`
+// in `sscratch`.
+// Saves every general-purpose register in a `TrapFrame` on the stack for
+// `trap_handler()`, then restores the frame, which the handler may change.
+.align 4
+.global _trap
+_trap:
+    // check there is room for the frame before touching the stack, as an
+    // overflow would otherwise fault again on every attempt to save it
+    csrrw t0, sscratch, t0
+    sd t1, SCRATCH_SPILL(t0)
+    ld t1, SCRATCH_LIMIT(t0)
+    addi t1, t1, 36 * 8
+    bltu sp, t1, _trap_stack_overflow
+    ld t1, SCRATCH_SPILL(t0)
+    csrrw t0, sscratch, t0
+
+    addi sp, sp, -36 * 8
+
+    save_registers
+    // the interrupted stack pointer
+    addi t0, sp, 36 * 8
+    sd t0, 2 * 8(sp)
+
+    save_csrs
+
+    // trap_handler(frame: a0)
+    mv a0, sp
+    call trap_handler
+
+    ld t0, 32 * 8(sp)
+    csrw sepc, t0
+    ld t0, 33 * 8(sp)
+    csrw sstatus, t0
+
+    ld x1, 1 * 8(sp)
+    ld x3, 3 * 8(sp)
+    ld x4, 4 * 8(sp)
+    ld x5, 5 * 8(sp)
+    ld x6, 6 * 8(sp)
+    ld x7, 7 * 8(sp)
+    ld x8, 8 * 8(sp)
+    ld x9, 9 * 8(sp)
+    ld x10, 10 * 8(sp)
+    ld x11, 11 * 8(sp)
+    ld x12, 12 * 8(sp)
+    ld x13, 13 * 8(sp)
+    ld x14, 14 * 8(sp)
+    ld x15, 15 * 8(sp)
+    ld x16, 16 * 8(sp)
+    ld x17, 17 * 8(sp)
+    ld x18, 18 * 8(sp)
+    ld x19, 19 * 8(sp)
+    ld x20, 20 * 8(sp)
+    ld x21, 21 * 8(sp)
+    ld x22, 22 * 8(sp)
+    ld x23, 23 * 8(sp)
+    ld x24, 24 * 8(sp)
+    ld x25, 25 * 8(sp)
+    ld x26, 26 * 8(sp)
+    ld x27, 27 * 8(sp)
+    ld x28, 28 * 8(sp)
+    ld x29, 29 * 8(sp)
+    ld x30, 30 * 8(sp)
+    ld x31, 31 * 8(sp)
+    ld sp, 2 * 8(sp)
+    sret
+
+// Save a `TrapFrame` on the hart's trap stack, with t0 holding its
+// `TrapScratch` and sscratch holding t0, and report the overflow.
+_trap_stack_overflow:
+    // a fault while reporting must not report again
+    la t1, _hang
+    csrw stvec, t1
+
+    mv t1, sp
+    ld sp, SCRATCH_STACK(t0)
+    addi sp, sp, -36 * 8
+    sd t1, 2 * 8(sp)
+    ld t1, SCRATCH_SPILL(t0)
+    csrr t0, sscratch
+    save_registers
+    save_csrs
+
+    // trap_stack_overflow(frame: a0) -> !
+    mv a0, sp
+    j trap_stack_overflow
+
 .align 4
 .global _hang
 _hang:
     wfi
     j _hang
+
+.section .data
+.align 2
+// Set once `_init` has zeroed out bss.
+.global _bss_cleared
+_bss_cleared:
+    .word 0
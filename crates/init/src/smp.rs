@@ -0,0 +1,69 @@
+//! Starting the secondary harts.
+//!
+//! The boot hart starts every other hart in the device tree with SBI
+//! `hart_start`, entering the kernel at `_start_hart` on its own stack.
+
+use ::log::warn;
+use ::time::{Duration, Instant};
+
+/// How long a started hart has to come online.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+extern "Rust" {
+    /// The kernel entry point of every hart but the boot hart.
+    fn bluemetal_hart(hart_id: usize) -> !;
+}
+
+/// Start every hart in the device tree that can run the kernel, and wait for
+/// them to come online.
+pub fn start(boot_hart: usize, fdt: Option<&fdt::Fdt>) {
+    extern "C" {
+        fn _start_hart();
+    }
+    let Some(cpus) = fdt.and_then(|fdt| fdt.find_node("/cpus")) else {
+        return;
+    };
+    let harts = cpus.children()
+        .filter(|cpu| cpu.base_name() == "cpu" && cpu.is_enabled())
+        // harts without an MMU, like the monitor core of the FU540, cannot
+        // run the kernel
+        .filter(|cpu| cpu.property("mmu-type").and_then(|p| p.as_str()).is_some_and(|mmu| mmu != "riscv,none"))
+        .filter_map(|cpu| cpu.reg().next().map(|reg| reg.address as usize))
+        .filter(|&hart| hart != boot_hart);
+
+    let mut started = [false; ::hart::MAX_HARTS];
+    for hart in harts {
+        if hart >= ::hart::MAX_HARTS {
+            warn!("hart {hart} is beyond the {} supported", ::hart::MAX_HARTS);
+            continue;
+        }
+        // Safety: `_start_hart` runs with translation disabled
+        match unsafe { ::sbi::hsm::hart_start(hart, _start_hart as *const () as usize, 0) } {
+            Ok(()) => started[hart] = true,
+            Err(e) => warn!("failed to start hart {hart}: {e:?}"),
+        }
+    }
+
+    let deadline = Instant::now() + TIMEOUT;
+    let pending = || (0..::hart::MAX_HARTS).filter(|&hart| started[hart] && !::hart::is_online(hart));
+    while pending().next().is_some() && Instant::now() < deadline {
+        core::hint::spin_loop();
+    }
+    for hart in pending() {
+        warn!("hart {hart} did not come online");
+    }
+}
+
+/// The supervisor-mode entry point of every hart but the boot hart, from
+/// `_start_hart`.
+#[no_mangle]
+extern "C" fn hart_init(hart_id: usize, _opaque: usize) -> ! {
+    ::memory::paging::init_hart();
+    crate::trap::init();
+    ::time::init_hart();
+    ::interrupt::init_hart(hart_id);
+    ::ipi::init_hart();
+    ::thread::init_hart();
+    ::hart::set_online(hart_id);
+    unsafe { bluemetal_hart(hart_id) }
+}
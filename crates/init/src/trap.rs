@@ -1,12 +1,111 @@
+//! Supervisor-mode trap handling.
+//!
+//! `_trap_early_panic` catches traps until [`init`] installs `_trap`, which
+//! saves a [`TrapFrame`] and dispatches on the cause of the trap.
+
+use core::fmt;
+
+use riscv::instruction::Instruction;
+use riscv::trap::{Cause, Exception, Interrupt, TrapFrame};
+
+/// Replace the early panic trap vector with the full trap handler, which
+/// reaches the hart's [`hart::TrapScratch`] through `sscratch`.
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+pub fn init() {
+    extern "C" {
+        fn _trap();
+    }
+    const SIE: usize = 1 << 1;
+    unsafe {
+        core::arch::asm!(
+            "csrw sscratch, {scratch}",
+            "csrw stvec, {trap}",
+            "csrs sstatus, {sie}",
+            scratch = in(reg) ::hart::trap_scratch() as *const _,
+            trap = in(reg) _trap,
+            sie = in(reg) SIE,
+        );
+    }
+}
+
+/// Handle a trap taken to supervisor mode, dispatching on its cause.
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+#[no_mangle]
+extern "C" fn trap_handler(frame: &mut TrapFrame) {
+    match frame.cause() {
+        Cause::Interrupt(Interrupt::SupervisorSoftware) => ::ipi::interrupt(),
+        Cause::Interrupt(Interrupt::SupervisorTimer) => ::time::interrupt(),
+        Cause::Interrupt(Interrupt::SupervisorExternal) => ::interrupt::handle(),
+        Cause::Exception(Exception::Breakpoint) => breakpoint(frame),
+        _ => panic!("unhandled trap\n{}", Report(frame)),
+    }
+    // the interrupt may have ended the time slice or readied another thread
+    ::thread::preempt();
+}
+
+/// Report a trap taken with the stack pointer below the stack the hart runs
+/// on, from `_trap` on the hart's trap stack.
 #[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
 #[no_mangle]
-extern "C" fn trap_early_panic(trap_pc: usize, trap_cause: usize) -> ! {
+extern "C" fn trap_stack_overflow(frame: &TrapFrame) -> ! {
+    panic!("kernel stack overflow\n{}", Report(frame))
+}
+
+/// A description of a trap for diagnosing it from the serial log: the decoded
+/// cause and trap value, the function and trapping instruction, the saved
+/// registers and the stack.
+struct Report<'a>(&'a TrapFrame);
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frame = self.0;
+        let cause = frame.cause();
+        write!(f, "{cause} at 0x{:016x}", frame.pc)?;
+        let symbol = ::panic::resolve(frame.pc);
+        match symbol {
+            Some(symbol) => writeln!(f, " in {symbol}")?,
+            None => writeln!(f)?,
+        }
+        if let Some(location) = symbol.and_then(|symbol| symbol.location()) {
+            writeln!(f, "    at: {location}")?;
+        }
+        writeln!(f, "  tval: {}", frame.value())?;
+        // the interrupted instruction did not cause an interrupt, and a failed
+        // fetch cannot be repeated
+        if matches!(cause, Cause::Exception(_)) && !cause.is_fetch_fault() {
+            // Safety: the instruction was just fetched from `pc`
+            let instruction = unsafe { Instruction::read(frame.pc) };
+            writeln!(f, "  insn: {instruction}")?;
+        }
+        write!(f, "{frame}")?;
+        write!(f, "{}", ::panic::Backtrace::from_trap(frame))
+    }
+}
+
+/// Report an `ebreak` and continue after it.
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+fn breakpoint(frame: &mut TrapFrame) {
+    ::serial::println!("breakpoint at 0x{:016x}", frame.pc);
+    // Safety: the instruction was just executed from `pc`
+    unsafe { frame.skip_instruction() };
+}
+
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+#[no_mangle]
+pub(crate) extern "C" fn trap_early_panic(frame: &TrapFrame) -> ! {
     extern "C" {
         fn _hang() -> !;
     }
+    use core::sync::atomic::{AtomicBool, Ordering};
+    static TRAPPED: AtomicBool = AtomicBool::new(false);
+    let mut out = unsafe { ::serial::global().force_lock() };
     // UART may not have been initialised
-    ::serial::init();
-    let mut out = ::serial::global().lock();
+    out.ensure_device();
+    // the report unwinds the stack, which can trap again if it is corrupted
+    if TRAPPED.swap(true, Ordering::AcqRel) {
+        let _ = writeln!(out, "trapped while reporting a trap, at 0x{:016x}", frame.pc);
+        let _ = out.flush();
+        unsafe { _hang() }
+    }
     let _ = writeln!(
         out,
         r##"
@@ -49,7 +148,16 @@ extern "C" fn trap_early_panic(trap_pc: usize, trap_cause: usize) -> ! {
           `._              _..-'                         |
              `-..____...-''                              |
  ITS A TRAP!                                             |
-     pc: 0x{trap_pc:016x}    mGk                       |
-  cause: 0x{trap_cause:016x}                              |"##);
+     pc: 0x{:016x}    mGk                       |
+  cause: 0x{:016x}                              |
+
+{}
+{}"##,
+        frame.pc,
+        frame.cause,
+        Report(frame),
+        ::panic::Postmortem,
+    );
+    let _ = out.flush();
     unsafe { _hang() }
 }
@@ -0,0 +1,158 @@
+#![no_std]
+//! External interrupts, routed through the PLIC to handlers registered by
+//! drivers.
+
+pub mod plic;
+
+use core::fmt;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use hart::MAX_HARTS;
+
+/// The priority given to interrupts when they are registered.
+const DEFAULT_PRIORITY: u32 = 1;
+
+/// Services interrupt source `irq` on the device that raised it.
+pub type Handler = fn(irq: u32);
+
+struct Slot {
+    /// The [`Handler`], or null if none is registered.
+    handler: AtomicPtr<()>,
+    /// The hart the interrupt is routed to.
+    hart: AtomicUsize,
+    /// How many times the interrupt has been handled.
+    count: AtomicUsize,
+}
+static SLOTS: [Slot; plic::MAX_SOURCES as usize] = [const {
+    Slot {
+        handler: AtomicPtr::new(core::ptr::null_mut()),
+        hart: AtomicUsize::new(0),
+        count: AtomicUsize::new(0),
+    }
+}; plic::MAX_SOURCES as usize];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The interrupt source does not exist.
+    InvalidIrq(u32),
+    /// Another handler is registered for the interrupt.
+    AlreadyRegistered(u32),
+    /// Interrupts cannot be routed to the hart.
+    InvalidHart(usize),
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidIrq(irq) => write!(f, "no interrupt source {irq}"),
+            Self::AlreadyRegistered(irq) => write!(f, "interrupt {irq} already has a handler"),
+            Self::InvalidHart(hart) => write!(f, "cannot route interrupts to hart {hart}"),
+        }
+    }
+}
+
+/// Find the PLIC in the device tree.
+pub fn init(fdt: Option<&fdt::Fdt>) {
+    plic::init(fdt);
+}
+/// Accept external interrupts on `hart_id`.
+pub fn init_hart(hart_id: usize) {
+    plic::set_threshold(plic::supervisor_context(hart_id), 0);
+    enable_external_interrupts();
+}
+
+/// Call `handler` whenever source `irq` interrupts, routing it to the
+/// current hart.
+pub fn register(irq: u32, handler: Handler) -> Result<(), Error> {
+    let slot = slot(irq)?;
+    slot.handler.compare_exchange(
+        core::ptr::null_mut(),
+        handler as *mut (),
+        Ordering::AcqRel,
+        Ordering::Relaxed,
+    ).map_err(|_| Error::AlreadyRegistered(irq))?;
+    let hart = hart::id();
+    slot.hart.store(hart, Ordering::Relaxed);
+    slot.count.store(0, Ordering::Relaxed);
+    plic::set_priority(irq, DEFAULT_PRIORITY);
+    plic::enable(plic::supervisor_context(hart), irq);
+    Ok(())
+}
+/// Stop handling source `irq`.
+pub fn unregister(irq: u32) -> Result<(), Error> {
+    let slot = slot(irq)?;
+    plic::disable(plic::supervisor_context(slot.hart.load(Ordering::Relaxed)), irq);
+    plic::set_priority(irq, 0);
+    slot.handler.store(core::ptr::null_mut(), Ordering::Release);
+    Ok(())
+}
+/// Route source `irq` to `hart_id` instead.
+pub fn route(irq: u32, hart_id: usize) -> Result<(), Error> {
+    let slot = slot(irq)?;
+    if hart_id >= MAX_HARTS {
+        return Err(Error::InvalidHart(hart_id));
+    }
+    let previous = slot.hart.swap(hart_id, Ordering::Relaxed);
+    plic::enable(plic::supervisor_context(hart_id), irq);
+    if previous != hart_id {
+        plic::disable(plic::supervisor_context(previous), irq);
+    }
+    Ok(())
+}
+
+/// A registered interrupt source.
+#[derive(Debug, Clone, Copy)]
+pub struct Registration {
+    pub irq: u32,
+    pub hart: usize,
+    pub priority: u32,
+    /// How many times the interrupt has been handled.
+    pub count: usize,
+}
+/// The interrupt sources with a handler.
+pub fn registered() -> impl Iterator<Item = Registration> {
+    (1..=plic::sources()).filter_map(|irq| {
+        let slot = &SLOTS[irq as usize];
+        if slot.handler.load(Ordering::Relaxed).is_null() {
+            return None;
+        }
+        Some(Registration {
+            irq,
+            hart: slot.hart.load(Ordering::Relaxed),
+            priority: plic::priority(irq),
+            count: slot.count.load(Ordering::Relaxed),
+        })
+    })
+}
+
+/// Handle every external interrupt pending on the current hart.
+pub fn handle() {
+    let context = plic::supervisor_context(hart::id());
+    while let Some(irq) = plic::claim(context) {
+        if let Some(slot) = SLOTS.get(irq as usize) {
+            let handler = slot.handler.load(Ordering::Acquire);
+            if !handler.is_null() {
+                // Safety: only `Handler`s are stored in the slot
+                let handler = unsafe { core::mem::transmute::<*mut (), Handler>(handler) };
+                handler(irq);
+                slot.count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        plic::complete(context, irq);
+    }
+}
+
+fn slot(irq: u32) -> Result<&'static Slot, Error> {
+    if irq == 0 || irq > plic::sources() {
+        return Err(Error::InvalidIrq(irq));
+    }
+    Ok(&SLOTS[irq as usize])
+}
+
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+fn enable_external_interrupts() {
+    const SEIE: usize = 1 << 9;
+    unsafe { core::arch::asm!("csrs sie, {}", in(reg) SEIE) };
+}
+
+#[cfg(not(any(target_arch = "riscv64", target_arch = "riscv32")))]
+fn enable_external_interrupts() {}
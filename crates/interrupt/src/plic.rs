@@ -0,0 +1,126 @@
+//! Platform-level interrupt controller.
+//!
+//! Each interrupt source has a priority, and each context, a privilege mode on
+//! a hart, has its own set of enabled sources and a priority threshold. A
+//! context claims its highest priority pending interrupt and completes it once
+//! handled.
+
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use hart::MAX_HARTS;
+
+/// Both supported machines place the PLIC here.
+const DEFAULT_BASE: usize = 0x0c00_0000;
+/// The most sources a PLIC can have, including the reserved source 0.
+pub const MAX_SOURCES: u32 = 1024;
+const UNKNOWN: usize = usize::MAX;
+
+static BASE: AtomicUsize = AtomicUsize::new(DEFAULT_BASE);
+/// The highest source number, from `riscv,ndev`.
+static SOURCES: AtomicU32 = AtomicU32::new(MAX_SOURCES - 1);
+/// The supervisor-mode context of each hart, if the device tree lists it.
+static CONTEXTS: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(UNKNOWN) }; MAX_HARTS];
+
+/// Find the PLIC and the supervisor-mode context of each hart in the device
+/// tree.
+///
+/// Each context is listed in the `interrupts-extended` property of the PLIC
+/// as the phandle of a hart's interrupt controller and the interrupt it
+/// raises there.
+pub fn init(fdt: Option<&fdt::Fdt>) {
+    const SUPERVISOR_EXTERNAL: u32 = 9;
+    let Some(fdt) = fdt else {
+        return;
+    };
+    let Some(plic) = fdt.find_compatible("riscv,plic0").next()
+        .or_else(|| fdt.find_compatible("sifive,plic-1.0.0").next())
+    else {
+        return;
+    };
+    if let Some(base) = plic.reg().next() {
+        BASE.store(base.address as usize, Ordering::Relaxed);
+    }
+    if let Some(sources) = plic.property("riscv,ndev").and_then(|ndev| ndev.as_u32()) {
+        SOURCES.store(sources.min(MAX_SOURCES - 1), Ordering::Relaxed);
+    }
+    let Some(cpus) = fdt.find_node("/cpus") else {
+        return;
+    };
+    let hart = |phandle| {
+        cpus.children().find_map(|cpu| {
+            if cpu.child("interrupt-controller")?.phandle()? != phandle {
+                return None;
+            }
+            cpu.reg().next()
+        })
+    };
+    let Some(mut contexts) = plic.property("interrupts-extended").map(|p| p.as_u32s()) else {
+        return;
+    };
+    let pairs = core::iter::from_fn(|| Some((contexts.next()?, contexts.next()?)));
+    for (context, (phandle, irq)) in pairs.enumerate() {
+        if irq != SUPERVISOR_EXTERNAL {
+            continue;
+        }
+        let Some(hart) = hart(phandle).map(|reg| reg.address as usize) else {
+            continue;
+        };
+        if let Some(slot) = CONTEXTS.get(hart) {
+            slot.store(context, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The highest interrupt source number.
+pub fn sources() -> u32 {
+    SOURCES.load(Ordering::Relaxed)
+}
+/// The context that raises supervisor external interrupts on `hart_id`.
+///
+/// Without a device tree, the QEMU `virt` layout of a machine and supervisor
+/// context for each hart is assumed.
+pub fn supervisor_context(hart_id: usize) -> usize {
+    match CONTEXTS.get(hart_id).map(|context| context.load(Ordering::Relaxed)) {
+        Some(context) if context != UNKNOWN => context,
+        _ => 2 * hart_id + 1,
+    }
+}
+
+fn register(offset: usize) -> *mut u32 {
+    (BASE.load(Ordering::Relaxed) + offset) as *mut u32
+}
+
+/// Set the priority of source `irq`, where 0 never interrupts.
+pub fn set_priority(irq: u32, priority: u32) {
+    unsafe { register(4 * irq as usize).write_volatile(priority) }
+}
+pub fn priority(irq: u32) -> u32 {
+    unsafe { register(4 * irq as usize).read_volatile() }
+}
+pub fn is_pending(irq: u32) -> bool {
+    let pending = unsafe { register(0x1000 + 4 * (irq as usize / 32)).read_volatile() };
+    pending & 1 << (irq % 32) != 0
+}
+/// Allow source `irq` to interrupt `context`.
+pub fn enable(context: usize, irq: u32) {
+    let enable = register(0x2000 + 0x80 * context + 4 * (irq as usize / 32));
+    unsafe { enable.write_volatile(enable.read_volatile() | 1 << (irq % 32)) }
+}
+pub fn disable(context: usize, irq: u32) {
+    let enable = register(0x2000 + 0x80 * context + 4 * (irq as usize / 32));
+    unsafe { enable.write_volatile(enable.read_volatile() & !(1 << (irq % 32))) }
+}
+/// Mask interrupts to `context` with a priority less than or equal to
+/// `threshold`.
+pub fn set_threshold(context: usize, threshold: u32) {
+    unsafe { register(0x20_0000 + 0x1000 * context).write_volatile(threshold) }
+}
+/// Take the highest priority interrupt pending for `context`.
+pub fn claim(context: usize) -> Option<u32> {
+    let irq = unsafe { register(0x20_0004 + 0x1000 * context).read_volatile() };
+    (irq != 0).then_some(irq)
+}
+/// Signal that an interrupt taken with [`claim`] has been handled.
+pub fn complete(context: usize, irq: u32) {
+    unsafe { register(0x20_0004 + 0x1000 * context).write_volatile(irq) }
+}
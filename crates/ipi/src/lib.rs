@@ -0,0 +1,204 @@
+#![no_std]
+//! Inter-processor interrupts: running functions on other harts, flushing
+//! their TLBs and stopping them.
+//!
+//! An IPI is a supervisor software interrupt raised through the SBI IPI
+//! extension. Each hart posts at most one call at a time, in its own slot, and
+//! marks it pending for each target hart, which runs it from the interrupt.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use hart::{PerHart, MAX_HARTS};
+use sbi::HartMask;
+use sync::irq;
+use time::{Duration, Instant};
+
+/// How long [`stop_others`] waits for the other harts to stop.
+const STOP_TIMEOUT: Duration = Duration::from_millis(100);
+const NONE: usize = usize::MAX;
+
+/// A function posted by a hart for other harts to run.
+struct Call {
+    function: UnsafeCell<Option<*const (dyn Fn() + Sync)>>,
+    /// How many targets have yet to run the function.
+    remaining: AtomicUsize,
+}
+// Safety: `function` is only written by its hart while no target can read it
+unsafe impl Sync for Call {}
+
+static CALLS: PerHart<Call> = PerHart::new([const {
+    Call { function: UnsafeCell::new(None), remaining: AtomicUsize::new(0) }
+}; MAX_HARTS]);
+/// For each hart, the harts whose calls it has yet to run, as a bitmask.
+static PENDING: PerHart<AtomicUsize> = PerHart::new([const { AtomicUsize::new(0) }; MAX_HARTS]);
+/// The hart stopping the others, or [`NONE`].
+static STOPPING: AtomicUsize = AtomicUsize::new(NONE);
+/// Whether the SBI can fence the TLBs of other harts itself.
+static RFENCE: AtomicBool = AtomicBool::new(false);
+
+/// Check which SBI extensions are available.
+pub fn init() {
+    RFENCE.store(sbi::base::probe_extension(sbi::rfence::EID), Ordering::Relaxed);
+}
+/// Accept IPIs on the current hart.
+pub fn init_hart() {
+    enable_software_interrupts();
+}
+
+/// Interrupt each hart in the bitmask `harts`.
+pub fn send(harts: usize) -> sbi::Result<()> {
+    sbi::ipi::send_ipi(HartMask { mask: harts, base: 0 })
+}
+/// The online harts other than the current one, as a bitmask.
+pub fn others() -> usize {
+    let hart = hart::id();
+    hart::online().filter(|&other| other != hart).fold(0, |mask, other| mask | 1 << other)
+}
+
+/// Run `function` on each online hart in the bitmask `harts`, including the
+/// current one, returning once every hart has run it.
+///
+/// Other harts run `function` from an interrupt, so it must not block or make
+/// calls itself.
+pub fn call(harts: usize, function: impl Fn() + Sync) -> sbi::Result<()> {
+    let hart = hart::id();
+    let targets = harts & others();
+    let result = if targets != 0 { call_others(hart, targets, &function) } else { Ok(()) };
+    if harts & 1 << hart != 0 {
+        function();
+    }
+    result
+}
+fn call_others(hart: usize, targets: usize, function: &(dyn Fn() + Sync)) -> sbi::Result<()> {
+    // an interrupt handler must not post a call over this one
+    let interrupts = irq::disable();
+    let call = CALLS.of(hart);
+    // Safety: no target reads the slot until the call is marked pending, and
+    // the call is waited for before `function` goes out of scope
+    unsafe {
+        let function: *const (dyn Fn() + Sync + '_) = function;
+        *call.function.get() = Some(core::mem::transmute::<
+            *const (dyn Fn() + Sync + '_),
+            *const (dyn Fn() + Sync + 'static),
+        >(function));
+    }
+    call.remaining.store(targets.count_ones() as usize, Ordering::Relaxed);
+    for target in bits(targets) {
+        PENDING.of(target).fetch_or(1 << hart, Ordering::Release);
+    }
+    let result = send(targets);
+    if result.is_err() {
+        // withdraw the call from every target that has not taken it
+        for target in bits(targets) {
+            if PENDING.of(target).fetch_and(!(1 << hart), Ordering::AcqRel) & 1 << hart != 0 {
+                call.remaining.fetch_sub(1, Ordering::Release);
+            }
+        }
+    }
+    while call.remaining.load(Ordering::Acquire) != 0 {
+        // a target may be waiting on a call of its own to this hart
+        run_calls();
+        core::hint::spin_loop();
+    }
+    irq::restore(interrupts);
+    result
+}
+/// Run the calls pending for the current hart.
+fn run_calls() {
+    let pending = PENDING.get().swap(0, Ordering::Acquire);
+    for caller in bits(pending) {
+        let call = CALLS.of(caller);
+        // Safety: the caller keeps the function alive until every target has
+        // run it
+        if let Some(function) = unsafe { *call.function.get() } {
+            unsafe { (*function)() };
+        }
+        call.remaining.fetch_sub(1, Ordering::Release);
+    }
+}
+
+/// Flush the TLBs of every online hart, for one address or all of them, after
+/// a change to the kernel page tables.
+pub fn flush_tlb(virt: Option<usize>) {
+    flush(virt);
+    let others = others();
+    if others == 0 {
+        return;
+    }
+    if RFENCE.load(Ordering::Relaxed) {
+        // a size of 0 flushes everything, and any size flushes a whole page
+        let (start, size) = virt.map_or((0, 0), |virt| (virt, 1));
+        if sbi::rfence::remote_sfence_vma(HartMask { mask: others, base: 0 }, start, size).is_ok() {
+            return;
+        }
+    }
+    let _ = call(others, || flush(virt));
+}
+
+/// Stop every other hart, waiting briefly for them to go offline.
+///
+/// Returns false if another hart is already stopping the system, in which
+/// case the current hart should [`halt`].
+pub fn stop_others() -> bool {
+    let hart = hart::id();
+    if let Err(stopping) = STOPPING.compare_exchange(NONE, hart, Ordering::AcqRel, Ordering::Acquire) {
+        return stopping == hart;
+    }
+    let others = others();
+    let _ = send(others);
+    let deadline = Instant::now() + STOP_TIMEOUT;
+    while bits(others).any(hart::is_online) && Instant::now() < deadline {
+        core::hint::spin_loop();
+    }
+    true
+}
+/// Take the current hart offline and idle it forever.
+pub fn halt() -> ! {
+    irq::disable();
+    hart::set_offline(hart::id());
+    loop {
+        irq::wait();
+    }
+}
+
+/// Handle a supervisor software interrupt.
+pub fn interrupt() {
+    clear_software_interrupt();
+    let stopping = STOPPING.load(Ordering::Acquire);
+    if stopping != NONE && stopping != hart::id() {
+        halt();
+    }
+    run_calls();
+}
+
+/// The harts in the bitmask `harts`.
+fn bits(harts: usize) -> impl Iterator<Item = usize> {
+    (0..MAX_HARTS).filter(move |hart| harts & 1 << hart != 0)
+}
+
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+fn enable_software_interrupts() {
+    const SSIE: usize = 1 << 1;
+    unsafe { core::arch::asm!("csrs sie, {}", in(reg) SSIE) };
+}
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+fn clear_software_interrupt() {
+    const SSIP: usize = 1 << 1;
+    unsafe { core::arch::asm!("csrc sip, {}", in(reg) SSIP) };
+}
+/// Flush the TLB of the current hart, for one address or all of them.
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+fn flush(virt: Option<usize>) {
+    match virt {
+        Some(virt) => unsafe { core::arch::asm!("sfence.vma {}, zero", in(reg) virt) },
+        None => unsafe { core::arch::asm!("sfence.vma") },
+    }
+}
+
+#[cfg(not(any(target_arch = "riscv64", target_arch = "riscv32")))]
+fn enable_software_interrupts() {}
+#[cfg(not(any(target_arch = "riscv64", target_arch = "riscv32")))]
+fn clear_software_interrupt() {}
+#[cfg(not(any(target_arch = "riscv64", target_arch = "riscv32")))]
+fn flush(_: Option<usize>) {}
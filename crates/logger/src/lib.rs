@@ -0,0 +1,182 @@
+#![no_std]
+//! The kernel logger, behind the `log` facade.
+//!
+//! Records are kept in memory for `dmesg`, dropping the oldest to make room,
+//! and printed to the console with the time since boot, the hart and the
+//! level. The `[log]` table of the profile sets the level logged by each
+//! module, and whether levels are coloured.
+
+mod ring;
+
+use core::fmt::{self, Write};
+
+use log::{Level, LevelFilter, Log, Metadata};
+use sync::IrqSafeSpinLock;
+use time::Duration;
+
+use ring::Ring;
+
+/// The default level, then `module=level` for each module with its own,
+/// separated by commas.
+const FILTER: &str = env!("BLUEMETAL_LOG");
+const COLOUR: bool = matches!(env!("BLUEMETAL_LOG_COLOUR").as_bytes(), b"true");
+/// The longest text of a record, beyond which it is cut off.
+const MAX_TEXT: usize = 512;
+
+static LOGGER: Logger = Logger;
+static RING: IrqSafeSpinLock<Ring> = IrqSafeSpinLock::new(Ring::new());
+
+/// Install the logger for the `log` macros.
+pub fn init() {
+    // only fails if a logger is already installed
+    let _ = log::set_logger(&LOGGER);
+    let max = directives().map(|(_, level)| level).max().unwrap_or(LevelFilter::Info);
+    log::set_max_level(max);
+}
+
+/// Call `f` with each of the last `count` records kept, oldest first.
+///
+/// Records cannot be logged until `f` returns.
+pub fn for_each(count: usize, mut f: impl FnMut(&Record<'_>)) {
+    let ring = RING.lock();
+    for record in ring.iter().skip(ring.len().saturating_sub(count)) {
+        f(&record);
+    }
+}
+
+/// Like [`for_each`], but calls nothing and returns false if a hart holds the
+/// records, rather than waiting for it.
+///
+/// For reporting why the kernel stopped, as the hart holding them may never
+/// let go.
+pub fn try_for_each(count: usize, mut f: impl FnMut(&Record<'_>)) -> bool {
+    let Some(ring) = RING.try_lock() else {
+        return false;
+    };
+    for record in ring.iter().skip(ring.len().saturating_sub(count)) {
+        f(&record);
+    }
+    true
+}
+
+/// A record kept in memory.
+#[derive(Debug, Clone, Copy)]
+pub struct Record<'a> {
+    pub level: Level,
+    pub hart: usize,
+    /// The time since boot.
+    pub time: Duration,
+    /// The module that logged the record, then the message.
+    pub text: &'a str,
+}
+/// Prints the record as on the console.
+impl fmt::Display for Record<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{:>5}.{:06}] hart {} ", self.time.as_secs(), self.time.subsec_micros(), self.hart)?;
+        if COLOUR {
+            write!(f, "\x1b[{}m{:<5}\x1b[0m", colour(self.level), self.level)?;
+        } else {
+            write!(f, "{:<5}", self.level)?;
+        }
+        write!(f, " {}", self.text)
+    }
+}
+
+/// The ANSI foreground colour of `level`.
+fn colour(level: Level) -> u8 {
+    match level {
+        Level::Error => 31,
+        Level::Warn => 33,
+        Level::Info => 32,
+        Level::Debug => 36,
+        Level::Trace => 35,
+    }
+}
+
+struct Logger;
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= level(metadata.target())
+    }
+    fn log(&self, record: &log::Record<'_>) {
+        // a record logged while keeping another, such as from its message,
+        // is dropped
+        if !self.enabled(record.metadata()) || RING.is_held() {
+            return;
+        }
+        let mut text = Text { buffer: [0; MAX_TEXT], len: 0 };
+        let _ = write!(text, "{}: {}", record.target(), record.args());
+
+        let mut ring = RING.lock();
+        let record = ring.push(record.level(), ::hart::id(), ::time::uptime(), text.as_str());
+        // the record may be from printing, and is still kept
+        let serial = ::serial::global();
+        if !serial.is_held() {
+            let _ = writeln!(serial.lock(), "{record}");
+        }
+    }
+    fn flush(&self) {
+        let serial = ::serial::global();
+        if !serial.is_held() {
+            let _ = serial.lock().flush();
+        }
+    }
+}
+
+/// The `(module, level)` directives of the filter, with no module for the
+/// default level.
+fn directives() -> impl Iterator<Item = (Option<&'static str>, LevelFilter)> {
+    FILTER.split(',').filter_map(|directive| match directive.split_once('=') {
+        Some((module, level)) => Some((Some(module), level.parse().ok()?)),
+        None => Some((None, directive.parse().ok()?)),
+    })
+}
+
+/// The most detailed level logged by `target`, from the directive of the
+/// innermost module containing it.
+fn level(target: &str) -> LevelFilter {
+    let mut default = LevelFilter::Info;
+    let mut innermost: Option<(&str, LevelFilter)> = None;
+    for (module, level) in directives() {
+        match module {
+            None => default = level,
+            Some(module) if contains(module, target) => match innermost {
+                Some((other, _)) if other.len() >= module.len() => {},
+                _ => innermost = Some((module, level)),
+            },
+            Some(_) => {},
+        }
+    }
+    innermost.map_or(default, |(_, level)| level)
+}
+
+/// Whether `target` is `module` or one of its submodules.
+fn contains(module: &str, target: &str) -> bool {
+    target.strip_prefix(module).is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+/// The text of a record, cut off at the last whole character that fits.
+struct Text {
+    buffer: [u8; MAX_TEXT],
+    len: usize,
+}
+impl Text {
+    fn as_str(&self) -> &str {
+        // Safety: only whole characters are written
+        unsafe { core::str::from_utf8_unchecked(&self.buffer[..self.len]) }
+    }
+}
+impl Write for Text {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut len = s.len().min(MAX_TEXT - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.buffer[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        if len < s.len() {
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+//! The records kept in memory.
+//!
+//! Each record is a header of its level, hart, text length and time, followed
+//! by its text, and never wraps around the end of the buffer. A record that
+//! does not fit before the end starts over at the beginning, leaving the end
+//! unused.
+
+use log::Level;
+use time::Duration;
+
+use crate::{Record, MAX_TEXT};
+
+/// Bytes of records kept. A power of two.
+const SIZE: usize = 0x1_0000;
+const HEADER_SIZE: usize = 12;
+/// The level of the unused end of the buffer.
+const UNUSED: u8 = 0;
+
+const _: () = assert!(SIZE.is_power_of_two() && HEADER_SIZE + MAX_TEXT <= SIZE / 2);
+
+pub(crate) struct Ring {
+    buffer: [u8; SIZE],
+    /// The position of the oldest record.
+    ///
+    /// Positions wrap at `usize::MAX` rather than the buffer size, so the
+    /// buffer is full rather than empty when they are `SIZE` apart.
+    head: usize,
+    /// The position after the newest record.
+    tail: usize,
+    /// The number of records kept.
+    len: usize,
+}
+impl Ring {
+    pub(crate) const fn new() -> Self {
+        Self { buffer: [0; SIZE], head: 0, tail: 0, len: 0 }
+    }
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+    /// Keep a record, dropping the oldest ones to make room for it.
+    ///
+    /// `text` must be at most [`MAX_TEXT`] bytes.
+    pub(crate) fn push(&mut self, level: Level, hart: usize, time: Duration, text: &str) -> Record<'_> {
+        let size = HEADER_SIZE + text.len();
+        let rest = SIZE - self.tail % SIZE;
+        let unused = if rest < size { rest } else { 0 };
+        let end = self.tail.wrapping_add(unused + size);
+        while end.wrapping_sub(self.head) > SIZE {
+            self.pop();
+        }
+        if unused >= HEADER_SIZE {
+            self.buffer[self.tail % SIZE] = UNUSED;
+        }
+
+        let start = self.tail.wrapping_add(unused) % SIZE;
+        let header = &mut self.buffer[start..start + HEADER_SIZE];
+        header[0] = level as u8;
+        header[1] = hart as u8;
+        header[2..4].copy_from_slice(&(text.len() as u16).to_le_bytes());
+        header[4..12].copy_from_slice(&(time.as_nanos() as u64).to_le_bytes());
+        self.buffer[start + HEADER_SIZE..start + size].copy_from_slice(text.as_bytes());
+        self.tail = end;
+        self.len += 1;
+        self.record(start)
+    }
+    /// The records kept, oldest first.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = Record<'_>> {
+        let mut position = self.head;
+        core::iter::from_fn(move || {
+            if position == self.tail {
+                return None;
+            }
+            let record = self.record(position % SIZE);
+            position = self.next(position);
+            Some(record)
+        })
+    }
+    fn pop(&mut self) {
+        self.head = self.next(self.head);
+        self.len -= 1;
+    }
+    /// The position of the record after the one at `position`, skipping the
+    /// unused end of the buffer.
+    fn next(&self, position: usize) -> usize {
+        let length = u16::from_le_bytes([self.buffer[position % SIZE + 2], self.buffer[position % SIZE + 3]]);
+        let next = position.wrapping_add(HEADER_SIZE + length as usize);
+        if next == self.tail {
+            return next;
+        }
+        let rest = SIZE - next % SIZE;
+        if rest < HEADER_SIZE || self.buffer[next % SIZE] == UNUSED {
+            return next.wrapping_add(rest);
+        }
+        next
+    }
+    /// The record at `index` in the buffer.
+    fn record(&self, index: usize) -> Record<'_> {
+        let header = &self.buffer[index..index + HEADER_SIZE];
+        let level = match header[0] {
+            1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            _ => Level::Trace,
+        };
+        let length = u16::from_le_bytes([header[2], header[3]]) as usize;
+        let nanos = u64::from_le_bytes(header[4..12].try_into().unwrap());
+        let text = &self.buffer[index + HEADER_SIZE..index + HEADER_SIZE + length];
+        Record {
+            level,
+            hart: header[1] as usize,
+            time: Duration::from_nanos(nanos),
+            // Safety: only whole strings are kept
+            text: unsafe { core::str::from_utf8_unchecked(text) },
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! A bitmap allocator for physical frames.
+//!
+//! Each region of physical memory keeps a bitmap of its frames at the start of
+//! its first free space, with one bit set for every used frame.
+
+use sync::IrqSafeSpinLock;
+use crate::Region;
+
+pub const FRAME_SIZE: usize = 4096;
+
+/// The most regions of physical memory that can be managed.
+const MAX_ZONES: usize = 8;
+
+static ALLOCATOR: IrqSafeSpinLock<FrameAllocator> = IrqSafeSpinLock::new(FrameAllocator::new());
+
+/// The sizes of frames that can be mapped by a single page table entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameSize {
+    /// A 4 KiB frame.
+    Size4K,
+    /// A 2 MiB mega frame.
+    Size2M,
+    /// A 1 GiB giga frame.
+    Size1G,
+}
+impl FrameSize {
+    pub const fn bytes(self) -> usize {
+        match self {
+            Self::Size4K => FRAME_SIZE,
+            Self::Size2M => 512 * FRAME_SIZE,
+            Self::Size1G => 512 * 512 * FRAME_SIZE,
+        }
+    }
+    /// The number of 4 KiB frames.
+    pub const fn frames(self) -> usize {
+        self.bytes() / FRAME_SIZE
+    }
+}
+
+/// Frame usage counts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub total: usize,
+    pub free: usize,
+}
+
+/// Allocate a frame of `size`, aligned to its size.
+///
+/// The frame is not zeroed.
+pub fn allocate(size: FrameSize) -> Option<usize> {
+    ALLOCATOR.lock().allocate(size.frames(), size.frames())
+}
+
+/// Allocate `frames` physically contiguous 4 KiB frames.
+///
+/// The frames are not zeroed.
+pub fn allocate_contiguous(frames: usize) -> Option<usize> {
+    ALLOCATOR.lock().allocate(frames, 1)
+}
+
+/// Return a frame from [`allocate`] to the allocator.
+///
+/// # Safety
+/// The frame must not be used after it is freed.
+///
+/// # Panics
+/// Panics if the frame is not allocated.
+pub unsafe fn free(address: usize, size: FrameSize) {
+    ALLOCATOR.lock().free(address, size.frames())
+}
+
+/// Return frames from [`allocate_contiguous`] to the allocator.
+///
+/// # Safety
+/// The frames must not be used after they are freed.
+///
+/// # Panics
+/// Panics if any of the frames are not allocated.
+pub unsafe fn free_contiguous(address: usize, frames: usize) {
+    ALLOCATOR.lock().free(address, frames)
+}
+
+/// Frame usage across all regions.
+pub fn stats() -> Stats {
+    let allocator = ALLOCATOR.lock();
+    allocator.zones().fold(Stats::default(), |stats, zone| Stats {
+        total: stats.total + zone.frames,
+        free: stats.free + zone.free,
+    })
+}
+
+/// Call `f` with each managed region and its frame usage.
+pub fn for_each_region(mut f: impl FnMut(Region, Stats)) {
+    let allocator = ALLOCATOR.lock();
+    for zone in allocator.zones() {
+        f(zone.region(), Stats { total: zone.frames, free: zone.free });
+    }
+}
+
+/// Manage the frames of a region of physical memory, excluding any that are
+/// `reserved`.
+///
+/// Regions that are too small to hold their own bitmap are ignored.
+///
+/// # Safety
+/// The region must be usable memory that is not otherwise in use.
+pub unsafe fn add_region(region: Region, reserved: &[Region]) {
+    let Some(zone) = Zone::new(region, reserved) else {
+        return;
+    };
+    let mut allocator = ALLOCATOR.lock();
+    if let Some(slot) = allocator.zones.iter_mut().find(|zone| zone.is_none()) {
+        *slot = Some(zone);
+    }
+}
+
+struct FrameAllocator {
+    zones: [Option<Zone>; MAX_ZONES],
+}
+impl FrameAllocator {
+    const fn new() -> Self {
+        Self {
+            zones: [const { None }; MAX_ZONES],
+        }
+    }
+    fn zones(&self) -> impl Iterator<Item = &Zone> {
+        self.zones.iter().flatten()
+    }
+    fn allocate(&mut self, frames: usize, align: usize) -> Option<usize> {
+        self.zones.iter_mut().flatten().find_map(|zone| zone.allocate(frames, align))
+    }
+    fn free(&mut self, address: usize, frames: usize) {
+        let Some(zone) = self.zones.iter_mut().flatten().find(|zone| zone.region().contains(address)) else {
+            panic!("freed frame {address:#x} is not managed by the frame allocator");
+        };
+        zone.free(address, frames);
+    }
+}
+
+/// A region of physical memory divided into frames.
+struct Zone {
+    /// The address of the first frame.
+    base: usize,
+    frames: usize,
+    free: usize,
+    /// One bit for each frame, set if it is in use.
+    bitmap: &'static mut [u64],
+    /// A word of the bitmap that may have a free frame.
+    hint: usize,
+}
+impl Zone {
+    unsafe fn new(region: Region, reserved: &[Region]) -> Option<Self> {
+        let base = align_up(region.start, FRAME_SIZE);
+        let end = region.end & !(FRAME_SIZE - 1);
+        let frames = end.checked_sub(base)? / FRAME_SIZE;
+        let words = frames.div_ceil(64);
+        let bitmap_size = align_up(words * 8, FRAME_SIZE);
+
+        // place the bitmap in the first space clear of reserved regions
+        let mut bitmap_start = base;
+        'search: loop {
+            let bitmap_region = Region::new(bitmap_start, bitmap_size);
+            if bitmap_region.end > end {
+                return None;
+            }
+            for reserved in reserved {
+                if bitmap_region.overlaps(reserved) {
+                    bitmap_start = align_up(reserved.end, FRAME_SIZE);
+                    continue 'search;
+                }
+            }
+            break;
+        }
+        let bitmap = core::slice::from_raw_parts_mut(bitmap_start as *mut u64, words);
+        bitmap.fill(0);
+
+        let mut zone = Self {
+            base,
+            frames,
+            free: frames,
+            bitmap,
+            hint: 0,
+        };
+        // frames past the end of the region in the last word are never free
+        if !frames.is_multiple_of(64) {
+            zone.bitmap[words - 1] = !0 << (frames % 64);
+        }
+        zone.reserve(Region::new(bitmap_start, bitmap_size));
+        for reserved in reserved {
+            zone.reserve(*reserved);
+        }
+        Some(zone)
+    }
+    fn region(&self) -> Region {
+        Region::new(self.base, self.frames * FRAME_SIZE)
+    }
+    /// Mark all frames overlapping `region` as used.
+    fn reserve(&mut self, region: Region) {
+        let zone = self.region();
+        if !zone.overlaps(&region) {
+            return;
+        }
+        let start = (region.start.max(zone.start) - self.base) / FRAME_SIZE;
+        let end = (align_up(region.end.min(zone.end), FRAME_SIZE) - self.base) / FRAME_SIZE;
+        for frame in start..end {
+            if !self.is_used(frame) {
+                self.set_used(frame, true);
+                self.free -= 1;
+            }
+        }
+    }
+    #[inline]
+    fn is_used(&self, frame: usize) -> bool {
+        self.bitmap[frame / 64] & 1 << (frame % 64) != 0
+    }
+    #[inline]
+    fn set_used(&mut self, frame: usize, used: bool) {
+        let word = &mut self.bitmap[frame / 64];
+        if used {
+            *word |= 1 << (frame % 64);
+        } else {
+            *word &= !(1 << (frame % 64));
+        }
+    }
+    /// Whether `count` frames starting from `frame` are all free.
+    fn is_free(&self, frame: usize, count: usize) -> bool {
+        let end = frame + count;
+        let mut frame = frame;
+        while frame < end {
+            if frame.is_multiple_of(64) && frame + 64 <= end {
+                if self.bitmap[frame / 64] != 0 {
+                    return false;
+                }
+                frame += 64;
+            } else {
+                if self.is_used(frame) {
+                    return false;
+                }
+                frame += 1;
+            }
+        }
+        true
+    }
+    /// Allocate `count` frames with the first aligned to `align` frames.
+    fn allocate(&mut self, count: usize, align: usize) -> Option<usize> {
+        if count == 0 || count > self.free {
+            return None;
+        }
+        let frame = if count == 1 {
+            self.find_single()?
+        } else {
+            // alignment is relative to physical addresses, not the zone
+            let align_bytes = align * FRAME_SIZE;
+            let first = (align_up(self.base, align_bytes) - self.base) / FRAME_SIZE;
+            (first..self.frames.saturating_sub(count - 1))
+                .step_by(align)
+                .find(|&frame| self.is_free(frame, count))?
+        };
+        for frame in frame..frame + count {
+            self.set_used(frame, true);
+        }
+        self.free -= count;
+        Some(self.base + frame * FRAME_SIZE)
+    }
+    fn find_single(&mut self) -> Option<usize> {
+        let words = self.bitmap.len();
+        for i in 0..words {
+            let word = (self.hint + i) % words;
+            let bits = self.bitmap[word];
+            if bits != !0 {
+                self.hint = word;
+                return Some(word * 64 + bits.trailing_ones() as usize);
+            }
+        }
+        None
+    }
+    fn free(&mut self, address: usize, count: usize) {
+        let frame = (address - self.base) / FRAME_SIZE;
+        assert!(address.is_multiple_of(FRAME_SIZE) && frame + count <= self.frames, "freed frames {address:#x} are out of bounds");
+        for frame in frame..frame + count {
+            if !self.is_used(frame) {
+                panic!("double free of frame {:#x}", self.base + frame * FRAME_SIZE);
+            }
+            self.set_used(frame, false);
+        }
+        self.free += count;
+        self.hint = frame / 64;
+    }
+}
+
+#[inline]
+const fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
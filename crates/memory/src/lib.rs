@@ -0,0 +1,149 @@
+#![no_std]
+//! Physical memory management.
+//!
+//! Discovers physical memory from the device tree, or the profile as a
+//! fallback, hands it out in frames, and maps it into the kernel address space.
+
+pub mod frame;
+pub mod paging;
+
+pub use frame::{FrameSize, FRAME_SIZE};
+
+/// The most memory regions or reserved regions that can be tracked.
+const MAX_REGIONS: usize = 16;
+
+/// A range of physical addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub start: usize,
+    pub end: usize,
+}
+impl Region {
+    pub const fn new(start: usize, size: usize) -> Self {
+        Self {
+            start,
+            end: start.saturating_add(size),
+        }
+    }
+    pub const fn size(&self) -> usize {
+        self.end - self.start
+    }
+    pub const fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+    pub const fn overlaps(&self, other: &Region) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+    pub const fn contains(&self, address: usize) -> bool {
+        self.start <= address && address < self.end
+    }
+}
+
+/// A fixed capacity list of regions.
+#[derive(Clone, Copy)]
+pub struct Regions {
+    regions: [Region; MAX_REGIONS],
+    len: usize,
+}
+impl Regions {
+    pub const fn new() -> Self {
+        Self {
+            regions: [Region { start: 0, end: 0 }; MAX_REGIONS],
+            len: 0,
+        }
+    }
+    /// Add a region, returning false if there is no room left.
+    pub fn push(&mut self, region: Region) -> bool {
+        if region.is_empty() {
+            return true;
+        }
+        let Some(slot) = self.regions.get_mut(self.len) else {
+            return false;
+        };
+        *slot = region;
+        self.len += 1;
+        true
+    }
+    pub fn as_slice(&self) -> &[Region] {
+        &self.regions[..self.len]
+    }
+}
+impl Default for Regions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The physical memory occupied by the kernel image, including the hart
+/// stacks.
+pub fn kernel() -> Region {
+    extern "C" {
+        static _kernel_start: u8;
+        static _kernel_end: u8;
+    }
+    let start = core::ptr::addr_of!(_kernel_start) as usize;
+    let end = core::ptr::addr_of!(_kernel_end) as usize;
+    Region { start, end }
+}
+
+/// Usable physical memory described by the `/memory` nodes of the device
+/// tree, or the `[memory]` table of the profile if there are none.
+pub fn discover(fdt: Option<&fdt::Fdt>) -> Regions {
+    let mut regions = Regions::new();
+    for node in fdt.into_iter().flat_map(|fdt| fdt.memory()).filter(|node| node.is_enabled()) {
+        for reg in node.reg() {
+            let Some(size) = reg.size else {
+                continue;
+            };
+            regions.push(Region::new(reg.address as usize, size as usize));
+        }
+    }
+    if regions.as_slice().is_empty() {
+        let parse = |value: &str| usize::from_str_radix(value.trim_start_matches("0x"), 16).ok();
+        let base = option_env!("BLUEMETAL_MEMORY_BASE").and_then(parse);
+        let size = option_env!("BLUEMETAL_MEMORY_SIZE").and_then(parse);
+        if let (Some(base), Some(size)) = (base, size) {
+            regions.push(Region::new(base, size));
+        }
+    }
+    regions
+}
+
+/// Physical memory that must never be allocated: the kernel image, the device
+/// tree blob, and the regions it reserves.
+///
+/// # Panics
+/// Panics if there are too many reserved regions to track.
+pub fn reserved(fdt: Option<&fdt::Fdt>) -> Regions {
+    let mut regions = Regions::new();
+    let mut reserve = |region| {
+        if !regions.push(region) {
+            panic!("too many reserved memory regions");
+        }
+    };
+    reserve(kernel());
+    let Some(fdt) = fdt else {
+        return regions;
+    };
+    reserve(Region::new(fdt.as_ptr() as usize, fdt.total_size()));
+    for reservation in fdt.reservations() {
+        reserve(Region::new(reservation.address as usize, reservation.size.unwrap_or(0) as usize));
+    }
+    let reserved_memory = fdt.find_node("/reserved-memory").into_iter().flat_map(|node| node.children());
+    for reg in reserved_memory.flat_map(|node| node.reg()) {
+        reserve(Region::new(reg.address as usize, reg.size.unwrap_or(0) as usize));
+    }
+    regions
+}
+
+/// Discover physical memory, initialise the frame allocator and build the
+/// kernel page tables.
+pub fn init(fdt: Option<&fdt::Fdt>) {
+    let memory = discover(fdt);
+    let reserved = reserved(fdt);
+    for region in memory.as_slice() {
+        // Safety: the region is usable memory, with reserved regions excluded
+        unsafe { frame::add_region(*region, reserved.as_slice()) };
+    }
+    paging::init(memory.as_slice(), paging::Mode::detect(fdt));
+}
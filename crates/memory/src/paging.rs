@@ -0,0 +1,546 @@
+//! Sv39 and Sv48 page tables.
+//!
+//! Page tables live in frames from the frame allocator and are accessed
+//! through their physical addresses, which the kernel address space maps to
+//! themselves.
+//!
+//! User address spaces are Sv39, and share the kernel mappings below
+//! [`USER_START`] with the kernel address space.
+
+use core::ops::{BitOr, BitOrAssign};
+use sync::IrqSafeSpinLock;
+use crate::{frame, FrameSize, Region, FRAME_SIZE};
+
+const ENTRIES: usize = 512;
+
+/// The lowest address of user space. The kernel maps nothing from here up.
+pub const USER_START: usize = 0x10_0000_0000;
+/// The end of the lower half of Sv39, and of user space.
+pub const USER_END: usize = 0x40_0000_0000;
+
+static KERNEL: IrqSafeSpinLock<Option<AddressSpace>> = IrqSafeSpinLock::new(None);
+
+/// A virtual memory translation scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Three levels of page tables, for 39-bit virtual addresses.
+    Sv39,
+    /// Four levels of page tables, for 48-bit virtual addresses.
+    Sv48,
+}
+impl Mode {
+    pub const fn levels(self) -> usize {
+        match self {
+            Self::Sv39 => 3,
+            Self::Sv48 => 4,
+        }
+    }
+    /// The value of the `satp.MODE` field.
+    const fn satp(self) -> usize {
+        match self {
+            Self::Sv39 => 8,
+            Self::Sv48 => 9,
+        }
+    }
+    /// Whether `address` is a valid virtual address, with the bits above the
+    /// highest translated bit all copies of it.
+    pub const fn is_canonical(self, address: usize) -> bool {
+        let bits = 12 + 9 * self.levels();
+        let high = (address as isize) >> (bits - 1);
+        high == 0 || high == -1
+    }
+    /// The widest mode supported by every hart, from the `mmu-type` of each
+    /// cpu in the device tree, or Sv39 without one.
+    pub fn detect(fdt: Option<&fdt::Fdt>) -> Self {
+        let cpus = fdt.and_then(|fdt| fdt.find_node("/cpus"));
+        let mut mmu_types = cpus.into_iter()
+            .flat_map(|cpus| cpus.children())
+            .filter(|cpu| cpu.base_name() == "cpu")
+            .filter_map(|cpu| cpu.property("mmu-type")?.as_str())
+            .filter(|mmu_type| *mmu_type != "riscv,none")
+            .peekable();
+        if mmu_types.peek().is_some() && mmu_types.all(|mmu_type| matches!(mmu_type, "riscv,sv48" | "riscv,sv57")) {
+            Self::Sv48
+        } else {
+            Self::Sv39
+        }
+    }
+}
+
+/// Page table entry permission and attribute bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flags(u64);
+impl Flags {
+    pub const NONE: Self = Self(0);
+    pub const READ: Self = Self(1 << 1);
+    pub const WRITE: Self = Self(1 << 2);
+    pub const EXECUTE: Self = Self(1 << 3);
+    /// Accessible from user mode.
+    pub const USER: Self = Self(1 << 4);
+    /// Present in every address space.
+    pub const GLOBAL: Self = Self(1 << 5);
+    pub const ACCESSED: Self = Self(1 << 6);
+    pub const DIRTY: Self = Self(1 << 7);
+
+    const VALID: u64 = 1 << 0;
+    const ALL: u64 = 0xff;
+
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+    /// Whether an entry with these flags maps a page rather than pointing to
+    /// the next level of page table.
+    const fn is_leaf(self) -> bool {
+        self.0 & (Self::READ.0 | Self::WRITE.0 | Self::EXECUTE.0) != 0
+    }
+}
+impl BitOr for Flags {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+impl BitOrAssign for Flags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    /// An address is not aligned to the page size.
+    Misaligned,
+    /// The virtual address is outside the range of the translation mode.
+    NonCanonical,
+    /// Part of the range is already mapped.
+    AlreadyMapped,
+    /// The address is not mapped.
+    NotMapped,
+    /// A page table could not be allocated.
+    OutOfMemory,
+    /// Leaf entries must be readable, writable or executable, and writable
+    /// pages must also be readable.
+    InvalidFlags,
+}
+
+/// A page table entry.
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+struct Entry(u64);
+impl Entry {
+    const EMPTY: Self = Self(0);
+
+    const fn new(address: usize, flags: u64) -> Self {
+        Self((address as u64 >> 12) << 10 | flags | Flags::VALID)
+    }
+    const fn is_valid(self) -> bool {
+        self.0 & Flags::VALID != 0
+    }
+    const fn flags(self) -> Flags {
+        Flags(self.0 & Flags::ALL & !Flags::VALID)
+    }
+    const fn address(self) -> usize {
+        ((self.0 >> 10) & ((1 << 44) - 1)) as usize * FRAME_SIZE
+    }
+}
+
+/// A tree of page tables mapping virtual addresses to physical addresses.
+pub struct AddressSpace {
+    /// The physical address of the root page table.
+    root: usize,
+    mode: Mode,
+    /// How many entries of the root page table are shared with the kernel
+    /// address space, and so not owned by this one.
+    shared: usize,
+}
+// Safety: the page tables are owned by the address space
+unsafe impl Send for AddressSpace {}
+impl AddressSpace {
+    /// Create an address space with nothing mapped.
+    pub fn new(mode: Mode) -> Option<Self> {
+        Some(Self {
+            root: allocate_table()?,
+            mode,
+            shared: 0,
+        })
+    }
+    /// Create an Sv39 address space for user mode, which shares the kernel
+    /// mappings below [`USER_START`].
+    ///
+    /// Kernel mappings made later are only seen if they fall within a
+    /// gigabyte the kernel had already mapped something in.
+    pub fn user() -> Option<Self> {
+        let kernel = KERNEL.lock();
+        let mut space = Self::new(Mode::Sv39)?;
+        space.shared = index(USER_START, 2);
+        let Some(kernel) = kernel.as_ref() else {
+            return Some(space);
+        };
+        // the lower half of Sv39 is the first entry of an Sv48 root table
+        let mut table = kernel.root;
+        if kernel.mode == Mode::Sv48 {
+            // Safety: the kernel page tables outlive every address space
+            let entry = unsafe { table_at(table)[0] };
+            if !entry.is_valid() || entry.flags().is_leaf() {
+                return Some(space);
+            }
+            table = entry.address();
+        }
+        // Safety: as above, and the new root table is owned by `space`
+        let shared = space.shared;
+        unsafe { table_at(space.root)[..shared].copy_from_slice(&table_at(table)[..shared]) };
+        Some(space)
+    }
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+    /// The physical address of the root page table.
+    pub fn root(&self) -> usize {
+        self.root
+    }
+    /// The value of `satp` that selects this address space.
+    pub fn satp(&self, asid: u16) -> usize {
+        self.mode.satp() << 60 | (asid as usize) << 44 | (self.root / FRAME_SIZE)
+    }
+    /// Map a single page of `size` at `virt` to `phys`.
+    ///
+    /// Pages are mapped as accessed, and as dirty if writable, so that
+    /// hardware never has to update the entries.
+    pub fn map(&mut self, virt: usize, phys: usize, size: FrameSize, flags: Flags) -> Result<(), MapError> {
+        if !flags.is_leaf() || (flags.contains(Flags::WRITE) && !flags.contains(Flags::READ)) {
+            return Err(MapError::InvalidFlags);
+        }
+        if !virt.is_multiple_of(size.bytes()) || !phys.is_multiple_of(size.bytes()) {
+            return Err(MapError::Misaligned);
+        }
+        if !self.mode.is_canonical(virt) {
+            return Err(MapError::NonCanonical);
+        }
+        let entry = self.walk(virt, level(size))?;
+        if entry.is_valid() {
+            return Err(MapError::AlreadyMapped);
+        }
+        let mut flags = flags | Flags::ACCESSED;
+        if flags.contains(Flags::WRITE) {
+            flags |= Flags::DIRTY;
+        }
+        *entry = Entry::new(phys, flags.0);
+        Ok(())
+    }
+    /// Map `length` bytes at `virt` to `phys`, with the largest pages that
+    /// fit.
+    ///
+    /// Pages mapped before an error are left in place.
+    pub fn map_range(&mut self, virt: usize, phys: usize, length: usize, flags: Flags) -> Result<(), MapError> {
+        if ![virt, phys, length].iter().all(|value| value.is_multiple_of(FRAME_SIZE)) {
+            return Err(MapError::Misaligned);
+        }
+        let mut offset = 0;
+        while offset < length {
+            let (virt, phys) = (virt + offset, phys + offset);
+            let size = [FrameSize::Size1G, FrameSize::Size2M, FrameSize::Size4K]
+                .into_iter()
+                .find(|size| (virt | phys).is_multiple_of(size.bytes()) && length - offset >= size.bytes())
+                .unwrap_or(FrameSize::Size4K);
+            self.map(virt, phys, size, flags)?;
+            offset += size.bytes();
+        }
+        Ok(())
+    }
+    /// Remove the page mapped at `virt`, returning its physical address and
+    /// size.
+    ///
+    /// Page tables left empty are not freed, and the TLB is not flushed.
+    pub fn unmap(&mut self, virt: usize) -> Result<(usize, FrameSize), MapError> {
+        let (entry, size) = self.leaf(virt).ok_or(MapError::NotMapped)?;
+        if !virt.is_multiple_of(size.bytes()) {
+            return Err(MapError::Misaligned);
+        }
+        // Safety: the entry is in a page table owned by this address space
+        let phys = unsafe { entry.replace(Entry::EMPTY) }.address();
+        Ok((phys, size))
+    }
+    /// Break the page mapping `virt` into pages of `size` mapped the same
+    /// way, so that part of it can be unmapped.
+    ///
+    /// The TLB is not flushed, as nothing is mapped differently.
+    pub fn split(&mut self, virt: usize, size: FrameSize) -> Result<(), MapError> {
+        loop {
+            let (entry, leaf) = self.leaf(virt).ok_or(MapError::NotMapped)?;
+            if leaf.bytes() <= size.bytes() {
+                return Ok(());
+            }
+            let table = allocate_table().ok_or(MapError::OutOfMemory)?;
+            let part = leaf.bytes() / ENTRIES;
+            // Safety: the entry is in a page table owned by this address space,
+            // and the table was just allocated
+            unsafe {
+                let old = entry.read();
+                for (i, new) in table_at(table).iter_mut().enumerate() {
+                    *new = Entry::new(old.address() + i * part, old.flags().0);
+                }
+                entry.write(Entry::new(table, 0));
+            }
+        }
+    }
+    /// The physical address and flags that `virt` is mapped to.
+    pub fn translate(&self, virt: usize) -> Option<(usize, Flags)> {
+        let (entry, size) = self.leaf(virt)?;
+        // Safety: the entry is in a page table owned by this address space
+        let entry = unsafe { entry.read() };
+        Some((entry.address() + virt % size.bytes(), entry.flags()))
+    }
+    /// Find the leaf entry mapping `virt`.
+    fn leaf(&self, virt: usize) -> Option<(*mut Entry, FrameSize)> {
+        if !self.mode.is_canonical(virt) {
+            return None;
+        }
+        let mut table = self.root;
+        for level in (0..self.mode.levels()).rev() {
+            // Safety: page tables are only reached through valid entries
+            let entry = unsafe { &mut table_at(table)[index(virt, level)] };
+            if !entry.is_valid() {
+                return None;
+            }
+            if entry.flags().is_leaf() {
+                return Some((entry, size(level)?));
+            }
+            table = entry.address();
+        }
+        None
+    }
+    /// Find the entry for `virt` at `target` level, allocating page tables
+    /// on the way.
+    fn walk(&mut self, virt: usize, target: usize) -> Result<&mut Entry, MapError> {
+        let mut table = self.root;
+        for level in (target + 1..self.mode.levels()).rev() {
+            // Safety: page tables are only reached through valid entries
+            let entry = unsafe { &mut table_at(table)[index(virt, level)] };
+            if !entry.is_valid() {
+                let next = allocate_table().ok_or(MapError::OutOfMemory)?;
+                *entry = Entry::new(next, 0);
+            } else if entry.flags().is_leaf() {
+                return Err(MapError::AlreadyMapped);
+            }
+            table = entry.address();
+        }
+        // Safety: as above
+        Ok(unsafe { &mut table_at(table)[index(virt, target)] })
+    }
+}
+/// Frees the page tables, but not the pages they map.
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        // Safety: the address space owns the tables past its shared entries
+        unsafe { free_table(self.root, self.mode.levels() - 1, self.shared) };
+    }
+}
+
+/// Build the kernel address space and switch to it.
+///
+/// Physical memory and the kernel are mapped to themselves: kernel text is
+/// read-execute, read-only data is read-only, and data and the stacks and
+/// trap stacks of the harts are read-write, with an unmapped guard page below
+/// each stack. The machine-mode stacks are left unmapped. Everything below the lowest
+/// `memory` region is assumed to be devices and is mapped read-write, except
+/// the first page, which is left unmapped to catch null pointers.
+///
+/// # Panics
+/// Panics if the page tables cannot be allocated.
+pub fn init(memory: &[Region], mode: Mode) {
+    let mut space = AddressSpace::new(mode).expect("no memory for the kernel page tables");
+    let mut map = |region: Region, flags| {
+        if region.is_empty() {
+            return;
+        }
+        let start = region.start & !(FRAME_SIZE - 1);
+        let end = (region.end + FRAME_SIZE - 1) & !(FRAME_SIZE - 1);
+        if end > USER_START {
+            panic!("{start:#x}..{end:#x} overlaps user space, so cannot be mapped into the kernel");
+        }
+        if let Err(e) = space.map_range(start, start, end - start, flags | Flags::GLOBAL) {
+            panic!("failed to map {start:#x}..{end:#x} into the kernel address space: {e:?}");
+        }
+    };
+
+    let rw = Flags::READ | Flags::WRITE;
+    if let Some(lowest) = memory.iter().map(|region| region.start).min() {
+        map(Region { start: FRAME_SIZE, end: lowest }, rw);
+    }
+    let kernel = sections();
+    map(kernel.text, Flags::READ | Flags::EXECUTE);
+    map(kernel.rodata, Flags::READ);
+    map(kernel.data, rw);
+    for hart in 0..hart::MAX_HARTS {
+        let stack = hart::stack(hart);
+        map(Region { start: stack.start, end: stack.end }, rw);
+        let trap = hart::trap_stack(hart);
+        map(Region { start: trap.start, end: trap.end }, rw);
+    }
+    let image = crate::kernel();
+    for region in memory {
+        map(Region { start: region.start, end: image.start.clamp(region.start, region.end) }, rw);
+        map(Region { start: image.end.clamp(region.start, region.end), end: region.end }, rw);
+    }
+
+    // Safety: everything the kernel uses is mapped to itself
+    unsafe { activate(space.satp(0)) };
+    *KERNEL.lock() = Some(space);
+}
+/// Switch a hart started after [`init`] to the kernel address space.
+pub fn init_hart() {
+    let satp = KERNEL.lock().as_ref().map(|space| space.satp(0));
+    if let Some(satp) = satp {
+        // Safety: the kernel address space maps everything the kernel uses to
+        // itself
+        unsafe { activate(satp) };
+    }
+}
+
+/// The value of `satp` that selects the kernel address space, or bare
+/// translation before [`init`].
+pub fn kernel_satp() -> usize {
+    KERNEL.lock().as_ref().map_or(0, |space| space.satp(0))
+}
+
+/// Map a page into the kernel address space.
+pub fn map(virt: usize, phys: usize, size: FrameSize, flags: Flags) -> Result<(), MapError> {
+    with_kernel(|space| space.map(virt, phys, size, flags | Flags::GLOBAL))
+}
+/// Map a range into the kernel address space, with the largest pages that
+/// fit.
+pub fn map_range(virt: usize, phys: usize, length: usize, flags: Flags) -> Result<(), MapError> {
+    with_kernel(|space| space.map_range(virt, phys, length, flags | Flags::GLOBAL))
+}
+/// Break the page mapping `virt` in the kernel address space into pages of
+/// `size`, so that part of it can be unmapped.
+///
+/// User address spaces created before a gigabyte page is split still map all
+/// of it.
+pub fn split(virt: usize, size: FrameSize) -> Result<(), MapError> {
+    with_kernel(|space| space.split(virt, size))
+}
+/// Remove a page from the kernel address space and flush it from the TLB of
+/// every hart.
+///
+/// # Safety
+/// Nothing may access the page after it is unmapped.
+pub unsafe fn unmap(virt: usize) -> Result<(usize, FrameSize), MapError> {
+    let unmapped = with_kernel(|space| space.unmap(virt))?;
+    ::ipi::flush_tlb(Some(virt));
+    Ok(unmapped)
+}
+/// The physical address and flags that `virt` is mapped to in the kernel
+/// address space.
+pub fn translate(virt: usize) -> Option<(usize, Flags)> {
+    let kernel = KERNEL.lock();
+    kernel.as_ref()?.translate(virt)
+}
+fn with_kernel<R>(f: impl FnOnce(&mut AddressSpace) -> Result<R, MapError>) -> Result<R, MapError> {
+    let mut kernel = KERNEL.lock();
+    let space = kernel.as_mut().ok_or(MapError::NotMapped)?;
+    f(space)
+}
+
+/// The regions of the kernel image that are mapped with different
+/// permissions.
+struct Sections {
+    text: Region,
+    rodata: Region,
+    data: Region,
+}
+fn sections() -> Sections {
+    extern "C" {
+        static _rodata_start: u8;
+        static _data_start: u8;
+    }
+    let kernel = crate::kernel();
+    let rodata = core::ptr::addr_of!(_rodata_start) as usize;
+    let data = core::ptr::addr_of!(_data_start) as usize;
+    Sections {
+        text: Region { start: kernel.start, end: rodata },
+        rodata: Region { start: rodata, end: data },
+        data: Region { start: data, end: hart::stacks() },
+    }
+}
+
+fn allocate_table() -> Option<usize> {
+    let table = frame::allocate(FrameSize::Size4K)?;
+    // Safety: the frame was just allocated
+    unsafe { table_at(table).fill(Entry::EMPTY) };
+    Some(table)
+}
+/// Free the page table at `address` of `level`, and the tables below all but
+/// its first `skip` entries.
+///
+/// # Safety
+/// Nothing may use the freed tables again.
+unsafe fn free_table(address: usize, level: usize, skip: usize) {
+    if level > 0 {
+        for entry in &table_at(address)[skip..] {
+            if entry.is_valid() && !entry.flags().is_leaf() {
+                free_table(entry.address(), level - 1, 0);
+            }
+        }
+    }
+    frame::free(address, FrameSize::Size4K);
+}
+/// # Safety
+/// `address` must be a page table owned by an address space.
+unsafe fn table_at<'a>(address: usize) -> &'a mut [Entry; ENTRIES] {
+    &mut *(address as *mut [Entry; ENTRIES])
+}
+const fn index(virt: usize, level: usize) -> usize {
+    (virt >> (12 + 9 * level)) % ENTRIES
+}
+const fn level(size: FrameSize) -> usize {
+    match size {
+        FrameSize::Size4K => 0,
+        FrameSize::Size2M => 1,
+        FrameSize::Size1G => 2,
+    }
+}
+const fn size(level: usize) -> Option<FrameSize> {
+    match level {
+        0 => Some(FrameSize::Size4K),
+        1 => Some(FrameSize::Size2M),
+        2 => Some(FrameSize::Size1G),
+        _ => None,
+    }
+}
+
+/// Switch the current hart to the page tables selected by `satp`.
+///
+/// # Safety
+/// The code and data in use must stay mapped at the same addresses.
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+pub unsafe fn activate(satp: usize) {
+    core::arch::asm!("csrw satp, {}", "sfence.vma", in(reg) satp);
+}
+/// The value of `satp` on the current hart.
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+pub fn satp() -> usize {
+    let satp;
+    unsafe { core::arch::asm!("csrr {}, satp", out(reg) satp) };
+    satp
+}
+/// Flush the TLB of the current hart, for one address or all of them.
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+pub fn flush(virt: Option<usize>) {
+    match virt {
+        Some(virt) => unsafe { core::arch::asm!("sfence.vma {}, zero", in(reg) virt) },
+        None => unsafe { core::arch::asm!("sfence.vma") },
+    }
+}
+
+/// # Safety
+/// The code and data in use must stay mapped at the same addresses.
+#[cfg(not(any(target_arch = "riscv64", target_arch = "riscv32")))]
+pub unsafe fn activate(_: usize) {}
+#[cfg(not(any(target_arch = "riscv64", target_arch = "riscv32")))]
+pub fn satp() -> usize { 0 }
+#[cfg(not(any(target_arch = "riscv64", target_arch = "riscv32")))]
+pub fn flush(_: Option<usize>) {}
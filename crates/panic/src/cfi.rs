@@ -0,0 +1,759 @@
+//! Call frame information from `.eh_frame`, which describes how to recover
+//! the registers of the caller from anywhere in a function.
+//!
+//! Entries are found through the sorted table the linker builds in
+//! `.eh_frame_hdr`, or by scanning `.eh_frame` when there is no usable table.
+//! Only the general-purpose registers are tracked, as the kernel never uses
+//! the float registers.
+
+/// The DWARF number of the stack pointer.
+pub const SP: usize = 2;
+/// The general-purpose registers, numbered as by DWARF.
+pub const REGISTERS: usize = 32;
+
+/// How deeply `DW_CFA_remember_state` can nest.
+const STATE_STACK: usize = 4;
+/// How many values a DWARF expression can push.
+const EXPRESSION_STACK: usize = 16;
+
+pub(crate) const PE_OMIT: u8 = 0xff;
+const PE_ABSPTR: u8 = 0x00;
+const PE_ULEB128: u8 = 0x01;
+const PE_UDATA2: u8 = 0x02;
+const PE_UDATA4: u8 = 0x03;
+const PE_UDATA8: u8 = 0x04;
+const PE_SLEB128: u8 = 0x09;
+const PE_SDATA2: u8 = 0x0a;
+const PE_SDATA4: u8 = 0x0b;
+const PE_SDATA8: u8 = 0x0c;
+const PE_PCREL: u8 = 0x10;
+const PE_DATAREL: u8 = 0x30;
+const PE_FUNCREL: u8 = 0x40;
+const PE_ALIGNED: u8 = 0x50;
+const PE_INDIRECT: u8 = 0x80;
+
+extern "C" {
+    static EH_FRAME: Sections;
+}
+
+/// The sections holding call frame information and language-specific data,
+/// as laid out by `debug.s`.
+#[repr(C)]
+struct Sections {
+    eh_frame: *const u8,
+    eh_frame_len: usize,
+    eh_frame_hdr: *const u8,
+    eh_frame_hdr_len: usize,
+    gcc_except_table: *const u8,
+    gcc_except_table_len: usize,
+}
+fn eh_frame() -> &'static [u8] {
+    // Safety: the linker script places the symbols around the section, which
+    // is read-only
+    unsafe { core::slice::from_raw_parts(EH_FRAME.eh_frame, EH_FRAME.eh_frame_len) }
+}
+fn eh_frame_hdr() -> &'static [u8] {
+    // Safety: as above
+    unsafe { core::slice::from_raw_parts(EH_FRAME.eh_frame_hdr, EH_FRAME.eh_frame_hdr_len) }
+}
+/// The language-specific data starting at `address`, to the end of its
+/// section.
+pub(crate) fn lsda(address: usize) -> Option<Reader<'static>> {
+    // Safety: as above
+    let section = unsafe {
+        core::slice::from_raw_parts(EH_FRAME.gcc_except_table, EH_FRAME.gcc_except_table_len)
+    };
+    let offset = address.checked_sub(section.as_ptr() as usize)?;
+    Some(Reader::new(section.get(offset..)?))
+}
+
+/// Read a word of memory, if the address could hold one.
+pub(crate) fn read_word(address: usize) -> Option<usize> {
+    if address == 0 || !address.is_multiple_of(core::mem::size_of::<usize>()) {
+        return None;
+    }
+    // Safety: the caller only reads memory described by the call frame
+    // information, which a corrupted stack can still make fault
+    Some(unsafe { (address as *const usize).read() })
+}
+
+/// The addresses that encoded pointers can be relative to.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Bases {
+    pub data: usize,
+    pub function: usize,
+}
+
+/// A cursor over call frame information in memory.
+#[derive(Clone)]
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+    pub(crate) fn is_empty(&self) -> bool {
+        self.offset >= self.data.len()
+    }
+    /// The address of the next byte.
+    pub(crate) fn address(&self) -> usize {
+        self.data.as_ptr() as usize + self.offset
+    }
+    pub(crate) fn rest(&self) -> &'a [u8] {
+        self.data.get(self.offset..).unwrap_or(&[])
+    }
+    pub(crate) fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.offset..self.offset.checked_add(len)?)?;
+        self.offset += len;
+        Some(bytes)
+    }
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.bytes(N)?.try_into().ok()
+    }
+    pub(crate) fn u8(&mut self) -> Option<u8> {
+        Some(self.array::<1>()?[0])
+    }
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.array()?))
+    }
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.array()?))
+    }
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.array()?))
+    }
+    pub(crate) fn uleb128(&mut self) -> Option<u64> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+    }
+    pub(crate) fn sleb128(&mut self) -> Option<i64> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                return Some(value);
+            }
+        }
+    }
+    fn register(&mut self) -> Option<usize> {
+        Some(self.uleb128()? as usize)
+    }
+    /// Read a pointer in a `DW_EH_PE_*` encoding, which must not be omitted.
+    pub(crate) fn pointer(&mut self, encoding: u8, bases: Bases) -> Option<usize> {
+        if encoding == PE_OMIT {
+            return None;
+        }
+        let field = self.address();
+        if encoding & 0x70 == PE_ALIGNED {
+            let size = core::mem::size_of::<usize>();
+            let padding = field.next_multiple_of(size) - field;
+            self.bytes(padding)?;
+        }
+        let value = match encoding & 0x0f {
+            PE_ABSPTR | PE_UDATA8 => self.u64()? as usize,
+            PE_ULEB128 => self.uleb128()? as usize,
+            PE_UDATA2 => self.u16()? as usize,
+            PE_UDATA4 => self.u32()? as usize,
+            PE_SLEB128 => self.sleb128()? as usize,
+            PE_SDATA2 => self.u16()? as i16 as usize,
+            PE_SDATA4 => self.u32()? as i32 as usize,
+            PE_SDATA8 => self.u64()? as usize,
+            _ => return None,
+        };
+        let base = match encoding & 0x70 {
+            0 | PE_ALIGNED => 0,
+            PE_PCREL => field,
+            PE_DATAREL => bases.data,
+            PE_FUNCREL => bases.function,
+            _ => return None,
+        };
+        // a null pointer stays null whatever it is relative to
+        if value == 0 {
+            return Some(0);
+        }
+        let pointer = base.wrapping_add(value);
+        if encoding & PE_INDIRECT != 0 {
+            read_word(pointer)
+        } else {
+            Some(pointer)
+        }
+    }
+}
+
+/// A common information entry, shared by the FDEs that point to it.
+#[derive(Clone, Copy)]
+pub(crate) struct Cie<'a> {
+    pub code_alignment: u64,
+    pub data_alignment: i64,
+    /// The register holding the return address.
+    pub return_address: usize,
+    fde_encoding: u8,
+    lsda_encoding: u8,
+    /// The routine that decides what the frames do with an exception.
+    pub personality: Option<usize>,
+    /// Whether FDEs have augmentation data.
+    augmented: bool,
+    /// Whether the frames interrupted an instruction rather than calling a
+    /// function, so their `pc` is exact rather than a return address.
+    pub signal_frame: bool,
+    instructions: &'a [u8],
+}
+
+/// A frame description entry, covering one function.
+#[derive(Clone, Copy)]
+pub(crate) struct Fde<'a> {
+    pub cie: Cie<'a>,
+    pub start: usize,
+    pub end: usize,
+    /// The language-specific data for the personality routine, describing
+    /// the landing pads of the function.
+    pub lsda: Option<usize>,
+    instructions: &'a [u8],
+}
+
+/// An entry of `.eh_frame`, with its contents after the CIE id or pointer.
+enum Entry<'a> {
+    Cie(Reader<'a>),
+    Fde { cie: usize, contents: Reader<'a> },
+    /// The zero length entry that ends the section.
+    End,
+}
+
+/// Read the entry at the start of `reader`, moving past it.
+fn entry<'a>(reader: &mut Reader<'a>) -> Option<Entry<'a>> {
+    let length = match reader.u32()? {
+        0 => return Some(Entry::End),
+        0xffff_ffff => reader.u64()? as usize,
+        length => length as usize,
+    };
+    let mut contents = Reader::new(reader.bytes(length)?);
+    let id_field = contents.address();
+    let id = contents.u32()?;
+    Some(match id {
+        0 => Entry::Cie(contents),
+        pointer => Entry::Fde { cie: id_field.wrapping_sub(pointer as usize), contents },
+    })
+}
+
+fn parse_cie(mut reader: Reader<'_>) -> Option<Cie<'_>> {
+    let version = reader.u8()?;
+    if !matches!(version, 1 | 3) {
+        return None;
+    }
+    let augmentation_start = reader.offset;
+    while reader.u8()? != 0 {}
+    let augmentation = &reader.data[augmentation_start..reader.offset - 1];
+    if augmentation.starts_with(b"eh") {
+        reader.bytes(core::mem::size_of::<usize>())?;
+    }
+    let mut cie = Cie {
+        code_alignment: reader.uleb128()?,
+        data_alignment: reader.sleb128()?,
+        return_address: if version == 1 { reader.u8()? as usize } else { reader.register()? },
+        fde_encoding: PE_ABSPTR,
+        lsda_encoding: PE_OMIT,
+        personality: None,
+        augmented: augmentation.first() == Some(&b'z'),
+        signal_frame: false,
+        instructions: &[],
+    };
+    if cie.augmented {
+        let length = reader.uleb128()? as usize;
+        let mut data = Reader { data: reader.bytes(length)?, offset: 0 };
+        for &character in &augmentation[1..] {
+            match character {
+                b'R' => cie.fde_encoding = data.u8()?,
+                b'L' => cie.lsda_encoding = data.u8()?,
+                b'P' => {
+                    let encoding = data.u8()?;
+                    cie.personality = Some(data.pointer(encoding, Bases::default())?).filter(|&p| p != 0);
+                },
+                b'S' => cie.signal_frame = true,
+                // the remaining data cannot be interpreted, but its length is
+                // known
+                _ => break,
+            }
+        }
+    } else if !augmentation.is_empty() {
+        return None;
+    }
+    cie.instructions = reader.rest();
+    Some(cie)
+}
+
+fn parse_fde<'a>(cie: usize, mut reader: Reader<'a>) -> Option<Fde<'a>> {
+    let section = eh_frame();
+    let offset = cie.checked_sub(section.as_ptr() as usize)?;
+    let mut cie_reader = Reader::new(section.get(offset..)?);
+    let Entry::Cie(contents) = entry(&mut cie_reader)? else {
+        return None;
+    };
+    let cie = parse_cie(contents)?;
+    let start = reader.pointer(cie.fde_encoding, Bases::default())?;
+    // the range is a length, so only its format applies
+    let length = reader.pointer(cie.fde_encoding & 0x0f, Bases::default())?;
+    let mut lsda = None;
+    if cie.augmented {
+        let length = reader.uleb128()? as usize;
+        let mut data = Reader::new(reader.bytes(length)?);
+        if cie.lsda_encoding != PE_OMIT {
+            lsda = Some(data.pointer(cie.lsda_encoding, Bases::default())?).filter(|&lsda| lsda != 0);
+        }
+    }
+    Some(Fde {
+        cie,
+        start,
+        end: start.checked_add(length)?,
+        lsda,
+        instructions: reader.rest(),
+    })
+}
+
+/// Find the FDE of the function containing `pc`.
+pub(crate) fn find(pc: usize) -> Option<Fde<'static>> {
+    find_indexed(pc).or_else(|| find_scanning(pc)).filter(|fde| (fde.start..fde.end).contains(&pc))
+}
+/// Search the table of `.eh_frame_hdr`, which the linker sorts by address.
+fn find_indexed(pc: usize) -> Option<Fde<'static>> {
+    let header = eh_frame_hdr();
+    let bases = Bases { data: header.as_ptr() as usize, function: 0 };
+    let mut reader = Reader::new(header);
+    if reader.u8()? != 1 {
+        return None;
+    }
+    let [frame_encoding, count_encoding, table_encoding] = reader.array()?;
+    reader.pointer(frame_encoding, bases)?;
+    let count = reader.pointer(count_encoding, bases)?;
+    // the table can only be searched with fixed size entries
+    if table_encoding != PE_DATAREL | PE_SDATA4 {
+        return None;
+    }
+    let table = reader.rest().get(..count.checked_mul(8)?)?;
+    let entry = |index: usize| {
+        let mut reader = Reader::new(&table[index * 8..]);
+        Some((reader.pointer(table_encoding, bases)?, reader.pointer(table_encoding, bases)?))
+    };
+    // the last entry starting at or before `pc`
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if entry(middle)?.0 <= pc {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    let (_, fde) = entry(low.checked_sub(1)?)?;
+    fde_at(fde)
+}
+fn find_scanning(pc: usize) -> Option<Fde<'static>> {
+    let mut reader = Reader::new(eh_frame());
+    while !reader.is_empty() {
+        match entry(&mut reader)? {
+            Entry::End => break,
+            Entry::Cie(_) => {},
+            Entry::Fde { cie, contents } => {
+                let Some(fde) = parse_fde(cie, contents) else {
+                    continue;
+                };
+                if (fde.start..fde.end).contains(&pc) {
+                    return Some(fde);
+                }
+            },
+        }
+    }
+    None
+}
+fn fde_at(address: usize) -> Option<Fde<'static>> {
+    let section = eh_frame();
+    let offset = address.checked_sub(section.as_ptr() as usize)?;
+    match entry(&mut Reader::new(section.get(offset..)?))? {
+        Entry::Fde { cie, contents } => parse_fde(cie, contents),
+        _ => None,
+    }
+}
+
+/// How to find the canonical frame address, the value of the stack pointer
+/// at the call into the function.
+#[derive(Clone, Copy)]
+pub(crate) enum Cfa<'a> {
+    RegisterOffset { register: usize, offset: i64 },
+    Expression(&'a [u8]),
+}
+
+/// How to recover a register of the caller.
+#[derive(Clone, Copy)]
+pub(crate) enum Rule<'a> {
+    Undefined,
+    SameValue,
+    /// Saved at an offset from the CFA.
+    Offset(i64),
+    /// The CFA plus an offset.
+    ValOffset(i64),
+    /// Held in another register.
+    Register(usize),
+    /// Saved at the address computed by the expression.
+    Expression(&'a [u8]),
+    /// The value computed by the expression.
+    ValExpression(&'a [u8]),
+}
+
+/// The rules for recovering the caller at one address in a function.
+#[derive(Clone, Copy)]
+pub(crate) struct Row<'a> {
+    pub cfa: Cfa<'a>,
+    pub registers: [Rule<'a>; REGISTERS],
+}
+
+impl<'a> Fde<'a> {
+    /// The rules at `pc`, by running the instructions of the CIE and then
+    /// those of the FDE up to `pc`.
+    pub(crate) fn row(&self, pc: usize) -> Option<Row<'a>> {
+        let mut row = Row {
+            cfa: Cfa::RegisterOffset { register: SP, offset: 0 },
+            // registers without a rule are assumed to be preserved
+            registers: [Rule::SameValue; REGISTERS],
+        };
+        let mut state = State { location: self.start, stack: [None; STATE_STACK], depth: 0 };
+        self.execute(self.cie.instructions, &mut row, None, &mut state, usize::MAX)?;
+        let initial = row;
+        self.execute(self.instructions, &mut row, Some(&initial), &mut state, pc)?;
+        Some(row)
+    }
+
+    /// Run call frame instructions on `row` until the location passes `pc`.
+    fn execute(
+        &self,
+        instructions: &'a [u8],
+        row: &mut Row<'a>,
+        initial: Option<&Row<'a>>,
+        state: &mut State<'a>,
+        pc: usize,
+    ) -> Option<()> {
+        let cie = &self.cie;
+        let mut reader = Reader::new(instructions);
+        let offset = |factored: i64| factored.wrapping_mul(cie.data_alignment);
+        let set = |row: &mut Row<'a>, register: usize, rule| {
+            if let Some(slot) = row.registers.get_mut(register) {
+                *slot = rule;
+            }
+        };
+        let restore = |row: &mut Row<'a>, register: usize| {
+            let rule = initial.and_then(|initial| initial.registers.get(register).copied());
+            if let (Some(slot), Some(rule)) = (row.registers.get_mut(register), rule) {
+                *slot = rule;
+            }
+        };
+        while !reader.is_empty() {
+            let opcode = reader.u8()?;
+            let operand = (opcode & 0x3f) as usize;
+            let advance = match opcode >> 6 {
+                // DW_CFA_advance_loc
+                1 => Some(operand as u64),
+                // DW_CFA_offset
+                2 => {
+                    let factored = reader.uleb128()? as i64;
+                    set(row, operand, Rule::Offset(offset(factored)));
+                    None
+                },
+                // DW_CFA_restore
+                3 => {
+                    restore(row, operand);
+                    None
+                },
+                _ => match opcode {
+                    // DW_CFA_nop
+                    0x00 => None,
+                    // DW_CFA_set_loc
+                    0x01 => {
+                        let location = reader.pointer(cie.fde_encoding, Bases::default())?;
+                        if location > pc {
+                            return Some(());
+                        }
+                        state.location = location;
+                        None
+                    },
+                    // DW_CFA_advance_loc1, 2 and 4
+                    0x02 => Some(reader.u8()? as u64),
+                    0x03 => Some(reader.u16()? as u64),
+                    0x04 => Some(reader.u32()? as u64),
+                    // DW_CFA_offset_extended
+                    0x05 => {
+                        let register = reader.register()?;
+                        let factored = reader.uleb128()? as i64;
+                        set(row, register, Rule::Offset(offset(factored)));
+                        None
+                    },
+                    // DW_CFA_restore_extended
+                    0x06 => {
+                        restore(row, reader.register()?);
+                        None
+                    },
+                    // DW_CFA_undefined
+                    0x07 => {
+                        set(row, reader.register()?, Rule::Undefined);
+                        None
+                    },
+                    // DW_CFA_same_value
+                    0x08 => {
+                        set(row, reader.register()?, Rule::SameValue);
+                        None
+                    },
+                    // DW_CFA_register
+                    0x09 => {
+                        let register = reader.register()?;
+                        let source = reader.register()?;
+                        set(row, register, Rule::Register(source));
+                        None
+                    },
+                    // DW_CFA_remember_state
+                    0x0a => {
+                        *state.stack.get_mut(state.depth)? = Some((row.cfa, row.registers));
+                        state.depth += 1;
+                        None
+                    },
+                    // DW_CFA_restore_state
+                    0x0b => {
+                        state.depth = state.depth.checked_sub(1)?;
+                        (row.cfa, row.registers) = state.stack[state.depth].take()?;
+                        None
+                    },
+                    // DW_CFA_def_cfa
+                    0x0c => {
+                        let register = reader.register()?;
+                        let offset = reader.uleb128()? as i64;
+                        row.cfa = Cfa::RegisterOffset { register, offset };
+                        None
+                    },
+                    // DW_CFA_def_cfa_register
+                    0x0d => {
+                        let register = reader.register()?;
+                        let Cfa::RegisterOffset { offset, .. } = row.cfa else {
+                            return None;
+                        };
+                        row.cfa = Cfa::RegisterOffset { register, offset };
+                        None
+                    },
+                    // DW_CFA_def_cfa_offset
+                    0x0e => {
+                        let offset = reader.uleb128()? as i64;
+                        let Cfa::RegisterOffset { register, .. } = row.cfa else {
+                            return None;
+                        };
+                        row.cfa = Cfa::RegisterOffset { register, offset };
+                        None
+                    },
+                    // DW_CFA_def_cfa_expression
+                    0x0f => {
+                        let length = reader.uleb128()? as usize;
+                        row.cfa = Cfa::Expression(reader.bytes(length)?);
+                        None
+                    },
+                    // DW_CFA_expression
+                    0x10 => {
+                        let register = reader.register()?;
+                        let length = reader.uleb128()? as usize;
+                        set(row, register, Rule::Expression(reader.bytes(length)?));
+                        None
+                    },
+                    // DW_CFA_offset_extended_sf
+                    0x11 => {
+                        let register = reader.register()?;
+                        let factored = reader.sleb128()?;
+                        set(row, register, Rule::Offset(offset(factored)));
+                        None
+                    },
+                    // DW_CFA_def_cfa_sf
+                    0x12 => {
+                        let register = reader.register()?;
+                        let factored = reader.sleb128()?;
+                        row.cfa = Cfa::RegisterOffset { register, offset: offset(factored) };
+                        None
+                    },
+                    // DW_CFA_def_cfa_offset_sf
+                    0x13 => {
+                        let factored = reader.sleb128()?;
+                        let Cfa::RegisterOffset { register, .. } = row.cfa else {
+                            return None;
+                        };
+                        row.cfa = Cfa::RegisterOffset { register, offset: offset(factored) };
+                        None
+                    },
+                    // DW_CFA_val_offset
+                    0x14 => {
+                        let register = reader.register()?;
+                        let factored = reader.uleb128()? as i64;
+                        set(row, register, Rule::ValOffset(offset(factored)));
+                        None
+                    },
+                    // DW_CFA_val_offset_sf
+                    0x15 => {
+                        let register = reader.register()?;
+                        let factored = reader.sleb128()?;
+                        set(row, register, Rule::ValOffset(offset(factored)));
+                        None
+                    },
+                    // DW_CFA_val_expression
+                    0x16 => {
+                        let register = reader.register()?;
+                        let length = reader.uleb128()? as usize;
+                        set(row, register, Rule::ValExpression(reader.bytes(length)?));
+                        None
+                    },
+                    // DW_CFA_GNU_args_size, which only matters for landing
+                    // pads that adjust the stack
+                    0x2e => {
+                        reader.uleb128()?;
+                        None
+                    },
+                    // DW_CFA_GNU_negative_offset_extended
+                    0x2f => {
+                        let register = reader.register()?;
+                        let factored = reader.uleb128()? as i64;
+                        set(row, register, Rule::Offset(offset(factored.wrapping_neg())));
+                        None
+                    },
+                    _ => return None,
+                },
+            };
+            if let Some(delta) = advance {
+                let location = state.location.wrapping_add((delta * cie.code_alignment) as usize);
+                if location > pc {
+                    return Some(());
+                }
+                state.location = location;
+            }
+        }
+        Some(())
+    }
+}
+
+/// The state of running call frame instructions.
+struct State<'a> {
+    /// The address the current row applies from.
+    location: usize,
+    /// Rows saved by `DW_CFA_remember_state`.
+    stack: [Option<(Cfa<'a>, [Rule<'a>; REGISTERS])>; STATE_STACK],
+    depth: usize,
+}
+
+/// The values of a DWARF expression being evaluated.
+struct Stack {
+    values: [usize; EXPRESSION_STACK],
+    depth: usize,
+}
+impl Stack {
+    fn push(&mut self, value: usize) -> Option<()> {
+        *self.values.get_mut(self.depth)? = value;
+        self.depth += 1;
+        Some(())
+    }
+    fn pop(&mut self) -> Option<usize> {
+        self.depth = self.depth.checked_sub(1)?;
+        Some(self.values[self.depth])
+    }
+    /// The value `n` from the top.
+    fn peek(&self, n: usize) -> Option<usize> {
+        Some(self.values[self.depth.checked_sub(n + 1)?])
+    }
+}
+
+/// Evaluate a DWARF expression with the general-purpose registers of a
+/// frame, starting with `initial` on the stack if given.
+///
+/// Only the operations that describe frames are supported.
+pub(crate) fn evaluate(expression: &[u8], registers: &[usize; REGISTERS], initial: Option<usize>) -> Option<usize> {
+    let mut stack = Stack { values: [0; EXPRESSION_STACK], depth: 0 };
+    if let Some(initial) = initial {
+        stack.push(initial)?;
+    }
+    let mut reader = Reader::new(expression);
+    while !reader.is_empty() {
+        let opcode = reader.u8()?;
+        let value = match opcode {
+            // DW_OP_addr
+            0x03 => reader.u64()? as usize,
+            // DW_OP_deref
+            0x06 => read_word(stack.pop()?)?,
+            // DW_OP_const1u to DW_OP_const8s
+            0x08 => reader.u8()? as usize,
+            0x09 => reader.u8()? as i8 as usize,
+            0x0a => reader.u16()? as usize,
+            0x0b => reader.u16()? as i16 as usize,
+            0x0c => reader.u32()? as usize,
+            0x0d => reader.u32()? as i32 as usize,
+            0x0e | 0x0f => reader.u64()? as usize,
+            // DW_OP_constu and DW_OP_consts
+            0x10 => reader.uleb128()? as usize,
+            0x11 => reader.sleb128()? as usize,
+            // DW_OP_dup
+            0x12 => stack.peek(0)?,
+            // DW_OP_drop
+            0x13 => {
+                stack.pop()?;
+                continue;
+            },
+            // DW_OP_over
+            0x14 => stack.peek(1)?,
+            // DW_OP_swap
+            0x16 => {
+                let (top, second) = (stack.pop()?, stack.pop()?);
+                stack.push(top)?;
+                second
+            },
+            // DW_OP_and, minus, or, plus, shl, shr and xor
+            0x1a | 0x1c | 0x21 | 0x22 | 0x24 | 0x25 | 0x27 => {
+                let (b, a) = (stack.pop()?, stack.pop()?);
+                match opcode {
+                    0x1a => a & b,
+                    0x1c => a.wrapping_sub(b),
+                    0x21 => a | b,
+                    0x22 => a.wrapping_add(b),
+                    0x24 => a.wrapping_shl(b as u32),
+                    0x25 => a.wrapping_shr(b as u32),
+                    _ => a ^ b,
+                }
+            },
+            // DW_OP_plus_uconst
+            0x23 => stack.pop()?.wrapping_add(reader.uleb128()? as usize),
+            // DW_OP_lit0 to DW_OP_lit31
+            0x30..=0x4f => (opcode - 0x30) as usize,
+            // DW_OP_breg0 to DW_OP_breg31
+            0x70..=0x8f => {
+                let offset = reader.sleb128()? as usize;
+                registers[(opcode - 0x70) as usize].wrapping_add(offset)
+            },
+            // DW_OP_bregx
+            0x92 => {
+                let register = reader.register()?;
+                let offset = reader.sleb128()? as usize;
+                registers.get(register)?.wrapping_add(offset)
+            },
+            // DW_OP_nop
+            0x96 => continue,
+            _ => return None,
+        };
+        stack.push(value)?;
+    }
+    stack.pop()
+}
@@ -1,6 +1,99 @@
 .section .rodata, "a", %progbits
 
+// The call frame information sections and the language-specific data of
+// landing pads, as absolute addresses and lengths
+.balign 8
 .global EH_FRAME
 EH_FRAME:
     .dword _eh_frame
     .dword _eh_frame_len
+    .dword _eh_frame_hdr
+    .dword _eh_frame_hdr_len
+    .dword _gcc_except_table
+    .dword _gcc_except_table_len
+
+// The space reserved for the symbol table
+.balign 8
+.global SYMBOLS
+SYMBOLS:
+    .dword _symbols
+    .dword _symbols_size
+
+.section .text, "ax", %progbits
+
+// Save the registers of the caller in the `Registers` at a0, with its return
+// address as the pc.
+.global _unwind_capture
+_unwind_capture:
+    sd zero, 0 * 8(a0)
+    sd x1, 1 * 8(a0)
+    sd x2, 2 * 8(a0)
+    sd x3, 3 * 8(a0)
+    sd x4, 4 * 8(a0)
+    sd x5, 5 * 8(a0)
+    sd x6, 6 * 8(a0)
+    sd x7, 7 * 8(a0)
+    sd x8, 8 * 8(a0)
+    sd x9, 9 * 8(a0)
+    sd x10, 10 * 8(a0)
+    sd x11, 11 * 8(a0)
+    sd x12, 12 * 8(a0)
+    sd x13, 13 * 8(a0)
+    sd x14, 14 * 8(a0)
+    sd x15, 15 * 8(a0)
+    sd x16, 16 * 8(a0)
+    sd x17, 17 * 8(a0)
+    sd x18, 18 * 8(a0)
+    sd x19, 19 * 8(a0)
+    sd x20, 20 * 8(a0)
+    sd x21, 21 * 8(a0)
+    sd x22, 22 * 8(a0)
+    sd x23, 23 * 8(a0)
+    sd x24, 24 * 8(a0)
+    sd x25, 25 * 8(a0)
+    sd x26, 26 * 8(a0)
+    sd x27, 27 * 8(a0)
+    sd x28, 28 * 8(a0)
+    sd x29, 29 * 8(a0)
+    sd x30, 30 * 8(a0)
+    sd x31, 31 * 8(a0)
+    sd ra, 32 * 8(a0)
+    ret
+
+// Load the registers from the `Registers` at a0 and jump to its pc, for
+// entering a landing pad. The landing pad is reached as if from a call, which
+// clobbers ra, so ra holds the pc.
+.global _unwind_install
+_unwind_install:
+    ld ra, 32 * 8(a0)
+    ld x2, 2 * 8(a0)
+    ld x3, 3 * 8(a0)
+    ld x4, 4 * 8(a0)
+    ld x5, 5 * 8(a0)
+    ld x6, 6 * 8(a0)
+    ld x7, 7 * 8(a0)
+    ld x8, 8 * 8(a0)
+    ld x9, 9 * 8(a0)
+    ld x11, 11 * 8(a0)
+    ld x12, 12 * 8(a0)
+    ld x13, 13 * 8(a0)
+    ld x14, 14 * 8(a0)
+    ld x15, 15 * 8(a0)
+    ld x16, 16 * 8(a0)
+    ld x17, 17 * 8(a0)
+    ld x18, 18 * 8(a0)
+    ld x19, 19 * 8(a0)
+    ld x20, 20 * 8(a0)
+    ld x21, 21 * 8(a0)
+    ld x22, 22 * 8(a0)
+    ld x23, 23 * 8(a0)
+    ld x24, 24 * 8(a0)
+    ld x25, 25 * 8(a0)
+    ld x26, 26 * 8(a0)
+    ld x27, 27 * 8(a0)
+    ld x28, 28 * 8(a0)
+    ld x29, 29 * 8(a0)
+    ld x30, 30 * 8(a0)
+    ld x31, 31 * 8(a0)
+    ld a0, 10 * 8(a0)
+    jr ra
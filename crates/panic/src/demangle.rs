@@ -0,0 +1,545 @@
+//! Demangling Rust symbol names, in both the legacy and the v0 schemes,
+//! without allocating.
+//!
+//! Hashes and crate disambiguators are left out, as they are by the alternate
+//! format of `rustc-demangle`. Names that fail to parse print as they are.
+
+use core::fmt::{self, Write};
+
+/// How deeply v0 names can nest, including through backreferences.
+const MAX_DEPTH: usize = 64;
+
+/// A symbol name that prints demangled.
+pub(crate) struct Demangle<'a>(pub &'a str);
+impl fmt::Display for Demangle<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // parse first so that a malformed name is not printed in part
+        if demangle(self.0, &mut Discard).is_ok() {
+            demangle(self.0, f)
+        } else {
+            f.write_str(self.0)
+        }
+    }
+}
+
+/// Output for checking that a name parses.
+struct Discard;
+impl Write for Discard {
+    fn write_str(&mut self, _: &str) -> fmt::Result {
+        Ok(())
+    }
+}
+
+fn demangle(name: &str, out: &mut impl Write) -> fmt::Result {
+    if let Some(rest) = name.strip_prefix("_R") {
+        // anything after a `.` was added by LLVM, such as `.llvm.1234`
+        let rest = rest.split('.').next().unwrap_or(rest);
+        V0 { bytes: rest.as_bytes(), position: 0, depth: 0, printing: true, out }.symbol()
+    } else if let Some(rest) = name.strip_prefix("_ZN") {
+        legacy(rest, out)
+    } else {
+        out.write_str(name)
+    }
+}
+
+/// Print a legacy name, made of length-prefixed identifiers ending with `E`,
+/// or with nothing if the symbol table stripped its hash.
+fn legacy(mut rest: &str, out: &mut impl Write) -> fmt::Result {
+    let is_hash = |ident: &str| {
+        ident.len() == 17 && ident.starts_with('h') && ident[1..].bytes().all(|b| b.is_ascii_hexdigit())
+    };
+    let mut first = true;
+    while !rest.is_empty() && !rest.starts_with('E') {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let len: usize = rest[..digits].parse().map_err(|_| fmt::Error)?;
+        let ident = rest.get(digits..digits + len).ok_or(fmt::Error)?;
+        rest = &rest[digits + len..];
+        if rest.starts_with('E') && is_hash(ident) {
+            break;
+        }
+        if !first {
+            out.write_str("::")?;
+        }
+        first = false;
+        legacy_ident(ident, out)?;
+    }
+    Ok(())
+}
+/// Print an identifier, replacing the `$`-escapes and `..` of legacy names.
+fn legacy_ident(mut ident: &str, out: &mut impl Write) -> fmt::Result {
+    // identifiers cannot start with `$`, so escapes at the start are prefixed
+    if ident.starts_with("_$") {
+        ident = &ident[1..];
+    }
+    while !ident.is_empty() {
+        if let Some(rest) = ident.strip_prefix("..") {
+            out.write_str("::")?;
+            ident = rest;
+        } else if let Some(rest) = ident.strip_prefix('$') {
+            let end = rest.find('$').ok_or(fmt::Error)?;
+            let character = match &rest[..end] {
+                "SP" => '@',
+                "BP" => '*',
+                "RF" => '&',
+                "LT" => '<',
+                "GT" => '>',
+                "LP" => '(',
+                "RP" => ')',
+                "C" => ',',
+                escape => {
+                    let hex = escape.strip_prefix('u').ok_or(fmt::Error)?;
+                    let code = u32::from_str_radix(hex, 16).map_err(|_| fmt::Error)?;
+                    char::from_u32(code).ok_or(fmt::Error)?
+                },
+            };
+            out.write_char(character)?;
+            ident = &rest[end + 1..];
+        } else {
+            // a lone `.` is kept
+            let end = ident[1..].find(['$', '.']).map_or(ident.len(), |end| end + 1);
+            out.write_str(&ident[..end])?;
+            ident = &ident[end..];
+        }
+    }
+    Ok(())
+}
+
+/// A printer for the grammar of v0 names, which prints as it parses.
+///
+/// Backreferences are followed by parsing from their target again, and the
+/// parts that are not shown are parsed with printing turned off.
+struct V0<'a, 'w, W> {
+    /// The name after `_R`, which backreferences are relative to.
+    bytes: &'a [u8],
+    position: usize,
+    depth: usize,
+    printing: bool,
+    out: &'w mut W,
+}
+impl<'a, W: Write> V0<'a, '_, W> {
+    fn symbol(&mut self) -> fmt::Result {
+        // the encoding version, if any
+        while self.peek().is_some_and(|b| b.is_ascii_digit()) {
+            self.position += 1;
+        }
+        self.path(true)?;
+        // the crate that instantiated a generic function
+        if self.position < self.bytes.len() {
+            self.hidden(|this| this.path(false))?;
+        }
+        if self.position == self.bytes.len() {
+            Ok(())
+        } else {
+            Err(fmt::Error)
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).copied()
+    }
+    fn next(&mut self) -> Result<u8, fmt::Error> {
+        let byte = self.peek().ok_or(fmt::Error)?;
+        self.position += 1;
+        Ok(byte)
+    }
+    fn eat(&mut self, byte: u8) -> bool {
+        let matches = self.peek() == Some(byte);
+        if matches {
+            self.position += 1;
+        }
+        matches
+    }
+    fn write(&mut self, s: &str) -> fmt::Result {
+        if self.printing {
+            self.out.write_str(s)
+        } else {
+            Ok(())
+        }
+    }
+    fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> fmt::Result {
+        if self.printing {
+            self.out.write_fmt(args)
+        } else {
+            Ok(())
+        }
+    }
+    /// Parse something without printing it.
+    fn hidden(&mut self, f: impl FnOnce(&mut Self) -> fmt::Result) -> fmt::Result {
+        let printing = core::mem::replace(&mut self.printing, false);
+        let result = f(self);
+        self.printing = printing;
+        result
+    }
+    /// Parse something nested, failing if it is nested too deeply.
+    fn nested(&mut self, f: impl FnOnce(&mut Self) -> fmt::Result) -> fmt::Result {
+        if self.depth == MAX_DEPTH {
+            return Err(fmt::Error);
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+    /// Print what the backreference after a `B` points to.
+    fn backref(&mut self, f: impl FnOnce(&mut Self) -> fmt::Result) -> fmt::Result {
+        let start = self.position - 1;
+        let target = self.base62()? as usize;
+        if target >= start {
+            return Err(fmt::Error);
+        }
+        // the target was already checked when it was parsed
+        if !self.printing {
+            return Ok(());
+        }
+        let position = core::mem::replace(&mut self.position, target);
+        let result = self.nested(f);
+        self.position = position;
+        result
+    }
+
+    /// A base-62 number ending with `_`, where `_` alone is 0.
+    fn base62(&mut self) -> Result<u64, fmt::Error> {
+        if self.eat(b'_') {
+            return Ok(0);
+        }
+        let mut value: u64 = 0;
+        loop {
+            let digit = match self.next()? {
+                digit @ b'0'..=b'9' => digit - b'0',
+                digit @ b'a'..=b'z' => digit - b'a' + 10,
+                digit @ b'A'..=b'Z' => digit - b'A' + 36,
+                b'_' => return value.checked_add(1).ok_or(fmt::Error),
+                _ => return Err(fmt::Error),
+            };
+            value = value.checked_mul(62).and_then(|value| value.checked_add(digit as u64)).ok_or(fmt::Error)?;
+        }
+    }
+    /// A base-62 number after `tag`, or 0 without the tag.
+    fn optional_base62(&mut self, tag: u8) -> Result<u64, fmt::Error> {
+        if self.eat(tag) {
+            self.base62()?.checked_add(1).ok_or(fmt::Error)
+        } else {
+            Ok(0)
+        }
+    }
+    fn decimal(&mut self) -> Result<usize, fmt::Error> {
+        // a number starting with 0 is just 0, so `00` is two of them
+        if self.eat(b'0') {
+            return Ok(0);
+        }
+        let start = self.position;
+        while self.peek().is_some_and(|b| b.is_ascii_digit()) {
+            self.position += 1;
+        }
+        let digits = core::str::from_utf8(&self.bytes[start..self.position]).map_err(|_| fmt::Error)?;
+        digits.parse().map_err(|_| fmt::Error)
+    }
+    /// An identifier, with whether it is punycode.
+    fn ident(&mut self) -> Result<(&'a str, bool), fmt::Error> {
+        let punycode = self.eat(b'u');
+        let len = self.decimal()?;
+        self.eat(b'_');
+        let end = self.position.checked_add(len).ok_or(fmt::Error)?;
+        let bytes = self.bytes;
+        let bytes = bytes.get(self.position..end).ok_or(fmt::Error)?;
+        self.position = end;
+        let ident = core::str::from_utf8(bytes).map_err(|_| fmt::Error)?;
+        Ok((ident, punycode))
+    }
+    fn write_ident(&mut self, (ident, punycode): (&'a str, bool)) -> fmt::Result {
+        if punycode {
+            write!(self, "punycode{{{ident}}}")
+        } else {
+            self.write(ident)
+        }
+    }
+
+    /// A path, which takes `::` before generic arguments in expressions.
+    fn path(&mut self, in_value: bool) -> fmt::Result {
+        self.nested(|this| {
+            let tag = this.next()?;
+            match tag {
+                b'C' => {
+                    this.optional_base62(b's')?;
+                    let ident = this.ident()?;
+                    this.write_ident(ident)
+                },
+                b'N' => {
+                    let namespace = this.next()?;
+                    if !namespace.is_ascii_alphabetic() {
+                        return Err(fmt::Error);
+                    }
+                    this.path(in_value)?;
+                    let disambiguator = this.optional_base62(b's')?;
+                    let ident = this.ident()?;
+                    if namespace.is_ascii_uppercase() {
+                        match namespace {
+                            b'C' => this.write("::{closure")?,
+                            b'S' => this.write("::{shim")?,
+                            other => write!(this, "::{{{}", other as char)?,
+                        }
+                        if !ident.0.is_empty() {
+                            this.write(":")?;
+                            this.write_ident(ident)?;
+                        }
+                        write!(this, "#{disambiguator}}}")
+                    } else if !ident.0.is_empty() {
+                        this.write("::")?;
+                        this.write_ident(ident)
+                    } else {
+                        Ok(())
+                    }
+                },
+                b'M' | b'X' | b'Y' => {
+                    if tag != b'Y' {
+                        this.optional_base62(b's')?;
+                        this.hidden(|this| this.path(false))?;
+                    }
+                    this.write("<")?;
+                    this.ty()?;
+                    if tag != b'M' {
+                        this.write(" as ")?;
+                        this.path(false)?;
+                    }
+                    this.write(">")
+                },
+                b'I' => {
+                    this.path(in_value)?;
+                    this.write(if in_value { "::<" } else { "<" })?;
+                    this.list(b'E', ", ", Self::generic_arg)?;
+                    this.write(">")
+                },
+                b'B' => this.backref(|this| this.path(in_value)),
+                _ => Err(fmt::Error),
+            }
+        })
+    }
+    /// Items up to `end`, separated by `separator`, returning how many.
+    fn list(&mut self, end: u8, separator: &str, mut item: impl FnMut(&mut Self) -> fmt::Result) -> Result<usize, fmt::Error> {
+        let mut count = 0;
+        while !self.eat(end) {
+            if count > 0 {
+                self.write(separator)?;
+            }
+            item(self)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+    fn generic_arg(&mut self) -> fmt::Result {
+        if self.eat(b'L') {
+            self.base62()?;
+            self.write("'_")
+        } else if self.eat(b'K') {
+            self.constant()
+        } else {
+            self.ty()
+        }
+    }
+
+    fn ty(&mut self) -> fmt::Result {
+        self.nested(|this| {
+            let tag = this.next()?;
+            if let Some(name) = basic_type(tag) {
+                return this.write(name);
+            }
+            match tag {
+                b'R' | b'Q' => {
+                    this.write("&")?;
+                    if this.eat(b'L') {
+                        this.base62()?;
+                    }
+                    if tag == b'Q' {
+                        this.write("mut ")?;
+                    }
+                    this.ty()
+                },
+                b'P' => {
+                    this.write("*const ")?;
+                    this.ty()
+                },
+                b'O' => {
+                    this.write("*mut ")?;
+                    this.ty()
+                },
+                b'A' => {
+                    this.write("[")?;
+                    this.ty()?;
+                    this.write("; ")?;
+                    this.constant()?;
+                    this.write("]")
+                },
+                b'S' => {
+                    this.write("[")?;
+                    this.ty()?;
+                    this.write("]")
+                },
+                b'T' => {
+                    this.write("(")?;
+                    let count = this.list(b'E', ", ", Self::ty)?;
+                    this.write(if count == 1 { ",)" } else { ")" })
+                },
+                b'F' => this.function(),
+                b'D' => {
+                    this.write("dyn ")?;
+                    this.optional_base62(b'G')?;
+                    this.list(b'E', " + ", Self::dyn_trait)?;
+                    // the lifetime bound
+                    if !this.eat(b'L') {
+                        return Err(fmt::Error);
+                    }
+                    this.base62().map(drop)
+                },
+                b'B' => this.backref(Self::ty),
+                _ => {
+                    this.position -= 1;
+                    this.path(false)
+                },
+            }
+        })
+    }
+    fn function(&mut self) -> fmt::Result {
+        self.optional_base62(b'G')?;
+        if self.eat(b'U') {
+            self.write("unsafe ")?;
+        }
+        if self.eat(b'K') {
+            if self.eat(b'C') {
+                self.write("extern \"C\" ")?;
+            } else {
+                let (abi, _) = self.ident()?;
+                self.write("extern \"")?;
+                // `-` is mangled as `_`
+                for (i, part) in abi.split('_').enumerate() {
+                    if i > 0 {
+                        self.write("-")?;
+                    }
+                    self.write(part)?;
+                }
+                self.write("\" ")?;
+            }
+        }
+        self.write("fn(")?;
+        self.list(b'E', ", ", Self::ty)?;
+        self.write(")")?;
+        if self.eat(b'u') {
+            Ok(())
+        } else {
+            self.write(" -> ")?;
+            self.ty()
+        }
+    }
+    fn dyn_trait(&mut self) -> fmt::Result {
+        let mut open = self.trait_path()?;
+        while self.eat(b'p') {
+            self.write(if open { ", " } else { "<" })?;
+            open = true;
+            let ident = self.ident()?;
+            self.write_ident(ident)?;
+            self.write(" = ")?;
+            self.ty()?;
+        }
+        if open {
+            self.write(">")?;
+        }
+        Ok(())
+    }
+    /// The path of a trait, leaving its generic arguments open for the
+    /// associated types that follow, and returning whether it did.
+    fn trait_path(&mut self) -> Result<bool, fmt::Error> {
+        match self.peek() {
+            Some(b'I') => {
+                self.position += 1;
+                self.path(false)?;
+                self.write("<")?;
+                self.list(b'E', ", ", Self::generic_arg)?;
+                Ok(true)
+            },
+            Some(b'B') if self.printing => {
+                self.position += 1;
+                let mut open = false;
+                self.backref(|this| {
+                    open = this.trait_path()?;
+                    Ok(())
+                })?;
+                Ok(open)
+            },
+            _ => self.path(false).map(|_| false),
+        }
+    }
+
+    /// A constant generic argument, of an integer, `bool` or `char` type.
+    fn constant(&mut self) -> fmt::Result {
+        self.nested(|this| {
+            let tag = this.next()?;
+            match tag {
+                b'p' => this.write("_"),
+                b'B' => this.backref(Self::constant),
+                b'a' | b'h' | b'i' | b'j' | b'l' | b'm' | b'n' | b'o' | b's' | b't' | b'x' | b'y' => {
+                    if this.eat(b'n') {
+                        this.write("-")?;
+                    }
+                    match this.hex()? {
+                        Ok(value) => write!(this, "{value}"),
+                        Err(digits) => write!(this, "0x{digits}"),
+                    }
+                },
+                b'b' => match this.hex()? {
+                    Ok(0) => this.write("false"),
+                    Ok(1) => this.write("true"),
+                    _ => Err(fmt::Error),
+                },
+                b'c' => {
+                    let value = this.hex()?.map_err(|_| fmt::Error)?;
+                    let character = u32::try_from(value).ok().and_then(char::from_u32).ok_or(fmt::Error)?;
+                    write!(this, "'{}'", character.escape_debug())
+                },
+                _ => Err(fmt::Error),
+            }
+        })
+    }
+    /// Hex digits ending with `_`, as a value or as the digits if too long.
+    fn hex(&mut self) -> Result<Result<u128, &'a str>, fmt::Error> {
+        let start = self.position;
+        while self.peek().is_some_and(|b| b.is_ascii_hexdigit()) {
+            self.position += 1;
+        }
+        let bytes = self.bytes;
+        let digits = core::str::from_utf8(&bytes[start..self.position]).map_err(|_| fmt::Error)?;
+        if !self.eat(b'_') {
+            return Err(fmt::Error);
+        }
+        if digits.is_empty() {
+            return Ok(Ok(0));
+        }
+        Ok(u128::from_str_radix(digits, 16).map_err(|_| digits))
+    }
+}
+
+/// The name of a v0 basic type.
+fn basic_type(tag: u8) -> Option<&'static str> {
+    Some(match tag {
+        b'a' => "i8",
+        b'b' => "bool",
+        b'c' => "char",
+        b'd' => "f64",
+        b'e' => "str",
+        b'f' => "f32",
+        b'h' => "u8",
+        b'i' => "isize",
+        b'j' => "usize",
+        b'l' => "i32",
+        b'm' => "u32",
+        b'n' => "i128",
+        b'o' => "u128",
+        b's' => "i16",
+        b't' => "u16",
+        b'u' => "()",
+        b'v' => "...",
+        b'x' => "i64",
+        b'y' => "u64",
+        b'z' => "!",
+        b'p' => "_",
+        _ => return None,
+    })
+}
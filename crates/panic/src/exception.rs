@@ -0,0 +1,157 @@
+//! Raising exceptions, as in the base ABI of the Itanium C++ ABI that LLVM
+//! generates landing pads for.
+//!
+//! An exception is raised in two phases. The search phase walks up the stack
+//! asking the personality routine of each frame whether it handles the
+//! exception, without changing anything. Only if a frame does, the cleanup
+//! phase walks up to it again, entering the landing pad of every frame with
+//! one, which runs destructors and calls [`_Unwind_Resume`] to carry on,
+//! until the handler's landing pad takes the exception. An exception nothing
+//! handles leaves the stack as it is, for reporting it.
+
+use crate::cfi::{self, Fde, SP};
+use crate::unwind::Frame;
+
+/// The registers that pass the exception and the selected action to a
+/// landing pad, a0 and a1.
+const DATA_REGISTERS: [usize; 2] = [10, 11];
+
+pub(crate) const ACTION_SEARCH_PHASE: u32 = 1;
+pub(crate) const ACTION_CLEANUP_PHASE: u32 = 2;
+const ACTION_HANDLER_FRAME: u32 = 4;
+
+/// What a personality routine found, numbered as by the ABI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub(crate) enum Reason {
+    FatalPhase2Error = 2,
+    FatalPhase1Error = 3,
+    EndOfStack = 5,
+    HandlerFound = 6,
+    InstallContext = 7,
+    ContinueUnwind = 8,
+}
+
+/// The header of every exception, followed by what the language raising it
+/// stores.
+#[repr(C)]
+pub(crate) struct Exception {
+    /// The language and vendor that raised the exception.
+    pub class: u64,
+    /// The destructor that other languages call, which panics never need.
+    _cleanup: usize,
+    /// The stack pointer of the frame that handles the exception, found by
+    /// the search phase.
+    handler: usize,
+    _reserved: usize,
+}
+impl Exception {
+    pub(crate) const fn new(class: u64) -> Self {
+        Self { class, _cleanup: 0, handler: 0, _reserved: 0 }
+    }
+}
+
+/// The frame a personality routine is asked about.
+pub(crate) struct Context {
+    frame: Frame,
+    fde: Fde<'static>,
+}
+impl Context {
+    /// An address within the instruction the frame is executing, which is
+    /// the call the exception passes through.
+    pub(crate) fn ip(&self) -> usize {
+        self.frame.address()
+    }
+    /// The start of the function.
+    pub(crate) fn region_start(&self) -> usize {
+        self.fde.start
+    }
+    /// The language-specific data of the function.
+    pub(crate) fn lsda(&self) -> Option<usize> {
+        self.fde.lsda
+    }
+    /// Pass the exception and `selector` to the landing pad at `pad`.
+    pub(crate) fn set_landing_pad(&mut self, pad: usize, exception: *mut Exception, selector: usize) {
+        let [exception_register, selector_register] = DATA_REGISTERS;
+        self.frame.registers.x[exception_register] = exception as usize;
+        self.frame.registers.x[selector_register] = selector;
+        self.frame.registers.pc = pad;
+    }
+}
+
+type Personality = unsafe extern "C" fn(i32, u32, u64, *mut Exception, *mut Context) -> Reason;
+
+/// Ask the personality routine of `frame`, if it has one, what to do with
+/// the exception, returning the frame as the routine left it.
+///
+/// # Safety
+/// `exception` must point to a live exception.
+unsafe fn personality(frame: Frame, actions: u32, exception: *mut Exception) -> (Reason, Option<Context>) {
+    let Some(fde) = cfi::find(frame.address()) else {
+        return (Reason::EndOfStack, None);
+    };
+    let mut context = Context { frame, fde };
+    let Some(personality) = fde.cie.personality else {
+        return (Reason::ContinueUnwind, Some(context));
+    };
+    let personality: Personality = core::mem::transmute(personality);
+    let reason = personality(1, actions, (*exception).class, exception, &mut context);
+    (reason, Some(context))
+}
+
+/// Search the stack of the caller for a frame that handles `exception`,
+/// recording it for [`unwind`].
+///
+/// # Safety
+/// `exception` must point to a live exception.
+#[inline(never)]
+pub(crate) unsafe fn search(exception: *mut Exception) -> bool {
+    let mut frame = Frame::capture().caller();
+    while let Some(current) = frame {
+        match personality(current, ACTION_SEARCH_PHASE, exception).0 {
+            Reason::HandlerFound => {
+                (*exception).handler = current.registers.x[SP];
+                return true;
+            },
+            Reason::ContinueUnwind => {},
+            _ => return false,
+        }
+        frame = current.caller();
+    }
+    false
+}
+
+/// Unwind the stack of the caller to the frame [`search`] found, entering
+/// the landing pads on the way.
+///
+/// # Safety
+/// `exception` must point to a live exception that [`search`] found a
+/// handler for, from the same caller, and which the landing pads may free.
+#[inline(never)]
+pub(crate) unsafe fn unwind(exception: *mut Exception) -> ! {
+    cleanup(Frame::capture().caller(), exception)
+}
+
+/// Continue unwinding after a landing pad that only ran destructors.
+///
+/// # Safety
+/// Only for calling from landing pads, with the exception they were given.
+#[no_mangle]
+unsafe extern "C-unwind" fn _Unwind_Resume(exception: *mut Exception) -> ! {
+    cleanup(Frame::capture().caller(), exception)
+}
+
+unsafe fn cleanup(mut frame: Option<Frame>, exception: *mut Exception) -> ! {
+    while let Some(current) = frame {
+        let handler = current.registers.x[SP] == (*exception).handler;
+        let actions = ACTION_CLEANUP_PHASE | if handler { ACTION_HANDLER_FRAME } else { 0 };
+        match personality(current, actions, exception) {
+            (Reason::InstallContext, Some(context)) => context.frame.registers.install(),
+            (Reason::ContinueUnwind, _) if !handler => {},
+            _ => break,
+        }
+        frame = current.caller();
+    }
+    // destructors have already run, so there is no going back
+    crate::abort(format_args!("failed to unwind to the handler of a panic"), None)
+}
@@ -1,29 +1,218 @@
 #![no_std]
 #![allow(internal_features)]
+#![feature(alloc_error_handler)]
+#![feature(allocator_api)]
+#![feature(core_intrinsics)]
 #![feature(lang_items)]
+#![feature(panic_can_unwind)]
 
-#[lang = "eh_personality"]
-#[no_mangle]
-unsafe extern "C" fn rust_eh_personality() {}
+extern crate alloc;
 
-#[no_mangle]
-unsafe extern "C" fn _Unwind_Resume() {}
+mod cfi;
+mod demangle;
+mod exception;
+mod personality;
+mod symbols;
+mod unwind;
+
+use alloc::{boxed::Box, string::String};
+use core::fmt::{self, Write};
+use core::mem::ManuallyDrop;
+use core::panic::{PanicInfo, UnwindSafe};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use exception::Exception;
+use hart::PerHart;
+
+pub use symbols::{resolve, Location, Symbol};
+pub use unwind::Backtrace;
+
+/// The exception class of Rust panics.
+const CLASS: u64 = u64::from_be_bytes(*b"MOZ\0RUST");
+
+/// The log records replayed when the kernel stops.
+const LOG_RECORDS: usize = 32;
+
+/// Set by the first fatal panic, which reports it.
+static PANICKING: AtomicBool = AtomicBool::new(false);
+/// Set while a hart prepares to unwind a panic, which cannot panic again.
+static RAISING: PerHart<AtomicBool> = PerHart::new([const { AtomicBool::new(false) }; hart::MAX_HARTS]);
 
 extern "C" {
     fn _hang() -> !;
 }
 
+/// A panic being unwound, with its message.
+#[repr(C)]
+struct Panic {
+    exception: Exception,
+    message: String,
+}
+impl Panic {
+    /// A panic for `info`, or None if there is no memory for it.
+    fn new(info: &PanicInfo) -> Option<Box<Self>> {
+        let mut message = String::new();
+        write!(Fallible(&mut message), "{}", info.message()).ok()?;
+        Box::try_new(Self { exception: Exception::new(CLASS), message }).ok()
+    }
+}
+
+/// Writes to a string only while there is memory for it, as a panic may be
+/// from running out.
+struct Fallible<'a>(&'a mut String);
+impl Write for Fallible<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.try_reserve(s.len()).map_err(|_| fmt::Error)?;
+        self.0.push_str(s);
+        Ok(())
+    }
+}
+
+/// Run `f`, returning the message of a panic that unwinds out of it once its
+/// destructors have run.
+///
+/// A panic that no `catch_unwind` encloses stops the kernel instead, as does
+/// one from code that cannot unwind.
+pub fn catch_unwind<F: FnOnce() -> R + UnwindSafe, R>(f: F) -> Result<R, String> {
+    union Data<F, R> {
+        f: ManuallyDrop<F>,
+        result: ManuallyDrop<R>,
+        message: ManuallyDrop<String>,
+    }
+    fn call<F: FnOnce() -> R, R>(data: *mut u8) {
+        // Safety: `data` holds the closure until it is called
+        unsafe {
+            let data = &mut *data.cast::<Data<F, R>>();
+            let f = ManuallyDrop::take(&mut data.f);
+            data.result = ManuallyDrop::new(f());
+        }
+    }
+    fn catch<F: FnOnce() -> R, R>(data: *mut u8, exception: *mut u8) {
+        // Safety: the closure was taken before it panicked, and the landing
+        // pad passes the exception it was given
+        unsafe {
+            let data = &mut *data.cast::<Data<F, R>>();
+            data.message = ManuallyDrop::new(take(exception.cast()));
+        }
+    }
+
+    let mut data = Data::<F, R> { f: ManuallyDrop::new(f) };
+    let pointer = core::ptr::addr_of_mut!(data).cast();
+    // Safety: `call` and `catch` set the field they return
+    unsafe {
+        if core::intrinsics::catch_unwind(call::<F, R>, pointer, catch::<F, R>) == 0 {
+            Ok(ManuallyDrop::into_inner(data.result))
+        } else {
+            Err(ManuallyDrop::into_inner(data.message))
+        }
+    }
+}
+
+/// Take the message of a caught panic, freeing it.
+///
+/// # Safety
+/// `exception` must be a caught exception, which is not used again.
+unsafe fn take(exception: *mut Exception) -> String {
+    if (*exception).class != CLASS {
+        abort(format_args!("caught an exception that is not a panic"), None);
+    }
+    Box::from_raw(exception.cast::<Panic>()).message
+}
+
 #[panic_handler]
-fn panic(info: &core::panic::PanicInfo) -> ! {
-    let mut out = ::serial::global().lock();
+fn panic(info: &PanicInfo) -> ! {
+    if info.can_unwind() && !RAISING.get().swap(true, Ordering::Relaxed) {
+        raise(info);
+        RAISING.get().store(false, Ordering::Relaxed);
+    }
+    abort(info, info.location())
+}
+
+/// Report the panic and unwind to the `catch_unwind` that handles it, only
+/// returning if there is none.
+fn raise(info: &PanicInfo) {
+    let Some(panic) = Panic::new(info) else {
+        return;
+    };
+    let exception = Box::into_raw(panic).cast::<Exception>();
+    // Safety: the exception is live until its handler takes it
+    unsafe {
+        if exception::search(exception) {
+            report(info);
+            RAISING.get().store(false, Ordering::Relaxed);
+            exception::unwind(exception);
+        }
+        drop(Box::from_raw(exception.cast::<Panic>()));
+    }
+}
+
+/// Report a panic that will be caught.
+fn report(info: &PanicInfo) {
+    let serial = ::serial::global();
+    // the panic may have come from printing
+    if serial.is_held() {
+        return;
+    }
+    let mut out = serial.lock();
+    let _ = writeln!(out, "hart {} {info}", ::hart::id());
+    let _ = writeln!(out, "{}", Backtrace::capture());
+}
+
+/// Report a panic that cannot be caught, from `location`, and stop the
+/// kernel.
+fn abort(reason: impl fmt::Display, location: Option<&core::panic::Location<'_>>) -> ! {
+    // report only the first panic, with the rest of the system stopped
+    if !::ipi::stop_others() {
+        ::ipi::halt();
+    }
+    // The panic may have come from code holding the serial lock
+    let mut out = unsafe { ::serial::global().force_lock() };
+    // a fault while reporting the first panic, such as from unwinding a
+    // corrupted stack, must not report it again
+    if PANICKING.swap(true, Ordering::AcqRel) {
+        if let Some(location) = location {
+            let _ = writeln!(out, "panicked while panicking at {location}");
+        }
+        let _ = out.flush();
+        unsafe { _hang() }
+    }
     let _ = writeln!(
         out,
 "
 ╔═══════════════════╗
 ║ ⚠ Kernel Panic 🮲🮳 ║
 ╚═══════════════════╝
-{info}
+{reason}
 "
     );
+    let _ = writeln!(out, "{}", Backtrace::capture());
+    let _ = writeln!(out, "{}", Postmortem);
+    let _ = out.flush();
     unsafe { _hang() }
 }
+
+/// The current hart, the time since boot and the last records of the kernel
+/// log, for reporting why the kernel stopped when earlier output was lost.
+pub struct Postmortem;
+impl fmt::Display for Postmortem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let uptime = ::time::uptime();
+        write!(f, "hart {}, up {}.{:06}s\nlog:", ::hart::id(), uptime.as_secs(), uptime.subsec_micros())?;
+        let mut result = Ok(());
+        let mut empty = true;
+        let available = ::logger::try_for_each(LOG_RECORDS, |record| {
+            empty = false;
+            result = result.and_then(|()| write!(f, "\n  {record}"));
+        });
+        match (available, empty) {
+            (false, _) => write!(f, " unavailable while a hart is logging"),
+            (true, true) => write!(f, " empty"),
+            (true, false) => result,
+        }
+    }
+}
+
+#[alloc_error_handler]
+fn out_of_memory(layout: core::alloc::Layout) -> ! {
+    panic!("out of memory allocating {} bytes aligned to {}", layout.size(), layout.align());
+}
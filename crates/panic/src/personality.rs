@@ -0,0 +1,112 @@
+//! The personality routine of Rust frames, which finds the landing pad for
+//! the call an exception passes through in the language-specific data that
+//! LLVM emits into `.gcc_except_table`.
+
+use crate::cfi::{self, Bases, PE_OMIT};
+use crate::exception::{Context, Exception, Reason, ACTION_CLEANUP_PHASE, ACTION_SEARCH_PHASE};
+
+/// What a frame does with an exception passing through a call.
+enum Action {
+    /// Nothing, as the call has no landing pad.
+    None,
+    /// Run destructors at the landing pad, then continue unwinding.
+    Cleanup(usize),
+    /// Stop unwinding at the landing pad, as `catch_unwind` does.
+    Catch(usize),
+    /// The call must not unwind.
+    Terminate,
+}
+
+#[lang = "eh_personality"]
+#[no_mangle]
+unsafe extern "C" fn rust_eh_personality(
+    version: i32,
+    actions: u32,
+    _class: u64,
+    exception: *mut Exception,
+    context: *mut Context,
+) -> Reason {
+    if version != 1 {
+        return Reason::FatalPhase1Error;
+    }
+    let context = &mut *context;
+    let Some(action) = find_action(context) else {
+        return Reason::FatalPhase1Error;
+    };
+    if actions & ACTION_SEARCH_PHASE != 0 {
+        match action {
+            Action::None | Action::Cleanup(_) => Reason::ContinueUnwind,
+            Action::Catch(_) => Reason::HandlerFound,
+            Action::Terminate => Reason::FatalPhase1Error,
+        }
+    } else if actions & ACTION_CLEANUP_PHASE != 0 {
+        match action {
+            Action::None => Reason::ContinueUnwind,
+            Action::Cleanup(pad) | Action::Catch(pad) => {
+                context.set_landing_pad(pad, exception, 0);
+                Reason::InstallContext
+            },
+            Action::Terminate => Reason::FatalPhase2Error,
+        }
+    } else {
+        Reason::FatalPhase1Error
+    }
+}
+
+/// Find what the frame of `context` does at the call it is in, or None if its
+/// language-specific data is malformed.
+fn find_action(context: &Context) -> Option<Action> {
+    let Some(lsda) = context.lsda() else {
+        return Some(Action::None);
+    };
+    let ip = context.ip();
+    let start = context.region_start();
+    let bases = Bases { data: 0, function: start };
+    let mut reader = cfi::lsda(lsda)?;
+
+    let pad_encoding = reader.u8()?;
+    let pad_base = match pad_encoding {
+        PE_OMIT => start,
+        encoding => reader.pointer(encoding, bases)?,
+    };
+    // the type table is only needed to match the types of C++ exceptions
+    if reader.u8()? != PE_OMIT {
+        reader.uleb128()?;
+    }
+    let call_site_encoding = reader.u8()?;
+    let call_site_length = reader.uleb128()? as usize;
+    let mut call_sites = cfi::Reader::new(reader.bytes(call_site_length)?);
+    let actions = reader.rest();
+
+    // the call sites are sorted, and only offsets within the function
+    let offset = |reader: &mut cfi::Reader<'_>| reader.pointer(call_site_encoding & 0x0f, Bases::default());
+    while !call_sites.is_empty() {
+        let site_start = start.wrapping_add(offset(&mut call_sites)?);
+        let site_length = offset(&mut call_sites)?;
+        let pad = offset(&mut call_sites)?;
+        let action = call_sites.uleb128()? as usize;
+        if ip < site_start {
+            break;
+        }
+        if ip < site_start.wrapping_add(site_length) {
+            if pad == 0 {
+                return Some(Action::None);
+            }
+            let pad = pad_base.wrapping_add(pad);
+            if action == 0 {
+                return Some(Action::Cleanup(pad));
+            }
+            // the type filter of the first action: 0 is a cleanup, a positive
+            // index a catch, which Rust only emits as catch-alls, and a
+            // negative one an exception specification, which Rust never uses
+            let mut entry = cfi::Reader::new(actions.get(action - 1..)?);
+            return Some(match entry.sleb128()? {
+                0 => Action::Cleanup(pad),
+                filter if filter > 0 => Action::Catch(pad),
+                _ => Action::Terminate,
+            });
+        }
+    }
+    // calls without an entry must not unwind
+    Some(Action::Terminate)
+}
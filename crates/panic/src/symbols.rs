@@ -0,0 +1,147 @@
+//! Names for addresses in the kernel, from the table that `configure` fills
+//! the `.symbols` section with after linking.
+//!
+//! The layout of the table is described in `configure/cli/src/symbols.rs`.
+//! A kernel that was not built through `configure` has an empty table, and
+//! resolves nothing.
+
+use core::fmt;
+
+use crate::demangle::Demangle;
+
+const MAGIC: &[u8; 4] = b"SYMS";
+const HEADER_SIZE: usize = 24;
+const SYMBOL_SIZE: usize = 12;
+const LINE_SIZE: usize = 8;
+const FILE_SIZE: usize = 4;
+
+extern "C" {
+    static SYMBOLS: Reserved;
+}
+
+/// The space reserved for the table, as laid out by `debug.s`.
+#[repr(C)]
+struct Reserved {
+    address: *const u8,
+    size: usize,
+}
+
+/// The function containing an address.
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol {
+    name: &'static str,
+    /// How far the address is into the function.
+    pub offset: usize,
+    location: Option<Location>,
+}
+impl Symbol {
+    /// The source of the address, if the table has line information.
+    pub fn location(&self) -> Option<Location> {
+        self.location
+    }
+}
+/// Prints the demangled name and the offset.
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+0x{:x}", Demangle(self.name), self.offset)
+    }
+}
+
+/// A line of source.
+#[derive(Debug, Clone, Copy)]
+pub struct Location {
+    pub file: &'static str,
+    pub line: u32,
+}
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// The symbol containing `address`, if the kernel has a symbol table.
+pub fn resolve(address: usize) -> Option<Symbol> {
+    let table = Table::new()?;
+    let offset = u32::try_from(address.checked_sub(table.base)?).ok()?;
+
+    let index = last_at_or_before(table.symbols, offset, |i| table.u32(table.symbol(i)))?;
+    let entry = table.symbol(index);
+    let start = table.u32(entry)?;
+    let size = table.u32(entry + 4)?;
+    // symbols without a size extend to the next one
+    if size != 0 && offset - start >= size {
+        return None;
+    }
+    let name = table.string(table.u32(entry + 8)?)?;
+
+    let location = last_at_or_before(table.lines, offset, |i| table.u32(table.line(i)))
+        .and_then(|index| {
+            let entry = table.line(index);
+            let line = table.u16(entry + 6)? as u32;
+            let file = table.u16(entry + 4)? as usize;
+            if line == 0 || file >= table.files {
+                return None;
+            }
+            let file = table.string(table.u32(table.file(file))?)?;
+            Some(Location { file, line })
+        });
+    Some(Symbol { name, offset: (offset - start) as usize, location })
+}
+
+/// The last of `count` entries sorted by `key` with a key of at most `target`.
+fn last_at_or_before(count: usize, target: u32, key: impl Fn(usize) -> Option<u32>) -> Option<usize> {
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if key(middle)? <= target {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    low.checked_sub(1)
+}
+
+struct Table {
+    data: &'static [u8],
+    symbols: usize,
+    lines: usize,
+    files: usize,
+    base: usize,
+}
+impl Table {
+    fn new() -> Option<Self> {
+        // Safety: the linker script reserves the section, which is read-only
+        let data = unsafe { core::slice::from_raw_parts(SYMBOLS.address, SYMBOLS.size) };
+        if data.get(..4)? != MAGIC {
+            return None;
+        }
+        let mut table = Self { data, symbols: 0, lines: 0, files: 0, base: 0 };
+        table.symbols = table.u32(4)? as usize;
+        table.lines = table.u32(8)? as usize;
+        table.files = table.u32(12)? as usize;
+        table.base = u64::from_le_bytes(data.get(16..24)?.try_into().ok()?) as usize;
+        Some(table)
+    }
+    fn symbol(&self, index: usize) -> usize {
+        HEADER_SIZE + index * SYMBOL_SIZE
+    }
+    fn line(&self, index: usize) -> usize {
+        self.symbol(self.symbols) + index * LINE_SIZE
+    }
+    fn file(&self, index: usize) -> usize {
+        self.line(self.lines) + index * FILE_SIZE
+    }
+    fn string(&self, offset: u32) -> Option<&'static str> {
+        let start = self.file(self.files) + offset as usize;
+        let len = self.u16(start)? as usize;
+        let bytes = self.data.get(start + 2..start + 2 + len)?;
+        core::str::from_utf8(bytes).ok()
+    }
+    fn u16(&self, offset: usize) -> Option<u16> {
+        Some(u16::from_le_bytes(self.data.get(offset..offset + 2)?.try_into().ok()?))
+    }
+    fn u32(&self, offset: usize) -> Option<u32> {
+        Some(u32::from_le_bytes(self.data.get(offset..offset + 4)?.try_into().ok()?))
+    }
+}
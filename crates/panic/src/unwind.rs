@@ -1,39 +1,177 @@
+//! Walking the stack with the call frame information of `.eh_frame`.
+
+use core::fmt;
+
+use riscv::trap::TrapFrame;
+
+use crate::cfi::{self, Cfa, Rule, REGISTERS, SP};
+use crate::symbols;
+
+/// Backtraces stop after this many frames, in case the stack loops.
+const MAX_FRAMES: usize = 64;
+
 extern "C" {
-    static EH_FRAME: CallFrameInfo;
+    fn _unwind_capture(registers: *mut Registers);
+    fn _unwind_install(registers: *const Registers) -> !;
 }
 
+/// The general-purpose registers of a frame, numbered as by DWARF, with the
+/// address it is executing.
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
+pub(crate) struct Registers {
+    pub x: [usize; REGISTERS],
+    pub pc: usize,
+}
+impl Registers {
+    /// The registers of the caller, as they are at the call.
+    #[inline(always)]
+    pub(crate) fn capture() -> Self {
+        let mut registers = Self { x: [0; REGISTERS], pc: 0 };
+        // Safety: the function only writes to the registers
+        unsafe { _unwind_capture(&mut registers) };
+        registers
+    }
+    pub(crate) fn from_trap(frame: &TrapFrame) -> Self {
+        let mut x = frame.x;
+        x[0] = 0;
+        Self { x, pc: frame.pc }
+    }
+    /// Continue running with these registers, abandoning the stack below
+    /// them.
+    ///
+    /// # Safety
+    /// The registers must be those of a frame further up the stack, with the
+    /// pc of a landing pad in it.
+    pub(crate) unsafe fn install(&self) -> ! {
+        _unwind_install(self)
+    }
+}
+
+/// A frame being unwound.
 #[derive(Clone, Copy)]
-struct CallFrameInfo {
-    ptr: *const u8,
-    len: usize,
+pub(crate) struct Frame {
+    pub registers: Registers,
+    /// Whether `pc` is the instruction being executed, rather than a return
+    /// address just after a call.
+    exact: bool,
 }
-impl CallFrameInfo {
-    fn new() -> Self {
-        unsafe { EH_FRAME }
+impl Frame {
+    /// The frame of the caller, at the call.
+    #[inline(always)]
+    pub(crate) fn capture() -> Self {
+        Self { registers: Registers::capture(), exact: false }
     }
-    fn length(self) -> u32 {
-        unsafe {
-            self.ptr.cast::<u32>().read()
+    /// An address within the instruction the frame is executing, which for a
+    /// return address is the call.
+    pub(crate) fn address(&self) -> usize {
+        if self.exact {
+            self.registers.pc
+        } else {
+            self.registers.pc.wrapping_sub(1)
         }
     }
-    fn cie_id(self) -> u32 {
-        unsafe {
-            self.ptr.add(4).cast::<u32>().read()
+
+    /// The frame of the caller, or None at the end of the stack or if it
+    /// cannot be found.
+    pub(crate) fn caller(&self) -> Option<Self> {
+        let fde = cfi::find(self.address())?;
+        let row = fde.row(self.address())?;
+        let registers = &self.registers;
+        let cfa = match row.cfa {
+            Cfa::RegisterOffset { register, offset } => {
+                registers.x.get(register)?.wrapping_add(offset as usize)
+            },
+            Cfa::Expression(expression) => cfi::evaluate(expression, &registers.x, None)?,
+        };
+        let mut caller = *registers;
+        for (register, rule) in row.registers.iter().enumerate().skip(1) {
+            caller.x[register] = match *rule {
+                // only the end of the stack leaves the return address undefined
+                Rule::Undefined if register == fde.cie.return_address => return None,
+                Rule::Undefined | Rule::SameValue => registers.x[register],
+                Rule::Offset(offset) => cfi::read_word(cfa.wrapping_add(offset as usize))?,
+                Rule::ValOffset(offset) => cfa.wrapping_add(offset as usize),
+                Rule::Register(source) => *registers.x.get(source)?,
+                Rule::Expression(expression) => {
+                    cfi::read_word(cfi::evaluate(expression, &registers.x, Some(cfa))?)?
+                },
+                Rule::ValExpression(expression) => cfi::evaluate(expression, &registers.x, Some(cfa))?,
+            };
         }
+        caller.x[SP] = cfa;
+        caller.pc = *caller.x.get(fde.cie.return_address)?;
+        // a frame that does not move up the stack would repeat forever
+        let moved = cfa > registers.x[SP] || (cfa == registers.x[SP] && caller.pc != registers.pc);
+        if caller.pc == 0 || !moved {
+            return None;
+        }
+        // the caller of a signal frame was interrupted rather than calling
+        Some(Self { registers: caller, exact: fde.cie.signal_frame })
     }
-    fn version(self) -> u8 {
-        unsafe {
-            self.ptr.add(8).cast::<u8>().read()
+}
+
+/// The frames on a stack, from the innermost.
+pub(crate) struct Frames {
+    next: Option<Frame>,
+    count: usize,
+}
+impl Iterator for Frames {
+    type Item = Frame;
+    fn next(&mut self) -> Option<Frame> {
+        if self.count == MAX_FRAMES {
+            return None;
         }
+        let frame = self.next.take()?;
+        self.next = frame.caller();
+        self.count += 1;
+        Some(frame)
+    }
+}
+
+/// The return addresses of a stack, printed one frame per line with the
+/// function and source of each, if the kernel has a symbol table.
+///
+/// Frames are only unwound while printing, so a backtrace can be taken while
+/// the heap is unusable.
+#[derive(Clone, Copy)]
+pub struct Backtrace(Frame);
+impl Backtrace {
+    /// The stack of the caller.
+    #[inline(always)]
+    pub fn capture() -> Self {
+        Self(Frame::capture())
+    }
+    /// The stack interrupted by a trap, starting at the trapping instruction.
+    pub fn from_trap(frame: &TrapFrame) -> Self {
+        Self(Frame { registers: Registers::from_trap(frame), exact: true })
+    }
+    pub(crate) fn frames(&self) -> Frames {
+        Frames { next: Some(self.0), count: 0 }
     }
 }
-impl core::fmt::Debug for CallFrameInfo {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        f.debug_struct("CallFrameInfo")
-            .field("length", &self.length())
-            .field("cie_id", &self.cie_id())
-            .field("version", &self.version())
-            .finish()
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "backtrace:")?;
+        let mut frames = self.frames();
+        for (i, frame) in frames.by_ref().enumerate() {
+            let pc = frame.registers.pc;
+            write!(f, "  {i:>2}: 0x{pc:016x}")?;
+            // the symbol of the call, with the offset of the return address
+            match symbols::resolve(frame.address()) {
+                Some(mut symbol) => {
+                    symbol.offset += pc - frame.address();
+                    writeln!(f, " - {symbol}")?;
+                    if let Some(location) = symbol.location() {
+                        writeln!(f, "                           at {location}")?;
+                    }
+                },
+                None => writeln!(f)?,
+            }
+        }
+        if frames.count == MAX_FRAMES && frames.next.is_some() {
+            writeln!(f, "  ...")?;
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,9 @@
+fn main() {
+    let config = configure::Config::load();
+    config.cfg().library("user", &[
+        "src/user.s",
+    ]);
+    config.executable("hello", &["user/hello.s"]);
+    config.executable("echo", &["user/echo.s"]);
+    config.executable("fault", &["user/fault.s"]);
+}
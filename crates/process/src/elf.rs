@@ -0,0 +1,162 @@
+//! Loading static RISC-V ELF executables.
+//!
+//! Only 64-bit little-endian executables without an interpreter or dynamic
+//! section are accepted, and their loadable segments must lie in user space.
+
+use core::fmt;
+
+use memory::paging::Flags;
+
+use crate::space::UserSpace;
+
+const MAGIC: &[u8; 4] = b"\x7fELF";
+const CLASS_64: u8 = 2;
+const LITTLE_ENDIAN: u8 = 1;
+const TYPE_EXECUTABLE: u16 = 2;
+const MACHINE_RISCV: u16 = 243;
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+const SEGMENT_LOAD: u32 = 1;
+const SEGMENT_DYNAMIC: u32 = 2;
+const SEGMENT_INTERPRETER: u32 = 3;
+
+const SEGMENT_EXECUTE: u32 = 1 << 0;
+const SEGMENT_WRITE: u32 = 1 << 1;
+const SEGMENT_READ: u32 = 1 << 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The image ends before a header or segment it describes.
+    Truncated,
+    /// The image does not start with the ELF magic.
+    NotElf,
+    /// The image is not for 64-bit little-endian RISC-V.
+    WrongMachine,
+    /// The image is not an executable.
+    NotExecutable,
+    /// The executable needs a dynamic linker.
+    Dynamic,
+    /// A segment lies outside user space, or overlaps another.
+    InvalidSegment { address: usize },
+    /// Frames or page tables could not be allocated for a segment.
+    OutOfMemory,
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "the image is truncated"),
+            Self::NotElf => write!(f, "not an ELF image"),
+            Self::WrongMachine => write!(f, "not a 64-bit little-endian RISC-V image"),
+            Self::NotExecutable => write!(f, "not an executable"),
+            Self::Dynamic => write!(f, "dynamically linked executables are not supported"),
+            Self::InvalidSegment { address } => write!(f, "invalid segment at {address:#x}"),
+            Self::OutOfMemory => write!(f, "out of memory for the segments"),
+        }
+    }
+}
+
+/// A program header describing a loadable segment.
+struct Segment {
+    kind: u32,
+    flags: u32,
+    offset: usize,
+    address: usize,
+    file_size: usize,
+    memory_size: usize,
+}
+
+/// Map the loadable segments of `image` into `space`, returning the address
+/// of its entry point.
+pub(crate) fn load(image: &[u8], space: &mut UserSpace) -> Result<usize, Error> {
+    if image.len() < HEADER_SIZE {
+        return Err(Error::Truncated);
+    }
+    if &image[..4] != MAGIC {
+        return Err(Error::NotElf);
+    }
+    if image[4] != CLASS_64 || image[5] != LITTLE_ENDIAN || read_u16(image, 18)? != MACHINE_RISCV {
+        return Err(Error::WrongMachine);
+    }
+    if read_u16(image, 16)? != TYPE_EXECUTABLE {
+        return Err(Error::NotExecutable);
+    }
+    let entry = read_u64(image, 24)?;
+    let table = read_u64(image, 32)?;
+    let entry_size = read_u16(image, 54)? as usize;
+    let count = read_u16(image, 56)? as usize;
+    if entry_size < PROGRAM_HEADER_SIZE {
+        return Err(Error::Truncated);
+    }
+
+    let segments = (0..count).map(|i| segment(image, table.saturating_add(i * entry_size)));
+    for segment in segments.clone() {
+        if matches!(segment?.kind, SEGMENT_DYNAMIC | SEGMENT_INTERPRETER) {
+            return Err(Error::Dynamic);
+        }
+    }
+    for segment in segments {
+        let segment = segment?;
+        if segment.kind == SEGMENT_LOAD && segment.memory_size > 0 {
+            load_segment(image, &segment, space)?;
+        }
+    }
+    Ok(entry)
+}
+
+fn load_segment(image: &[u8], segment: &Segment, space: &mut UserSpace) -> Result<(), Error> {
+    let invalid = Error::InvalidSegment { address: segment.address };
+    let data = segment.offset.checked_add(segment.file_size)
+        .and_then(|end| image.get(segment.offset..end))
+        .ok_or(Error::Truncated)?;
+    if segment.file_size > segment.memory_size {
+        return Err(invalid);
+    }
+    let mut flags = Flags::READ;
+    if segment.flags & SEGMENT_WRITE != 0 {
+        flags |= Flags::WRITE;
+    }
+    if segment.flags & SEGMENT_EXECUTE != 0 {
+        flags |= Flags::EXECUTE;
+    }
+    if segment.flags & (SEGMENT_READ | SEGMENT_WRITE | SEGMENT_EXECUTE) == 0 {
+        return Err(invalid);
+    }
+
+    let start = segment.address & !(memory::FRAME_SIZE - 1);
+    let end = segment.address.checked_add(segment.memory_size)
+        .and_then(|end| end.checked_next_multiple_of(memory::FRAME_SIZE))
+        .ok_or(invalid)?;
+    space.map(start, end - start, flags).map_err(|e| match e {
+        crate::space::MapError::OutOfMemory => Error::OutOfMemory,
+        crate::space::MapError::Invalid => invalid,
+    })?;
+    // the rest of the segment was zeroed when it was mapped
+    space.fill(segment.address, data);
+    Ok(())
+}
+
+fn segment(image: &[u8], offset: usize) -> Result<Segment, Error> {
+    Ok(Segment {
+        kind: read_u32(image, offset)?,
+        flags: read_u32(image, offset.saturating_add(4))?,
+        offset: read_u64(image, offset.saturating_add(8))?,
+        address: read_u64(image, offset.saturating_add(16))?,
+        file_size: read_u64(image, offset.saturating_add(32))?,
+        memory_size: read_u64(image, offset.saturating_add(40))?,
+    })
+}
+
+fn read_u16(image: &[u8], offset: usize) -> Result<u16, Error> {
+    Ok(u16::from_le_bytes(read(image, offset)?))
+}
+fn read_u32(image: &[u8], offset: usize) -> Result<u32, Error> {
+    Ok(u32::from_le_bytes(read(image, offset)?))
+}
+fn read_u64(image: &[u8], offset: usize) -> Result<usize, Error> {
+    Ok(u64::from_le_bytes(read(image, offset)?) as usize)
+}
+fn read<const N: usize>(image: &[u8], offset: usize) -> Result<[u8; N], Error> {
+    let bytes = image.get(offset..offset.saturating_add(N)).ok_or(Error::Truncated)?;
+    Ok(bytes.try_into().expect("slice has the requested length"))
+}
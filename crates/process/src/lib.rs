@@ -0,0 +1,188 @@
+#![no_std]
+//! User mode processes.
+//!
+//! A process runs a static RISC-V ELF executable in its own Sv39 address
+//! space, on a kernel thread of its own that enters user mode and handles its
+//! traps. Processes talk to the kernel through the system calls in
+//! [`syscall`], and a fault ends the process rather than the kernel.
+//!
+//! The kernel runs with the FPU off, so processes cannot use float registers.
+
+extern crate alloc;
+
+mod elf;
+mod space;
+pub mod syscall;
+mod user;
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use memory::paging::Flags;
+use riscv::trap::{Cause, Exception};
+use sync::{IrqSafeSpinLock, WaitQueue};
+
+pub use elf::Error as ElfError;
+pub use space::{STACK_SIZE, STACK_TOP};
+
+use space::UserSpace;
+use user::UserContext;
+
+static NEXT_PID: AtomicUsize = AtomicUsize::new(1);
+/// Every process that has not exited.
+static PROCESSES: IrqSafeSpinLock<Vec<Process>> = IrqSafeSpinLock::new(Vec::new());
+
+/// The executables embedded in the kernel image, by name.
+pub static PROGRAMS: &[(&str, &[u8])] = &[
+    ("hello", include_bytes!(concat!(env!("OUT_DIR"), "/hello"))),
+    ("echo", include_bytes!(concat!(env!("OUT_DIR"), "/echo"))),
+    ("fault", include_bytes!(concat!(env!("OUT_DIR"), "/fault"))),
+];
+
+/// The embedded executable called `name`.
+pub fn program(name: &str) -> Option<&'static [u8]> {
+    PROGRAMS.iter().find(|(program, _)| *program == name).map(|(_, image)| *image)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pid(usize);
+impl fmt::Display for Pid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// How a process ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The process called `exit` with the status.
+    Exited(i32),
+    /// The process took an exception other than a system call.
+    Faulted { exception: Exception, pc: usize, tval: usize },
+}
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exited(status) => write!(f, "exited with status {status}"),
+            Self::Faulted { exception, pc, tval } => {
+                write!(f, "{exception} at 0x{pc:016x}, tval 0x{tval:016x}")
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The executable could not be loaded.
+    Elf(ElfError),
+    /// There is no memory for the address space or stack.
+    OutOfMemory,
+    /// The thread to run the process could not be spawned.
+    Thread(::thread::Error),
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Elf(e) => write!(f, "failed to load the executable: {e}"),
+            Self::OutOfMemory => write!(f, "out of memory for the process"),
+            Self::Thread(e) => write!(f, "failed to spawn the process thread: {e}"),
+        }
+    }
+}
+
+struct Inner {
+    pid: Pid,
+    name: String,
+    status: IrqSafeSpinLock<Option<Status>>,
+    /// Woken once the process has exited.
+    exited: WaitQueue,
+}
+
+/// A handle to a process.
+#[derive(Clone)]
+pub struct Process(Arc<Inner>);
+impl Process {
+    pub fn pid(&self) -> Pid {
+        self.0.pid
+    }
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+    /// How the process ended, or None while it is running.
+    pub fn status(&self) -> Option<Status> {
+        *self.0.status.lock()
+    }
+    /// Block until the process has exited, returning how it ended.
+    pub fn wait(&self) -> Status {
+        self.0.exited.wait_until(|| self.status().is_some());
+        self.status().expect("exited process has no status")
+    }
+    fn exit(&self, status: Status) {
+        *self.0.status.lock() = Some(status);
+        PROCESSES.lock().retain(|other| !Arc::ptr_eq(&self.0, &other.0));
+        self.0.exited.notify_all();
+    }
+}
+impl fmt::Debug for Process {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Process").field("pid", &self.pid()).field("name", &self.name()).finish()
+    }
+}
+
+/// Every process that has not exited.
+pub fn processes() -> Vec<Process> {
+    PROCESSES.lock().clone()
+}
+
+/// Load the executable `image` into a new process called `name`, and start
+/// it on the least busy hart.
+pub fn spawn(name: &str, image: &[u8]) -> Result<Process, Error> {
+    let mut space = UserSpace::new().ok_or(Error::OutOfMemory)?;
+    let entry = elf::load(image, &mut space).map_err(Error::Elf)?;
+    space.map(STACK_TOP - STACK_SIZE, STACK_SIZE, Flags::READ | Flags::WRITE)
+        .map_err(|_| Error::OutOfMemory)?;
+
+    let process = Process(Arc::new(Inner {
+        pid: Pid(NEXT_PID.fetch_add(1, Ordering::Relaxed)),
+        name: name.into(),
+        status: IrqSafeSpinLock::new(None),
+        exited: WaitQueue::new(),
+    }));
+    PROCESSES.lock().push(process.clone());
+    let running = process.clone();
+    let spawned = ::thread::Builder::new()
+        .name(name)
+        .spawn(move || run(running, space, UserContext::new(entry, STACK_TOP)));
+    if let Err(e) = spawned {
+        PROCESSES.lock().retain(|other| !Arc::ptr_eq(&process.0, &other.0));
+        return Err(Error::Thread(e));
+    }
+    Ok(process)
+}
+
+/// Run `process` until it exits, on its own thread.
+fn run(process: Process, mut space: UserSpace, mut context: UserContext) {
+    let status = loop {
+        match context.run(&space) {
+            // already handled once `run` unmasked interrupts
+            Cause::Interrupt(_) => {},
+            Cause::Exception(Exception::UserEcall) => {
+                context.frame.pc += 4;
+                if let Some(status) = syscall::handle(&process, &mut space, &mut context.frame) {
+                    break Status::Exited(status);
+                }
+            },
+            Cause::Exception(exception) => {
+                let frame = &context.frame;
+                break Status::Faulted { exception, pc: frame.pc, tval: frame.tval };
+            },
+        }
+    };
+    if let Status::Faulted { .. } = status {
+        log::warn!("process {} ({}): {status}", process.pid(), process.name());
+    }
+    // the address space must be freed before waiters can see the memory back
+    drop(space);
+    process.exit(status);
+}
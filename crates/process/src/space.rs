@@ -0,0 +1,196 @@
+//! The address space of a process, and access to it from the kernel.
+//!
+//! Every page mapped for a process is backed by a frame it owns. The kernel
+//! reaches user memory through the physical addresses of those frames, which
+//! it maps to themselves, after checking that the process itself could access
+//! it.
+
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use memory::paging::{self, AddressSpace, Flags, USER_END, USER_START};
+use memory::{FrameSize, FRAME_SIZE};
+
+/// The top of the stack of a process.
+pub const STACK_TOP: usize = USER_END;
+pub const STACK_SIZE: usize = 0x10000;
+/// The lowest address given out by `mmap`.
+const MMAP_START: usize = 0x20_0000_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MapError {
+    /// The range is not page aligned, not in user space, or already mapped.
+    Invalid,
+    OutOfMemory,
+}
+
+/// A user pointer that the process cannot access as requested.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Fault;
+
+/// A range of pages mapped for the process.
+#[derive(Debug, Clone, Copy)]
+struct Mapping {
+    start: usize,
+    end: usize,
+}
+
+pub(crate) struct UserSpace {
+    space: AddressSpace,
+    /// Sorted by address.
+    mappings: Vec<Mapping>,
+}
+impl UserSpace {
+    pub(crate) fn new() -> Option<Self> {
+        Some(Self {
+            space: AddressSpace::user()?,
+            mappings: Vec::new(),
+        })
+    }
+    /// Switch the current hart to this address space, if it is not already.
+    pub(crate) fn activate(&self) {
+        let satp = self.space.satp(0);
+        if paging::satp() != satp {
+            // Safety: user address spaces share the kernel mappings
+            unsafe { paging::activate(satp) };
+        }
+    }
+
+    /// Map `length` bytes of zeroed memory at `start` for user mode.
+    ///
+    /// Nothing is left mapped on failure.
+    pub(crate) fn map(&mut self, start: usize, length: usize, flags: Flags) -> Result<(), MapError> {
+        let end = start.checked_add(length).ok_or(MapError::Invalid)?;
+        if length == 0 || !start.is_multiple_of(FRAME_SIZE) || !length.is_multiple_of(FRAME_SIZE)
+            || start < USER_START || end > USER_END
+            || self.mappings.iter().any(|mapping| start < mapping.end && mapping.start < end)
+        {
+            return Err(MapError::Invalid);
+        }
+        for page in (start..end).step_by(FRAME_SIZE) {
+            if let Err(e) = self.map_page(page, flags) {
+                self.unmap(Mapping { start, end: page });
+                return Err(e);
+            }
+        }
+        let index = self.mappings.partition_point(|mapping| mapping.start < start);
+        self.mappings.insert(index, Mapping { start, end });
+        Ok(())
+    }
+    fn map_page(&mut self, page: usize, flags: Flags) -> Result<(), MapError> {
+        let frame = memory::frame::allocate(FrameSize::Size4K).ok_or(MapError::OutOfMemory)?;
+        // Safety: the frame was just allocated, and is mapped to itself
+        unsafe { core::ptr::write_bytes(frame as *mut u8, 0, FRAME_SIZE) };
+        self.space.map(page, frame, FrameSize::Size4K, flags | Flags::USER).map_err(|_| {
+            // Safety: the frame was never mapped
+            unsafe { memory::frame::free(frame, FrameSize::Size4K) };
+            MapError::OutOfMemory
+        })
+    }
+    fn unmap(&mut self, mapping: Mapping) {
+        for page in (mapping.start..mapping.end).step_by(FRAME_SIZE) {
+            if let Ok((frame, size)) = self.space.unmap(page) {
+                // Safety: the frame was allocated for this page alone
+                unsafe { memory::frame::free(frame, size) };
+            }
+        }
+    }
+    /// Find `length` bytes of unmapped user space for `mmap`.
+    pub(crate) fn find_free(&self, length: usize) -> Option<usize> {
+        let mut start = MMAP_START;
+        for mapping in self.mappings.iter().filter(|mapping| mapping.end > MMAP_START) {
+            if start.checked_add(length)? <= mapping.start {
+                break;
+            }
+            start = start.max(mapping.end);
+        }
+        (start.checked_add(length)? <= STACK_TOP - STACK_SIZE).then_some(start)
+    }
+
+    /// Copy `data` to `address`, whatever the permissions of the pages.
+    ///
+    /// # Panics
+    /// Panics if the range is not mapped.
+    pub(crate) fn fill(&mut self, address: usize, data: &[u8]) {
+        let mut copied = 0;
+        self.chunks(address, data.len(), Flags::NONE, |chunk| {
+            let (source, destination) = (data[copied..].as_ptr(), chunk.start as *mut u8);
+            // Safety: the pages are mapped for this process, and mapped to
+            // themselves for the kernel
+            unsafe { core::ptr::copy_nonoverlapping(source, destination, chunk.len()) };
+            copied += chunk.len();
+        }).expect("filled memory is not mapped");
+    }
+    /// Fill `buffer` from the process memory at `address`.
+    pub(crate) fn read(&self, address: usize, buffer: &mut [u8]) -> Result<(), Fault> {
+        let mut copied = 0;
+        self.chunks(address, buffer.len(), Flags::READ, |chunk| {
+            let (source, destination) = (chunk.start as *const u8, buffer[copied..].as_mut_ptr());
+            // Safety: as for `fill`
+            unsafe { core::ptr::copy_nonoverlapping(source, destination, chunk.len()) };
+            copied += chunk.len();
+        })
+    }
+    /// Copy `data` to the process at `address`.
+    pub(crate) fn write(&mut self, address: usize, data: &[u8]) -> Result<(), Fault> {
+        let mut copied = 0;
+        self.chunks(address, data.len(), Flags::WRITE, |chunk| {
+            let (source, destination) = (data[copied..].as_ptr(), chunk.start as *mut u8);
+            // Safety: as for `fill`
+            unsafe { core::ptr::copy_nonoverlapping(source, destination, chunk.len()) };
+            copied += chunk.len();
+        })
+    }
+    /// Check that the process can access `length` bytes at `address` as
+    /// requested.
+    pub(crate) fn check(&self, address: usize, length: usize, access: Flags) -> Result<(), Fault> {
+        self.chunks(address, length, access, |_| {})
+    }
+    /// Visit the physical memory behind `length` bytes at `address`, a page
+    /// at a time, if every page is mapped for user mode with `access`.
+    fn chunks(
+        &self,
+        address: usize,
+        length: usize,
+        access: Flags,
+        mut f: impl FnMut(Range<usize>),
+    ) -> Result<(), Fault> {
+        let end = address.checked_add(length).ok_or(Fault)?;
+        if length == 0 {
+            return Ok(());
+        }
+        if address < USER_START || end > USER_END {
+            return Err(Fault);
+        }
+        // check every page before copying anything
+        let first = address & !(FRAME_SIZE - 1);
+        for page in (first..end).step_by(FRAME_SIZE) {
+            match self.space.translate(page) {
+                Some((_, flags)) if flags.contains(access | Flags::USER) => {},
+                _ => return Err(Fault),
+            }
+        }
+        let mut start = address;
+        while start < end {
+            let chunk_end = end.min((start & !(FRAME_SIZE - 1)) + FRAME_SIZE);
+            let (phys, _) = self.space.translate(start).ok_or(Fault)?;
+            f(phys..phys + (chunk_end - start));
+            start = chunk_end;
+        }
+        Ok(())
+    }
+}
+/// Frees the memory of the process, switching the current hart back to the
+/// kernel address space if it was using this one.
+impl Drop for UserSpace {
+    fn drop(&mut self) {
+        if paging::satp() == self.space.satp(0) {
+            // Safety: the kernel address space maps everything the kernel
+            // uses
+            unsafe { paging::activate(paging::kernel_satp()) };
+        }
+        for mapping in core::mem::take(&mut self.mappings) {
+            self.unmap(mapping);
+        }
+    }
+}
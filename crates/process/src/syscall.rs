@@ -0,0 +1,149 @@
+//! The system calls available to processes.
+//!
+//! A process makes a system call with `ecall`, passing its number in `a7` and
+//! up to six arguments in `a0` to `a5`. The result is returned in `a0`, as a
+//! negative [`Errno`] on failure. The numbers follow Linux on RISC-V:
+//!
+//! | Number | Call          | Arguments                            | Returns            |
+//! |--------|---------------|--------------------------------------|--------------------|
+//! | 63     | `read`        | fd, buffer, length                   | bytes read         |
+//! | 64     | `write`       | fd, buffer, length                   | bytes written      |
+//! | 93     | `exit`        | status                               | does not return    |
+//! | 124    | `sched_yield` |                                      | 0                  |
+//! | 172    | `getpid`      |                                      | the process id     |
+//! | 222    | `mmap`        | address, length, protection, ...     | the mapped address |
+//!
+//! - `read` blocks until at least one byte of console input is available on
+//!   fd 0.
+//! - `write` sends to the console on fd 1 or 2.
+//! - `mmap` only maps anonymous, zeroed memory. The address is a hint, and
+//!   the flags, fd and offset are ignored. The protection is a combination of
+//!   [`PROT_READ`], [`PROT_WRITE`] and [`PROT_EXEC`], and writable memory is
+//!   also readable.
+
+use memory::paging::Flags;
+use riscv::trap::TrapFrame;
+
+use crate::space::{Fault, MapError, UserSpace};
+use crate::Process;
+
+pub const READ: usize = 63;
+pub const WRITE: usize = 64;
+pub const EXIT: usize = 93;
+pub const YIELD: usize = 124;
+pub const GETPID: usize = 172;
+pub const MMAP: usize = 222;
+
+pub const PROT_READ: usize = 1 << 0;
+pub const PROT_WRITE: usize = 1 << 1;
+pub const PROT_EXEC: usize = 1 << 2;
+
+const STDIN: usize = 0;
+const STDOUT: usize = 1;
+const STDERR: usize = 2;
+/// How much of a `read` or `write` is copied through the kernel at once.
+const CHUNK_SIZE: usize = 256;
+
+/// Why a system call failed, with the Linux error numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(isize)]
+pub enum Errno {
+    /// The file descriptor is not open for the operation.
+    BadFd = 9,
+    OutOfMemory = 12,
+    /// A pointer argument is not accessible to the process.
+    Fault = 14,
+    InvalidArgument = 22,
+    /// There is no system call with the number.
+    NoSys = 38,
+}
+impl From<Fault> for Errno {
+    fn from(_: Fault) -> Self {
+        Self::Fault
+    }
+}
+
+/// Handle the system call in `frame`, returning the exit status if the
+/// process exits.
+pub(crate) fn handle(process: &Process, space: &mut UserSpace, frame: &mut TrapFrame) -> Option<i32> {
+    let args = [
+        frame.x[TrapFrame::A0],
+        frame.x[TrapFrame::A1],
+        frame.x[TrapFrame::A2],
+    ];
+    let result = match frame.x[TrapFrame::A7] {
+        READ => read(space, args[0], args[1], args[2]),
+        WRITE => write(space, args[0], args[1], args[2]),
+        EXIT => return Some(args[0] as i32),
+        YIELD => {
+            ::thread::yield_now();
+            Ok(0)
+        },
+        GETPID => Ok(process.pid().0),
+        MMAP => mmap(space, args[0], args[1], args[2]),
+        _ => Err(Errno::NoSys),
+    };
+    frame.x[TrapFrame::A0] = match result {
+        Ok(value) => value,
+        Err(errno) => (-(errno as isize)) as usize,
+    };
+    None
+}
+
+fn read(space: &mut UserSpace, fd: usize, buffer: usize, length: usize) -> Result<usize, Errno> {
+    if fd != STDIN {
+        return Err(Errno::BadFd);
+    }
+    let mut chunk = [0; CHUNK_SIZE];
+    let chunk = &mut chunk[..length.min(CHUNK_SIZE)];
+    // fail before taking input the process cannot receive
+    space.check(buffer, chunk.len(), Flags::WRITE)?;
+    let read = ::serial::read(chunk).map_err(|_| Errno::BadFd)?;
+    space.write(buffer, &chunk[..read])?;
+    Ok(read)
+}
+
+fn write(space: &mut UserSpace, fd: usize, buffer: usize, length: usize) -> Result<usize, Errno> {
+    if fd != STDOUT && fd != STDERR {
+        return Err(Errno::BadFd);
+    }
+    let mut chunk = [0; CHUNK_SIZE];
+    let mut written = 0;
+    while written < length {
+        let chunk = &mut chunk[..(length - written).min(CHUNK_SIZE)];
+        space.read(buffer + written, chunk)?;
+        ::serial::global().lock().write(chunk).map_err(|_| Errno::BadFd)?;
+        written += chunk.len();
+    }
+    Ok(written)
+}
+
+fn mmap(space: &mut UserSpace, address: usize, length: usize, protection: usize) -> Result<usize, Errno> {
+    let length = length.checked_next_multiple_of(memory::FRAME_SIZE).ok_or(Errno::InvalidArgument)?;
+    if length == 0 || protection & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(Errno::InvalidArgument);
+    }
+    let mut flags = Flags::NONE;
+    if protection & (PROT_READ | PROT_WRITE) != 0 {
+        flags |= Flags::READ;
+    }
+    if protection & PROT_WRITE != 0 {
+        flags |= Flags::WRITE;
+    }
+    if protection & PROT_EXEC != 0 {
+        flags |= Flags::EXECUTE;
+    }
+    if flags == Flags::NONE {
+        return Err(Errno::InvalidArgument);
+    }
+    let hint = address & !(memory::FRAME_SIZE - 1);
+    if hint != 0 && space.map(hint, length, flags).is_ok() {
+        return Ok(hint);
+    }
+    let address = space.find_free(length).ok_or(Errno::OutOfMemory)?;
+    space.map(address, length, flags).map_err(|e| match e {
+        MapError::OutOfMemory => Errno::OutOfMemory,
+        MapError::Invalid => Errno::InvalidArgument,
+    })?;
+    Ok(address)
+}
@@ -0,0 +1,60 @@
+//! Running a process in user mode until it traps.
+
+use riscv::trap::{Cause, TrapFrame};
+
+use crate::space::UserSpace;
+
+const SSTATUS_SIE: usize = 1 << 1;
+const SSTATUS_SPIE: usize = 1 << 5;
+const SSTATUS_SPP: usize = 1 << 8;
+const SSTATUS_SUM: usize = 1 << 18;
+
+/// The registers of a process, and of the kernel while the process runs,
+/// kept in sync with `_enter_user`.
+#[repr(C)]
+pub(crate) struct UserContext {
+    pub frame: TrapFrame,
+    /// `ra`, `sp`, `gp`, `tp`, `s0` to `s11`, `stvec` and `sscratch`, saved
+    /// by `_enter_user` and restored when the process traps.
+    kernel: [usize; 18],
+}
+const _: () = assert!(core::mem::size_of::<UserContext>() == (36 + 18) * 8);
+
+extern "C" {
+    fn _enter_user(context: *mut UserContext);
+}
+
+impl UserContext {
+    /// A context that starts the process at `entry` with the stack below
+    /// `stack`.
+    pub(crate) fn new(entry: usize, stack: usize) -> Self {
+        let mut frame = TrapFrame { pc: entry, ..TrapFrame::default() };
+        frame.x[TrapFrame::SP] = stack;
+        Self { frame, kernel: [0; 18] }
+    }
+    /// Run the process in `space` until it traps, returning the cause.
+    ///
+    /// Interrupts taken in user mode are left pending, and handled by the
+    /// kernel as soon as this returns.
+    pub(crate) fn run(&mut self, space: &UserSpace) -> Cause {
+        let interrupts = ::sync::irq::disable();
+        space.activate();
+        // return to user mode with interrupts enabled, and no access to user
+        // memory from the kernel
+        self.frame.status = (sstatus() & !(SSTATUS_SPP | SSTATUS_SIE | SSTATUS_SUM)) | SSTATUS_SPIE;
+        // Safety: the process address space maps the kernel, and the trap
+        // returns here with the kernel registers restored
+        unsafe { _enter_user(self) };
+        ::sync::irq::restore(interrupts);
+        self.frame.cause()
+    }
+}
+
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+fn sstatus() -> usize {
+    let sstatus;
+    unsafe { core::arch::asm!("csrr {}, sstatus", out(reg) sstatus) };
+    sstatus
+}
+#[cfg(not(any(target_arch = "riscv64", target_arch = "riscv32")))]
+fn sstatus() -> usize { 0 }
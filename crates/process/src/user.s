@@ -0,0 +1,145 @@
+.section .text, "ax", %progbits
+
+// Offsets into a `UserContext`, kept in sync with the process crate: the user
+// `TrapFrame`, then the kernel registers to return to.
+.equ KERNEL, 36 * 8
+.equ STVEC, KERNEL + 16 * 8
+.equ SSCRATCH, STVEC + 8
+
+// Run user code from the `UserContext` at a0, returning once it traps.
+// Interrupts must be masked, and the process address space active.
+.global _enter_user
+_enter_user:
+    sd ra, KERNEL + 0 * 8(a0)
+    sd sp, KERNEL + 1 * 8(a0)
+    sd gp, KERNEL + 2 * 8(a0)
+    sd tp, KERNEL + 3 * 8(a0)
+    sd s0, KERNEL + 4 * 8(a0)
+    sd s1, KERNEL + 5 * 8(a0)
+    sd s2, KERNEL + 6 * 8(a0)
+    sd s3, KERNEL + 7 * 8(a0)
+    sd s4, KERNEL + 8 * 8(a0)
+    sd s5, KERNEL + 9 * 8(a0)
+    sd s6, KERNEL + 10 * 8(a0)
+    sd s7, KERNEL + 11 * 8(a0)
+    sd s8, KERNEL + 12 * 8(a0)
+    sd s9, KERNEL + 13 * 8(a0)
+    sd s10, KERNEL + 14 * 8(a0)
+    sd s11, KERNEL + 15 * 8(a0)
+    csrr t0, stvec
+    sd t0, STVEC(a0)
+    csrr t0, sscratch
+    sd t0, SSCRATCH(a0)
+
+    // traps from user mode return from this function through `_user_trap`
+    lla t0, _user_trap
+    csrw stvec, t0
+    csrw sscratch, a0
+
+    ld t0, 32 * 8(a0)
+    csrw sepc, t0
+    ld t0, 33 * 8(a0)
+    csrw sstatus, t0
+
+    ld x1, 1 * 8(a0)
+    ld x2, 2 * 8(a0)
+    ld x3, 3 * 8(a0)
+    ld x4, 4 * 8(a0)
+    ld x5, 5 * 8(a0)
+    ld x6, 6 * 8(a0)
+    ld x7, 7 * 8(a0)
+    ld x8, 8 * 8(a0)
+    ld x9, 9 * 8(a0)
+    ld x11, 11 * 8(a0)
+    ld x12, 12 * 8(a0)
+    ld x13, 13 * 8(a0)
+    ld x14, 14 * 8(a0)
+    ld x15, 15 * 8(a0)
+    ld x16, 16 * 8(a0)
+    ld x17, 17 * 8(a0)
+    ld x18, 18 * 8(a0)
+    ld x19, 19 * 8(a0)
+    ld x20, 20 * 8(a0)
+    ld x21, 21 * 8(a0)
+    ld x22, 22 * 8(a0)
+    ld x23, 23 * 8(a0)
+    ld x24, 24 * 8(a0)
+    ld x25, 25 * 8(a0)
+    ld x26, 26 * 8(a0)
+    ld x27, 27 * 8(a0)
+    ld x28, 28 * 8(a0)
+    ld x29, 29 * 8(a0)
+    ld x30, 30 * 8(a0)
+    ld x31, 31 * 8(a0)
+    ld x10, 10 * 8(a0)
+    sret
+
+// Trap vector while user code runs. Saves the user registers in the
+// `UserContext` in sscratch, then returns from `_enter_user` with the trap
+// vector and sscratch it replaced.
+.align 4
+_user_trap:
+    csrrw a0, sscratch, a0
+    sd x1, 1 * 8(a0)
+    sd x2, 2 * 8(a0)
+    sd x3, 3 * 8(a0)
+    sd x4, 4 * 8(a0)
+    sd x5, 5 * 8(a0)
+    sd x6, 6 * 8(a0)
+    sd x7, 7 * 8(a0)
+    sd x8, 8 * 8(a0)
+    sd x9, 9 * 8(a0)
+    sd x11, 11 * 8(a0)
+    sd x12, 12 * 8(a0)
+    sd x13, 13 * 8(a0)
+    sd x14, 14 * 8(a0)
+    sd x15, 15 * 8(a0)
+    sd x16, 16 * 8(a0)
+    sd x17, 17 * 8(a0)
+    sd x18, 18 * 8(a0)
+    sd x19, 19 * 8(a0)
+    sd x20, 20 * 8(a0)
+    sd x21, 21 * 8(a0)
+    sd x22, 22 * 8(a0)
+    sd x23, 23 * 8(a0)
+    sd x24, 24 * 8(a0)
+    sd x25, 25 * 8(a0)
+    sd x26, 26 * 8(a0)
+    sd x27, 27 * 8(a0)
+    sd x28, 28 * 8(a0)
+    sd x29, 29 * 8(a0)
+    sd x30, 30 * 8(a0)
+    sd x31, 31 * 8(a0)
+    csrr t0, sscratch
+    sd t0, 10 * 8(a0)
+
+    csrr t0, sepc
+    sd t0, 32 * 8(a0)
+    csrr t0, sstatus
+    sd t0, 33 * 8(a0)
+    csrr t0, scause
+    sd t0, 34 * 8(a0)
+    csrr t0, stval
+    sd t0, 35 * 8(a0)
+
+    ld t0, STVEC(a0)
+    csrw stvec, t0
+    ld t0, SSCRATCH(a0)
+    csrw sscratch, t0
+    ld ra, KERNEL + 0 * 8(a0)
+    ld sp, KERNEL + 1 * 8(a0)
+    ld gp, KERNEL + 2 * 8(a0)
+    ld tp, KERNEL + 3 * 8(a0)
+    ld s0, KERNEL + 4 * 8(a0)
+    ld s1, KERNEL + 5 * 8(a0)
+    ld s2, KERNEL + 6 * 8(a0)
+    ld s3, KERNEL + 7 * 8(a0)
+    ld s4, KERNEL + 8 * 8(a0)
+    ld s5, KERNEL + 9 * 8(a0)
+    ld s6, KERNEL + 10 * 8(a0)
+    ld s7, KERNEL + 11 * 8(a0)
+    ld s8, KERNEL + 12 * 8(a0)
+    ld s9, KERNEL + 13 * 8(a0)
+    ld s10, KERNEL + 14 * 8(a0)
+    ld s11, KERNEL + 15 * 8(a0)
+    ret
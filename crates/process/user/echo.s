@@ -0,0 +1,44 @@
+// Echoes console input back through a page from mmap, until Ctrl-D.
+.section .text
+.global _start
+_start:
+    // mmap(0, 4096, PROT_READ | PROT_WRITE, 0, -1, 0)
+    li a0, 0
+    li a1, 4096
+    li a2, 3
+    li a3, 0
+    li a4, -1
+    li a5, 0
+    li a7, 222
+    ecall
+    bltz a0, fail
+    mv s0, a0
+
+loop:
+    // read(0, buffer, 4096)
+    li a0, 0
+    mv a1, s0
+    li a2, 4096
+    li a7, 63
+    ecall
+    blez a0, done
+    // stop at an end of transmission
+    lbu t0, 0(s0)
+    li t1, 4
+    beq t0, t1, done
+
+    // write(1, buffer, count)
+    mv a2, a0
+    li a0, 1
+    mv a1, s0
+    li a7, 64
+    ecall
+    j loop
+
+done:
+    li a0, 0
+    li a7, 93
+    ecall
+fail:
+    li a7, 93
+    ecall
@@ -0,0 +1,9 @@
+// Reads kernel memory, which is not mapped for user mode, so the process
+// faults.
+.section .text
+.global _start
+_start:
+    li t0, 0x80000000
+    ld a0, 0(t0)
+    li a7, 93
+    ecall
@@ -0,0 +1,26 @@
+// Greets the console, lets other threads run once, then exits with its
+// process id as the status.
+.section .text
+.global _start
+_start:
+    // write(1, message, length)
+    li a0, 1
+    lla a1, message
+    li a2, message_end - message
+    li a7, 64
+    ecall
+
+    // sched_yield()
+    li a7, 124
+    ecall
+
+    // exit(getpid())
+    li a7, 172
+    ecall
+    li a7, 93
+    ecall
+
+.section .rodata
+message:
+    .ascii "Hello from user mode!\n"
+message_end:
@@ -0,0 +1,506 @@
+//! Instruction decoding for trap reports.
+//!
+//! Covers RV64I with the M, A, Zicsr and Zifencei extensions, the privileged
+//! instructions, floating-point loads and stores, and the C extension, which is
+//! expanded to the equivalent 32-bit instruction before it is printed.
+
+use core::fmt;
+
+/// The ABI names of the general-purpose registers.
+pub const REGISTER_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// The ABI names of the floating-point registers.
+const FLOAT_REGISTER_NAMES: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1", "fa0", "fa1", "fa2",
+    "fa3", "fa4", "fa5", "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9",
+    "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
+/// The CSRs named in disassembly, others are shown by number.
+const CSR_NAMES: &[(u32, &str)] = &[
+    (0x001, "fflags"),
+    (0x002, "frm"),
+    (0x003, "fcsr"),
+    (0x100, "sstatus"),
+    (0x104, "sie"),
+    (0x105, "stvec"),
+    (0x106, "scounteren"),
+    (0x140, "sscratch"),
+    (0x141, "sepc"),
+    (0x142, "scause"),
+    (0x143, "stval"),
+    (0x144, "sip"),
+    (0x14d, "stimecmp"),
+    (0x180, "satp"),
+    (0x300, "mstatus"),
+    (0x301, "misa"),
+    (0x302, "medeleg"),
+    (0x303, "mideleg"),
+    (0x304, "mie"),
+    (0x305, "mtvec"),
+    (0x306, "mcounteren"),
+    (0x340, "mscratch"),
+    (0x341, "mepc"),
+    (0x342, "mcause"),
+    (0x343, "mtval"),
+    (0x344, "mip"),
+    (0x3a0, "pmpcfg0"),
+    (0x3b0, "pmpaddr0"),
+    (0x3b1, "pmpaddr1"),
+    (0xc00, "cycle"),
+    (0xc01, "time"),
+    (0xc02, "instret"),
+    (0xf11, "mvendorid"),
+    (0xf12, "marchid"),
+    (0xf13, "mimpid"),
+    (0xf14, "mhartid"),
+];
+
+/// A single instruction, 16 bits long if compressed and 32 bits otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction(u32);
+impl Instruction {
+    /// Take an instruction from its encoding, ignoring the upper half of
+    /// `bits` if the low half is a compressed instruction.
+    pub const fn from_bits(bits: u32) -> Self {
+        if bits & 0b11 == 0b11 {
+            Self(bits)
+        } else {
+            Self(bits & 0xffff)
+        }
+    }
+    /// Read the instruction at `pc`, in halves as it may only be 2-byte
+    /// aligned.
+    ///
+    /// # Safety
+    /// `pc` must point to a readable instruction.
+    pub unsafe fn read(pc: usize) -> Self {
+        let low = (pc as *const u16).read() as u32;
+        if low & 0b11 == 0b11 {
+            let high = (pc as *const u16).add(1).read() as u32;
+            Self(high << 16 | low)
+        } else {
+            Self(low)
+        }
+    }
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+    pub const fn is_compressed(self) -> bool {
+        self.0 & 0b11 != 0b11
+    }
+    /// The length of the instruction in bytes.
+    pub const fn size(self) -> usize {
+        if self.is_compressed() {
+            2
+        } else {
+            4
+        }
+    }
+}
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bits = if self.is_compressed() {
+            match expand(self.0) {
+                Some(bits) => bits,
+                None => return write!(f, ".insn 0x{:04x}", self.0),
+            }
+        } else {
+            self.0
+        };
+        match decode(bits, f) {
+            Some(result) => result,
+            None if self.is_compressed() => write!(f, ".insn 0x{:04x}", self.0),
+            None => write!(f, ".insn 0x{:08x}", self.0),
+        }
+    }
+}
+
+struct Reg(u32);
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REGISTER_NAMES[self.0 as usize & 31])
+    }
+}
+
+struct FloatReg(u32);
+impl fmt::Display for FloatReg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(FLOAT_REGISTER_NAMES[self.0 as usize & 31])
+    }
+}
+
+struct Csr(u32);
+impl fmt::Display for Csr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match CSR_NAMES.iter().find(|(number, _)| *number == self.0) {
+            Some((_, name)) => f.write_str(name),
+            None => write!(f, "0x{:03x}", self.0),
+        }
+    }
+}
+
+const fn bit(bits: u32, from: u32, to: u32) -> u32 {
+    (bits >> from & 1) << to
+}
+const fn field(bits: u32, low: u32, len: u32) -> u32 {
+    bits >> low & ((1 << len) - 1)
+}
+/// Sign-extend the low `len` bits of `value`.
+const fn sign_extend(value: u32, len: u32) -> i32 {
+    ((value << (32 - len)) as i32) >> (32 - len)
+}
+
+/// Print a 32-bit instruction, or `None` if it is not recognised.
+fn decode(bits: u32, f: &mut fmt::Formatter<'_>) -> Option<fmt::Result> {
+    let opcode = bits & 0x7f;
+    let rd = Reg(field(bits, 7, 5));
+    let funct3 = field(bits, 12, 3);
+    let rs1 = Reg(field(bits, 15, 5));
+    let rs2 = Reg(field(bits, 20, 5));
+    let funct7 = bits >> 25;
+
+    let imm_i = (bits as i32) >> 20;
+    let imm_s = (bits as i32) >> 25 << 5 | field(bits, 7, 5) as i32;
+    let imm_b = sign_extend(
+        bit(bits, 31, 12) | bit(bits, 7, 11) | field(bits, 25, 6) << 5 | field(bits, 8, 4) << 1,
+        13,
+    );
+    let imm_j = sign_extend(
+        bit(bits, 31, 20) | field(bits, 12, 8) << 12 | bit(bits, 20, 11) | field(bits, 21, 10) << 1,
+        21,
+    );
+    let imm_u = bits >> 12;
+
+    Some(match opcode {
+        0x37 => write!(f, "lui {rd}, 0x{imm_u:x}"),
+        0x17 => write!(f, "auipc {rd}, 0x{imm_u:x}"),
+        0x6f if rd.0 == 0 => write!(f, "j {imm_j}"),
+        0x6f => write!(f, "jal {rd}, {imm_j}"),
+        0x67 if funct3 == 0 => match (rd.0, rs1.0, imm_i) {
+            (0, 1, 0) => write!(f, "ret"),
+            (0, _, 0) => write!(f, "jr {rs1}"),
+            _ => write!(f, "jalr {rd}, {imm_i}({rs1})"),
+        },
+        0x63 => {
+            let name = ["beq", "bne", "", "", "blt", "bge", "bltu", "bgeu"][funct3 as usize];
+            if name.is_empty() {
+                return None;
+            }
+            write!(f, "{name} {rs1}, {rs2}, {imm_b}")
+        },
+        0x03 => {
+            let name = ["lb", "lh", "lw", "ld", "lbu", "lhu", "lwu", ""][funct3 as usize];
+            if name.is_empty() {
+                return None;
+            }
+            write!(f, "{name} {rd}, {imm_i}({rs1})")
+        },
+        0x23 => {
+            let name = ["sb", "sh", "sw", "sd", "", "", "", ""][funct3 as usize];
+            if name.is_empty() {
+                return None;
+            }
+            write!(f, "{name} {rs2}, {imm_s}({rs1})")
+        },
+        0x07 | 0x27 => {
+            let name = match (opcode, funct3) {
+                (0x07, 2) => "flw",
+                (0x07, 3) => "fld",
+                (0x27, 2) => "fsw",
+                (0x27, 3) => "fsd",
+                _ => return None,
+            };
+            if opcode == 0x07 {
+                write!(f, "{name} {}, {imm_i}({rs1})", FloatReg(rd.0))
+            } else {
+                write!(f, "{name} {}, {imm_s}({rs1})", FloatReg(rs2.0))
+            }
+        },
+        0x13 => {
+            let shamt = field(bits, 20, 6);
+            match (funct3, bits >> 26) {
+                (0, _) if rd.0 == 0 && rs1.0 == 0 && imm_i == 0 => write!(f, "nop"),
+                (0, _) if rs1.0 == 0 => write!(f, "li {rd}, {imm_i}"),
+                (0, _) if imm_i == 0 => write!(f, "mv {rd}, {rs1}"),
+                (0, _) => write!(f, "addi {rd}, {rs1}, {imm_i}"),
+                (2, _) => write!(f, "slti {rd}, {rs1}, {imm_i}"),
+                (3, _) => write!(f, "sltiu {rd}, {rs1}, {imm_i}"),
+                (4, _) => write!(f, "xori {rd}, {rs1}, {imm_i}"),
+                (6, _) => write!(f, "ori {rd}, {rs1}, {imm_i}"),
+                (7, _) => write!(f, "andi {rd}, {rs1}, {imm_i}"),
+                (1, 0x00) => write!(f, "slli {rd}, {rs1}, {shamt}"),
+                (5, 0x00) => write!(f, "srli {rd}, {rs1}, {shamt}"),
+                (5, 0x10) => write!(f, "srai {rd}, {rs1}, {shamt}"),
+                _ => return None,
+            }
+        },
+        0x1b => {
+            let shamt = field(bits, 20, 5);
+            match (funct3, funct7) {
+                (0, _) => write!(f, "addiw {rd}, {rs1}, {imm_i}"),
+                (1, 0x00) => write!(f, "slliw {rd}, {rs1}, {shamt}"),
+                (5, 0x00) => write!(f, "srliw {rd}, {rs1}, {shamt}"),
+                (5, 0x20) => write!(f, "sraiw {rd}, {rs1}, {shamt}"),
+                _ => return None,
+            }
+        },
+        0x33 => {
+            let name = match (funct7, funct3) {
+                (0x00, 0) => "add",
+                (0x20, 0) => "sub",
+                (0x00, 1) => "sll",
+                (0x00, 2) => "slt",
+                (0x00, 3) => "sltu",
+                (0x00, 4) => "xor",
+                (0x00, 5) => "srl",
+                (0x20, 5) => "sra",
+                (0x00, 6) => "or",
+                (0x00, 7) => "and",
+                (0x01, funct3) => {
+                    const NAMES: [&str; 8] =
+                        ["mul", "mulh", "mulhsu", "mulhu", "div", "divu", "rem", "remu"];
+                    NAMES[funct3 as usize]
+                },
+                _ => return None,
+            };
+            write!(f, "{name} {rd}, {rs1}, {rs2}")
+        },
+        0x3b => {
+            let name = match (funct7, funct3) {
+                (0x00, 0) => "addw",
+                (0x20, 0) => "subw",
+                (0x00, 1) => "sllw",
+                (0x00, 5) => "srlw",
+                (0x20, 5) => "sraw",
+                (0x01, 0) => "mulw",
+                (0x01, 4) => "divw",
+                (0x01, 5) => "divuw",
+                (0x01, 6) => "remw",
+                (0x01, 7) => "remuw",
+                _ => return None,
+            };
+            write!(f, "{name} {rd}, {rs1}, {rs2}")
+        },
+        0x0f => match funct3 {
+            0 => write!(f, "fence"),
+            1 => write!(f, "fence.i"),
+            _ => return None,
+        },
+        0x2f => {
+            let width = match funct3 {
+                2 => "w",
+                3 => "d",
+                _ => return None,
+            };
+            let ordering = match field(bits, 25, 2) {
+                0b00 => "",
+                0b01 => ".rl",
+                0b10 => ".aq",
+                _ => ".aqrl",
+            };
+            let name = match bits >> 27 {
+                0x02 if rs2.0 == 0 => return Some(write!(f, "lr.{width}{ordering} {rd}, ({rs1})")),
+                0x03 => "sc",
+                0x01 => "amoswap",
+                0x00 => "amoadd",
+                0x04 => "amoxor",
+                0x0c => "amoand",
+                0x08 => "amoor",
+                0x10 => "amomin",
+                0x14 => "amomax",
+                0x18 => "amominu",
+                0x1c => "amomaxu",
+                _ => return None,
+            };
+            write!(f, "{name}.{width}{ordering} {rd}, {rs2}, ({rs1})")
+        },
+        0x73 => {
+            let csr = Csr(bits >> 20);
+            let uimm = rs1.0;
+            match funct3 {
+                0 => match bits {
+                    0x0000_0073 => write!(f, "ecall"),
+                    0x0010_0073 => write!(f, "ebreak"),
+                    0x1020_0073 => write!(f, "sret"),
+                    0x3020_0073 => write!(f, "mret"),
+                    0x1050_0073 => write!(f, "wfi"),
+                    _ if funct7 == 0x09 && rd.0 == 0 => write!(f, "sfence.vma {rs1}, {rs2}"),
+                    _ => return None,
+                },
+                1 if rd.0 == 0 => write!(f, "csrw {csr}, {rs1}"),
+                1 => write!(f, "csrrw {rd}, {csr}, {rs1}"),
+                2 if rs1.0 == 0 => write!(f, "csrr {rd}, {csr}"),
+                2 => write!(f, "csrrs {rd}, {csr}, {rs1}"),
+                3 => write!(f, "csrrc {rd}, {csr}, {rs1}"),
+                5 => write!(f, "csrrwi {rd}, {csr}, {uimm}"),
+                6 => write!(f, "csrrsi {rd}, {csr}, {uimm}"),
+                7 => write!(f, "csrrci {rd}, {csr}, {uimm}"),
+                _ => return None,
+            }
+        },
+        _ => return None,
+    })
+}
+
+const fn r_type(funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    funct7 << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+const fn i_type(imm: i32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    (imm as u32) << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+const fn s_type(imm: i32, rs2: u32, rs1: u32, funct3: u32, opcode: u32) -> u32 {
+    let imm = imm as u32;
+    (imm >> 5 & 0x7f) << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | (imm & 0x1f) << 7 | opcode
+}
+const fn b_type(imm: i32, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+    let imm = imm as u32;
+    bit(imm, 12, 31)
+        | field(imm, 5, 6) << 25
+        | rs2 << 20
+        | rs1 << 15
+        | funct3 << 12
+        | field(imm, 1, 4) << 8
+        | bit(imm, 11, 7)
+        | 0x63
+}
+const fn j_type(imm: i32, rd: u32) -> u32 {
+    let imm = imm as u32;
+    bit(imm, 20, 31)
+        | field(imm, 1, 10) << 21
+        | bit(imm, 11, 20)
+        | field(imm, 12, 8) << 12
+        | rd << 7
+        | 0x6f
+}
+
+/// Expand a compressed instruction to its 32-bit equivalent, or `None` if it
+/// is reserved or not recognised.
+fn expand(c: u32) -> Option<u32> {
+    const SP: u32 = 2;
+    let funct3 = field(c, 13, 3);
+    // full register fields
+    let rd = field(c, 7, 5);
+    let rs2 = field(c, 2, 5);
+    // x8 to x15 register fields
+    let rd_ = field(c, 2, 3) + 8;
+    let rs1_ = field(c, 7, 3) + 8;
+
+    let imm6 = sign_extend(bit(c, 12, 5) | field(c, 2, 5), 6);
+    let shamt = bit(c, 12, 5) | field(c, 2, 5);
+    // offsets of 8-byte loads and stores
+    let uimm_d = (field(c, 10, 3) << 3 | field(c, 5, 2) << 6) as i32;
+    let uimm_w = (field(c, 10, 3) << 3 | bit(c, 6, 2) | bit(c, 5, 6)) as i32;
+
+    Some(match (c & 0b11, funct3) {
+        (0b00, 0b000) => {
+            let imm = field(c, 11, 2) << 4 | field(c, 7, 4) << 6 | bit(c, 6, 2) | bit(c, 5, 3);
+            if imm == 0 {
+                return None;
+            }
+            i_type(imm as i32, SP, 0, rd_, 0x13)
+        },
+        (0b00, 0b001) => i_type(uimm_d, rs1_, 3, rd_, 0x07),
+        (0b00, 0b010) => i_type(uimm_w, rs1_, 2, rd_, 0x03),
+        (0b00, 0b011) => i_type(uimm_d, rs1_, 3, rd_, 0x03),
+        (0b00, 0b101) => s_type(uimm_d, rd_, rs1_, 3, 0x27),
+        (0b00, 0b110) => s_type(uimm_w, rd_, rs1_, 2, 0x23),
+        (0b00, 0b111) => s_type(uimm_d, rd_, rs1_, 3, 0x23),
+
+        (0b01, 0b000) => i_type(imm6, rd, 0, rd, 0x13),
+        (0b01, 0b001) if rd != 0 => i_type(imm6, rd, 0, rd, 0x1b),
+        (0b01, 0b010) => i_type(imm6, 0, 0, rd, 0x13),
+        (0b01, 0b011) if rd == SP => {
+            let imm = sign_extend(
+                bit(c, 12, 9) | bit(c, 6, 4) | bit(c, 5, 6) | field(c, 3, 2) << 7 | bit(c, 2, 5),
+                10,
+            );
+            if imm == 0 {
+                return None;
+            }
+            i_type(imm, SP, 0, SP, 0x13)
+        },
+        (0b01, 0b011) => {
+            if imm6 == 0 {
+                return None;
+            }
+            (imm6 as u32) << 12 & 0xffff_f000 | rd << 7 | 0x37
+        },
+        (0b01, 0b100) => match (field(c, 10, 2), bit(c, 12, 0), field(c, 5, 2)) {
+            (0b00, _, _) => i_type(shamt as i32, rs1_, 5, rs1_, 0x13),
+            (0b01, _, _) => i_type((0x400 | shamt) as i32, rs1_, 5, rs1_, 0x13),
+            (0b10, _, _) => i_type(imm6, rs1_, 7, rs1_, 0x13),
+            (0b11, 0, 0b00) => r_type(0x20, rd_, rs1_, 0, rs1_, 0x33),
+            (0b11, 0, 0b01) => r_type(0x00, rd_, rs1_, 4, rs1_, 0x33),
+            (0b11, 0, 0b10) => r_type(0x00, rd_, rs1_, 6, rs1_, 0x33),
+            (0b11, 0, 0b11) => r_type(0x00, rd_, rs1_, 7, rs1_, 0x33),
+            (0b11, 1, 0b00) => r_type(0x20, rd_, rs1_, 0, rs1_, 0x3b),
+            (0b11, 1, 0b01) => r_type(0x00, rd_, rs1_, 0, rs1_, 0x3b),
+            _ => return None,
+        },
+        (0b01, 0b101) => {
+            let imm = sign_extend(
+                bit(c, 12, 11)
+                    | bit(c, 11, 4)
+                    | field(c, 9, 2) << 8
+                    | bit(c, 8, 10)
+                    | bit(c, 7, 6)
+                    | bit(c, 6, 7)
+                    | field(c, 3, 3) << 1
+                    | bit(c, 2, 5),
+                12,
+            );
+            j_type(imm, 0)
+        },
+        (0b01, 0b110 | 0b111) => {
+            let imm = sign_extend(
+                bit(c, 12, 8)
+                    | field(c, 10, 2) << 3
+                    | field(c, 5, 2) << 6
+                    | field(c, 3, 2) << 1
+                    | bit(c, 2, 5),
+                9,
+            );
+            b_type(imm, 0, rs1_, funct3 & 1)
+        },
+
+        (0b10, 0b000) => i_type(shamt as i32, rd, 1, rd, 0x13),
+        (0b10, 0b001) => {
+            let imm = bit(c, 12, 5) | field(c, 5, 2) << 3 | field(c, 2, 3) << 6;
+            i_type(imm as i32, SP, 3, rd, 0x07)
+        },
+        (0b10, 0b010) if rd != 0 => {
+            let imm = bit(c, 12, 5) | field(c, 4, 3) << 2 | field(c, 2, 2) << 6;
+            i_type(imm as i32, SP, 2, rd, 0x03)
+        },
+        (0b10, 0b011) if rd != 0 => {
+            let imm = bit(c, 12, 5) | field(c, 5, 2) << 3 | field(c, 2, 3) << 6;
+            i_type(imm as i32, SP, 3, rd, 0x03)
+        },
+        (0b10, 0b100) => match (bit(c, 12, 0), rd, rs2) {
+            (0, 0, 0) => return None,
+            (0, rs1, 0) => i_type(0, rs1, 0, 0, 0x67),
+            (0, rd, rs2) => r_type(0, rs2, 0, 0, rd, 0x33),
+            (1, 0, 0) => 0x0010_0073,
+            (1, rs1, 0) => i_type(0, rs1, 0, 1, 0x67),
+            (_, rd, rs2) => r_type(0, rs2, rd, 0, rd, 0x33),
+        },
+        (0b10, 0b101) => {
+            let imm = field(c, 10, 3) << 3 | field(c, 7, 3) << 6;
+            s_type(imm as i32, rs2, SP, 3, 0x27)
+        },
+        (0b10, 0b110) => {
+            let imm = field(c, 9, 4) << 2 | field(c, 7, 2) << 6;
+            s_type(imm as i32, rs2, SP, 2, 0x23)
+        },
+        (0b10, 0b111) => {
+            let imm = field(c, 10, 3) << 3 | field(c, 7, 3) << 6;
+            s_type(imm as i32, rs2, SP, 3, 0x23)
+        },
+        _ => return None,
+    })
+}
@@ -0,0 +1,6 @@
+#![no_std]
+//! Definitions of the RISC-V privileged architecture shared across the
+//! kernel.
+
+pub mod instruction;
+pub mod trap;
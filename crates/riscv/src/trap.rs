@@ -0,0 +1,256 @@
+//! Trap frames and causes.
+
+use core::fmt;
+
+use crate::instruction::{Instruction, REGISTER_NAMES};
+
+/// The state of an interrupted hart, saved by a trap vector and restored when
+/// it returns.
+#[derive(Debug, Clone, Default)]
+#[repr(C)]
+pub struct TrapFrame {
+    /// General-purpose registers by number, where `x[0]` is unused.
+    pub x: [usize; 32],
+    /// The address to return to, from `sepc` or `mepc`.
+    pub pc: usize,
+    /// The status register, `sstatus` or `mstatus`, restored on return.
+    pub status: usize,
+    /// The trap cause, from `scause` or `mcause`.
+    pub cause: usize,
+    /// The trap value, from `stval` or `mtval`.
+    pub tval: usize,
+}
+impl TrapFrame {
+    /// The size of the frame on the stack, kept in sync with the trap vectors.
+    pub const SIZE: usize = core::mem::size_of::<Self>();
+
+    pub const RA: usize = 1;
+    pub const SP: usize = 2;
+    pub const GP: usize = 3;
+    pub const TP: usize = 4;
+    pub const A0: usize = 10;
+    pub const A1: usize = 11;
+    pub const A2: usize = 12;
+    pub const A3: usize = 13;
+    pub const A4: usize = 14;
+    pub const A5: usize = 15;
+    pub const A6: usize = 16;
+    pub const A7: usize = 17;
+
+    pub fn cause(&self) -> Cause {
+        Cause::from_raw(self.cause)
+    }
+    /// Return past the instruction that trapped.
+    ///
+    /// # Safety
+    /// `pc` must point to a readable instruction.
+    pub unsafe fn skip_instruction(&mut self) {
+        self.pc += Instruction::read(self.pc).size();
+    }
+    /// What `tval` holds for the cause of this trap.
+    pub fn value(&self) -> Value {
+        self.cause().value(self.tval)
+    }
+}
+/// Print the saved registers by ABI name, four to a line.
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "    pc 0x{:016x}  status 0x{:016x}  cause 0x{:016x}  tval 0x{:016x}",
+            self.pc, self.status, self.cause, self.tval
+        )?;
+        for (row, registers) in self.x.chunks(4).enumerate() {
+            for (column, &value) in registers.iter().enumerate() {
+                let register = row * 4 + column;
+                // the slot for `zero` is never saved
+                let value = if register == 0 { 0 } else { value };
+                write!(f, "{:>6} 0x{value:016x}", REGISTER_NAMES[register])?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+const _: () = assert!(TrapFrame::SIZE == 36 * 8);
+
+/// Why a trap was taken, decoded from `scause` or `mcause`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cause {
+    Interrupt(Interrupt),
+    Exception(Exception),
+}
+impl Cause {
+    pub const fn from_raw(raw: usize) -> Self {
+        let code = raw & !(1 << (usize::BITS - 1));
+        if raw >> (usize::BITS - 1) != 0 {
+            Self::Interrupt(Interrupt::from_code(code))
+        } else {
+            Self::Exception(Exception::from_code(code))
+        }
+    }
+    /// What the trap value register holds for this cause.
+    pub const fn value(self, tval: usize) -> Value {
+        use Exception::*;
+        match self {
+            Self::Exception(
+                InstructionMisaligned | InstructionAccessFault | InstructionPageFault,
+            ) => Value::InstructionAddress(tval),
+            Self::Exception(
+                LoadMisaligned | LoadAccessFault | StoreMisaligned | StoreAccessFault
+                | LoadPageFault | StorePageFault,
+            ) => Value::DataAddress(tval),
+            Self::Exception(Breakpoint) => Value::InstructionAddress(tval),
+            // zero when the hart does not report the instruction
+            Self::Exception(IllegalInstruction) if tval != 0 => {
+                Value::Instruction(Instruction::from_bits(tval as u32))
+            },
+            _ => Value::None,
+        }
+    }
+    /// Whether the instruction at the trapping `pc` may not be readable.
+    pub const fn is_fetch_fault(self) -> bool {
+        matches!(
+            self,
+            Self::Exception(
+                Exception::InstructionMisaligned
+                    | Exception::InstructionAccessFault
+                    | Exception::InstructionPageFault
+            )
+        )
+    }
+}
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Interrupt(interrupt) => interrupt.fmt(f),
+            Self::Exception(exception) => exception.fmt(f),
+        }
+    }
+}
+
+/// The meaning of the trap value register for a given cause.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    /// The address of the instruction that could not be fetched.
+    InstructionAddress(usize),
+    /// The address of the faulting load or store.
+    DataAddress(usize),
+    /// The bits of the illegal instruction.
+    Instruction(Instruction),
+    /// Nothing, or the hart did not report a value.
+    None,
+}
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InstructionAddress(address) => write!(f, "instruction address 0x{address:016x}"),
+            Self::DataAddress(address) => write!(f, "data address 0x{address:016x}"),
+            Self::Instruction(instruction) if instruction.is_compressed() => {
+                write!(f, "instruction 0x{:04x} ({instruction})", instruction.bits())
+            },
+            Self::Instruction(instruction) => {
+                write!(f, "instruction 0x{:08x} ({instruction})", instruction.bits())
+            },
+            Self::None => write!(f, "none"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    SupervisorSoftware,
+    MachineSoftware,
+    SupervisorTimer,
+    MachineTimer,
+    SupervisorExternal,
+    MachineExternal,
+    Unknown(usize),
+}
+impl Interrupt {
+    pub const fn from_code(code: usize) -> Self {
+        match code {
+            1 => Self::SupervisorSoftware,
+            3 => Self::MachineSoftware,
+            5 => Self::SupervisorTimer,
+            7 => Self::MachineTimer,
+            9 => Self::SupervisorExternal,
+            11 => Self::MachineExternal,
+            code => Self::Unknown(code),
+        }
+    }
+}
+impl fmt::Display for Interrupt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SupervisorSoftware => write!(f, "supervisor software interrupt"),
+            Self::MachineSoftware => write!(f, "machine software interrupt"),
+            Self::SupervisorTimer => write!(f, "supervisor timer interrupt"),
+            Self::MachineTimer => write!(f, "machine timer interrupt"),
+            Self::SupervisorExternal => write!(f, "supervisor external interrupt"),
+            Self::MachineExternal => write!(f, "machine external interrupt"),
+            Self::Unknown(code) => write!(f, "unknown interrupt {code}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    InstructionMisaligned,
+    InstructionAccessFault,
+    IllegalInstruction,
+    Breakpoint,
+    LoadMisaligned,
+    LoadAccessFault,
+    StoreMisaligned,
+    StoreAccessFault,
+    UserEcall,
+    SupervisorEcall,
+    MachineEcall,
+    InstructionPageFault,
+    LoadPageFault,
+    StorePageFault,
+    Unknown(usize),
+}
+impl Exception {
+    pub const fn from_code(code: usize) -> Self {
+        match code {
+            0 => Self::InstructionMisaligned,
+            1 => Self::InstructionAccessFault,
+            2 => Self::IllegalInstruction,
+            3 => Self::Breakpoint,
+            4 => Self::LoadMisaligned,
+            5 => Self::LoadAccessFault,
+            6 => Self::StoreMisaligned,
+            7 => Self::StoreAccessFault,
+            8 => Self::UserEcall,
+            9 => Self::SupervisorEcall,
+            11 => Self::MachineEcall,
+            12 => Self::InstructionPageFault,
+            13 => Self::LoadPageFault,
+            15 => Self::StorePageFault,
+            code => Self::Unknown(code),
+        }
+    }
+}
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InstructionMisaligned => write!(f, "instruction address misaligned"),
+            Self::InstructionAccessFault => write!(f, "instruction access fault"),
+            Self::IllegalInstruction => write!(f, "illegal instruction"),
+            Self::Breakpoint => write!(f, "breakpoint"),
+            Self::LoadMisaligned => write!(f, "load address misaligned"),
+            Self::LoadAccessFault => write!(f, "load access fault"),
+            Self::StoreMisaligned => write!(f, "store address misaligned"),
+            Self::StoreAccessFault => write!(f, "store access fault"),
+            Self::UserEcall => write!(f, "environment call from user mode"),
+            Self::SupervisorEcall => write!(f, "environment call from supervisor mode"),
+            Self::MachineEcall => write!(f, "environment call from machine mode"),
+            Self::InstructionPageFault => write!(f, "instruction page fault"),
+            Self::LoadPageFault => write!(f, "load page fault"),
+            Self::StorePageFault => write!(f, "store page fault"),
+            Self::Unknown(code) => write!(f, "unknown exception {code}"),
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! The base extension, for discovering the firmware and other extensions.
+
+use crate::{call, Result};
+
+pub const EID: usize = 0x10;
+
+/// An SBI specification version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: usize,
+    pub minor: usize,
+}
+impl Version {
+    pub const fn from_raw(raw: usize) -> Self {
+        Self {
+            major: (raw >> 24) & 0x7f,
+            minor: raw & 0xff_ffff,
+        }
+    }
+    pub const fn raw(self) -> usize {
+        (self.major & 0x7f) << 24 | (self.minor & 0xff_ffff)
+    }
+}
+
+pub fn spec_version() -> Result<Version> {
+    call(EID, 0, [0; 6]).map(Version::from_raw)
+}
+pub fn impl_id() -> Result<usize> {
+    call(EID, 1, [0; 6])
+}
+pub fn impl_version() -> Result<usize> {
+    call(EID, 2, [0; 6])
+}
+/// Whether extension `eid` is available.
+pub fn probe_extension(eid: usize) -> bool {
+    call(EID, 3, [eid, 0, 0, 0, 0, 0]).is_ok_and(|available| available != 0)
+}
+pub fn mvendorid() -> Result<usize> {
+    call(EID, 4, [0; 6])
+}
+pub fn marchid() -> Result<usize> {
+    call(EID, 5, [0; 6])
+}
+pub fn mimpid() -> Result<usize> {
+    call(EID, 6, [0; 6])
+}
+
+/// The name of a known SBI implementation.
+pub fn impl_name(id: usize) -> Option<&'static str> {
+    Some(match id {
+        0 => "Berkeley Boot Loader",
+        1 => "OpenSBI",
+        2 => "Xvisor",
+        3 => "KVM",
+        4 => "RustSBI",
+        5 => "Diosix",
+        6 => "Coffer",
+        7 => "Xen Project",
+        8 => "PolarFire Hart Software Services",
+        9 => "coreboot",
+        10 => "oreboot",
+        11 => "bhyve",
+        _ => return None,
+    })
+}
@@ -0,0 +1,23 @@
+//! The debug console extension.
+
+use crate::{call, ecall, Result};
+
+pub const EID: usize = 0x4442_434e;
+
+/// Write up to `len` bytes from physical address `address` to the console,
+/// returning the number written.
+pub fn console_write(address: usize, len: usize) -> Result<usize> {
+    call(EID, 0, [len, address, 0, 0, 0, 0])
+}
+/// Read up to `len` bytes from the console to physical address `address`,
+/// returning the number read.
+///
+/// # Safety
+/// `address` must be valid for writes of `len` bytes.
+pub unsafe fn console_read(address: usize, len: usize) -> Result<usize> {
+    ecall(EID, 1, [len, address, 0, 0, 0, 0])
+}
+/// Write one byte to the console, waiting until it is sent.
+pub fn console_write_byte(byte: u8) -> Result<()> {
+    call(EID, 2, [byte as usize, 0, 0, 0, 0, 0]).map(drop)
+}
@@ -0,0 +1,59 @@
+//! The hart state management extension.
+
+use crate::{ecall, Error, Result};
+
+pub const EID: usize = 0x48_534d;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HartState {
+    Started,
+    Stopped,
+    StartPending,
+    StopPending,
+    Suspended,
+    SuspendPending,
+    ResumePending,
+}
+impl HartState {
+    const fn from_raw(raw: usize) -> Option<Self> {
+        Some(match raw {
+            0 => Self::Started,
+            1 => Self::Stopped,
+            2 => Self::StartPending,
+            3 => Self::StopPending,
+            4 => Self::Suspended,
+            5 => Self::SuspendPending,
+            6 => Self::ResumePending,
+            _ => return None,
+        })
+    }
+}
+
+/// Start `hart_id` in supervisor mode at physical address `start`, with
+/// `a0` holding its hart id and `a1` holding `opaque`.
+///
+/// # Safety
+/// `start` must be code that can run with translation disabled.
+pub unsafe fn hart_start(hart_id: usize, start: usize, opaque: usize) -> Result<()> {
+    ecall(EID, 0, [hart_id, start, opaque, 0, 0, 0]).map(drop)
+}
+/// Stop the current hart, only returning on failure.
+///
+/// # Safety
+/// Nothing may depend on the current hart running again.
+pub unsafe fn hart_stop() -> Error {
+    match ecall(EID, 1, [0; 6]) {
+        Ok(_) => Error::Failed,
+        Err(e) => e,
+    }
+}
+pub fn hart_status(hart_id: usize) -> Result<HartState> {
+    // Safety: querying state has no side effects
+    let raw = unsafe { ecall(EID, 2, [hart_id, 0, 0, 0, 0, 0])? };
+    HartState::from_raw(raw).ok_or(Error::Failed)
+}
+/// Suspend the current hart until an interrupt is pending, keeping its state.
+pub fn hart_retentive_suspend() -> Result<()> {
+    // Safety: a retentive suspend resumes after the call like `wfi`
+    unsafe { ecall(EID, 3, [0; 6]).map(drop) }
+}
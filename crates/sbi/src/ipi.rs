@@ -0,0 +1,10 @@
+//! The inter-processor interrupt extension.
+
+use crate::{call, HartMask, Result};
+
+pub const EID: usize = 0x73_5049;
+
+/// Raise a supervisor software interrupt on each of `harts`.
+pub fn send_ipi(harts: HartMask) -> Result<()> {
+    call(EID, 0, [harts.mask, harts.base, 0, 0, 0, 0]).map(drop)
+}
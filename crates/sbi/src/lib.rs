@@ -0,0 +1,130 @@
+#![no_std]
+//! Calls to the Supervisor Binary Interface.
+//!
+//! Each extension of the SBI v2 specification used by the kernel has its own
+//! module. Addresses passed to the firmware are physical, which the kernel
+//! address space maps to themselves.
+
+pub mod base;
+pub mod dbcn;
+pub mod hsm;
+pub mod ipi;
+pub mod rfence;
+pub mod srst;
+pub mod time;
+
+/// Standard SBI error codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Failed,
+    NotSupported,
+    InvalidParam,
+    Denied,
+    InvalidAddress,
+    AlreadyAvailable,
+    AlreadyStarted,
+    AlreadyStopped,
+    NoShmem,
+    InvalidState,
+    BadRange,
+    Timeout,
+    Io,
+    /// An error code not defined by the specification.
+    Unknown(isize),
+}
+impl Error {
+    pub const fn from_code(code: isize) -> Self {
+        match code {
+            -1 => Self::Failed,
+            -2 => Self::NotSupported,
+            -3 => Self::InvalidParam,
+            -4 => Self::Denied,
+            -5 => Self::InvalidAddress,
+            -6 => Self::AlreadyAvailable,
+            -7 => Self::AlreadyStarted,
+            -8 => Self::AlreadyStopped,
+            -9 => Self::NoShmem,
+            -10 => Self::InvalidState,
+            -11 => Self::BadRange,
+            -12 => Self::Timeout,
+            -13 => Self::Io,
+            code => Self::Unknown(code),
+        }
+    }
+    pub const fn code(self) -> isize {
+        match self {
+            Self::Failed => -1,
+            Self::NotSupported => -2,
+            Self::InvalidParam => -3,
+            Self::Denied => -4,
+            Self::InvalidAddress => -5,
+            Self::AlreadyAvailable => -6,
+            Self::AlreadyStarted => -7,
+            Self::AlreadyStopped => -8,
+            Self::NoShmem => -9,
+            Self::InvalidState => -10,
+            Self::BadRange => -11,
+            Self::Timeout => -12,
+            Self::Io => -13,
+            Self::Unknown(code) => code,
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// A set of harts, as a bitmask of up to `usize::BITS` harts starting from
+/// `base`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HartMask {
+    pub mask: usize,
+    pub base: usize,
+}
+impl HartMask {
+    /// Every hart in the system.
+    pub const ALL: Self = Self { mask: 0, base: usize::MAX };
+
+    pub const fn single(hart_id: usize) -> Self {
+        Self { mask: 1, base: hart_id }
+    }
+}
+
+/// Call function `fid` of extension `eid`.
+///
+/// # Safety
+/// The call must not break any assumptions the kernel makes, such as by
+/// writing to memory in use or stopping the hart.
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+#[inline]
+pub unsafe fn ecall(eid: usize, fid: usize, args: [usize; 6]) -> Result<usize> {
+    let (error, value): (isize, usize);
+    core::arch::asm!(
+        "ecall",
+        inlateout("a0") args[0] => error,
+        inlateout("a1") args[1] => value,
+        in("a2") args[2],
+        in("a3") args[3],
+        in("a4") args[4],
+        in("a5") args[5],
+        in("a6") fid,
+        in("a7") eid,
+        options(nostack),
+    );
+    match error {
+        0 => Ok(value),
+        code => Err(Error::from_code(code)),
+    }
+}
+/// # Safety
+/// The call must not break any assumptions the kernel makes.
+#[cfg(not(any(target_arch = "riscv64", target_arch = "riscv32")))]
+pub unsafe fn ecall(_: usize, _: usize, _: [usize; 6]) -> Result<usize> {
+    Err(Error::NotSupported)
+}
+
+/// Call a function that cannot change kernel state.
+#[inline]
+fn call(eid: usize, fid: usize, args: [usize; 6]) -> Result<usize> {
+    // Safety: only used for functions that have no effect on memory or harts
+    unsafe { ecall(eid, fid, args) }
+}
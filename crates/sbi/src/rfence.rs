@@ -0,0 +1,18 @@
+//! The remote fence extension, for flushing instruction caches and TLBs on
+//! other harts.
+//!
+//! A `size` of 0 or `usize::MAX` flushes the whole address space.
+
+use crate::{call, HartMask, Result};
+
+pub const EID: usize = 0x5246_4e43;
+
+pub fn remote_fence_i(harts: HartMask) -> Result<()> {
+    call(EID, 0, [harts.mask, harts.base, 0, 0, 0, 0]).map(drop)
+}
+pub fn remote_sfence_vma(harts: HartMask, start: usize, size: usize) -> Result<()> {
+    call(EID, 1, [harts.mask, harts.base, start, size, 0, 0]).map(drop)
+}
+pub fn remote_sfence_vma_asid(harts: HartMask, start: usize, size: usize, asid: usize) -> Result<()> {
+    call(EID, 2, [harts.mask, harts.base, start, size, asid, 0]).map(drop)
+}
@@ -0,0 +1,28 @@
+//! The system reset extension.
+
+use crate::{ecall, Error};
+
+pub const EID: usize = 0x5352_5354;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum ResetType {
+    Shutdown = 0,
+    ColdReboot = 1,
+    WarmReboot = 2,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum ResetReason {
+    None = 0,
+    SystemFailure = 1,
+}
+
+/// Reset the system, only returning on failure.
+pub fn system_reset(kind: ResetType, reason: ResetReason) -> Error {
+    // Safety: nothing runs after a successful reset
+    match unsafe { ecall(EID, 0, [kind as usize, reason as usize, 0, 0, 0, 0]) } {
+        Ok(_) => Error::Failed,
+        Err(e) => e,
+    }
+}
@@ -0,0 +1,11 @@
+//! The timer extension.
+
+use crate::{call, Result};
+
+pub const EID: usize = 0x5449_4d45;
+
+/// Raise a supervisor timer interrupt once `time` reaches `deadline`, and
+/// clear any pending one.
+pub fn set_timer(deadline: u64) -> Result<()> {
+    call(EID, 0, [deadline as usize, 0, 0, 0, 0, 0]).map(drop)
+}
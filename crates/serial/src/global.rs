@@ -5,8 +5,9 @@
 
 #![allow(dead_code)]
 
-use core::{cell::UnsafeCell, fmt, mem::MaybeUninit};
-use crate::{uart16550, Serial};
+use core::{fmt, mem::MaybeUninit};
+use ::sync::{IrqSafeSpinLock, IrqSafeSpinLockGuard};
+use crate::{line::{LineEditor, ReadLineError}, uart16550, Error, Serial};
 
 /// The global serial device.
 static GLOBAL: Global = Global::new();
@@ -16,20 +17,65 @@ pub fn global() -> &'static Global {
 
 /// Initialise the global serial device.
 ///
+/// Uses the device at `/chosen/stdout-path` in the device tree if there is a
+/// driver for it, otherwise the first configured `target_device`, or the SBI
+/// debug console when booted from SBI firmware.
+///
 /// Required for the [`print!`] and [`println!`] macros to work correctly.
-pub fn init() {
+pub fn init(fdt: Option<&fdt::Fdt>) {
+    let device = device(fdt);
+    let mut global = GLOBAL.lock();
+    global.inner.device = device;
+    global.inner.interrupts = false;
+}
+
+/// The device [`init`] uses.
+fn device(fdt: Option<&fdt::Fdt>) -> Option<&'static dyn Serial> {
     use crate::sifive_uart;
-    let device = sifive_uart(0)
-        .or_else(|| uart16550(0));
-    let global = GLOBAL.lock();
-    global.0.device = device;
+    fdt.and_then(|fdt| fdt.stdout())
+        .and_then(|(node, _)| crate::probe(&node))
+        .or_else(|| sifive_uart(0))
+        .or_else(|| uart16550(0))
+        .or_else(crate::sbi_console)
+}
+
+/// Switch the global serial device to interrupt-driven I/O, registering
+/// [`interrupt`] as the handler for its interrupt.
+///
+/// Devices without an interrupt, or whose interrupt cannot be registered, stay
+/// polled.
+pub fn enable_interrupts() -> Result<(), ::interrupt::Error> {
+    let Some(irq) = GLOBAL.lock().device().and_then(|device| device.irq()) else {
+        return Ok(());
+    };
+    ::interrupt::register(irq, |_| interrupt())?;
+    let mut global = GLOBAL.lock();
+    global.inner.interrupts = true;
+    global.inner.update_interrupts();
+    Ok(())
+}
+
+/// Service an interrupt raised by the global serial device.
+///
+/// Moves received bytes into the input buffer and sends queued output.
+pub fn interrupt() {
+    let mut global = GLOBAL.lock();
+    global.inner.receive();
+    global.inner.transmit();
+    global.inner.update_interrupts();
 }
 
+/// Capacity of each [`CircularBuffer`]. Must divide `u16::MAX + 1`.
+const BUFFER_SIZE: usize = 4096;
+
 struct CircularBuffer {
-    buffer: [MaybeUninit<u8>; 4096],
+    buffer: [MaybeUninit<u8>; BUFFER_SIZE],
     /// Buffer head index. The next byte to read.
     head: u16,
-    /// BUffer tail index. The last byte that can be read.
+    /// Buffer tail index. The next byte to write.
+    ///
+    /// Both indices wrap at `u16::MAX` rather than the buffer length, so the
+    /// buffer is full rather than empty when they are `BUFFER_SIZE` apart.
     tail: u16,
 }
 impl CircularBuffer {
@@ -40,57 +86,232 @@ impl CircularBuffer {
             tail: 0,
         }
     }
+    #[inline]
+    fn len(&self) -> usize {
+        self.tail.wrapping_sub(self.head) as usize
+    }
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.head == self.tail
+    }
+    #[inline]
+    fn is_full(&self) -> bool {
+        self.len() == BUFFER_SIZE
+    }
+    /// Append a byte, returning it back if the buffer is full.
+    fn push(&mut self, byte: u8) -> Result<(), u8> {
+        if self.is_full() {
+            return Err(byte);
+        }
+        self.buffer[self.tail as usize % BUFFER_SIZE].write(byte);
+        self.tail = self.tail.wrapping_add(1);
+        Ok(())
+    }
+    fn peek(&self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        Some(unsafe { self.buffer[self.head as usize % BUFFER_SIZE].assume_init() })
+    }
+    fn pop(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.head = self.head.wrapping_add(1);
+        Some(byte)
+    }
 }
 
-pub struct Global(UnsafeCell<GlobalInner>);
+pub struct Global {
+    inner: IrqSafeSpinLock<GlobalInner>,
+}
 impl Global {
     const fn new() -> Self {
-        Self(UnsafeCell::new(GlobalInner {
-            device: None,
-            input: CircularBuffer::new(),
-            output: CircularBuffer::new(),
-        }))
-    }
-    pub fn lock(&self) -> GlobalGuard {
-        // no locking for now
-        unsafe {
-            GlobalGuard(&mut *self.0.get())
+        Self {
+            inner: IrqSafeSpinLock::new(GlobalInner {
+                device: None,
+                input: CircularBuffer::new(),
+                output: CircularBuffer::new(),
+                interrupts: false,
+                skip_line_feed: false,
+            }),
         }
     }
-    pub fn try_lock(&self) -> Option<GlobalGuard> {
-        // no locking for now
-        Some(self.lock())
+    /// Lock the serial device, spinning until it is available.
+    ///
+    /// Interrupts are masked on the current hart while the guard is held.
+    ///
+    /// # Panics
+    /// Panics if the current hart already holds the lock.
+    pub fn lock(&self) -> GlobalGuard<'_> {
+        GlobalGuard { inner: self.inner.lock() }
+    }
+    /// Lock the serial device if it is not already held.
+    pub fn try_lock(&self) -> Option<GlobalGuard<'_>> {
+        Some(GlobalGuard { inner: self.inner.try_lock()? })
+    }
+    /// Whether the current hart holds the lock.
+    pub fn is_held(&self) -> bool {
+        self.inner.is_held()
+    }
+    /// Lock the serial device, taking it over if the current hart already
+    /// holds the lock, or another hart does not let go of it.
+    ///
+    /// # Safety
+    /// Only for use on paths that never return to the code that may hold the
+    /// lock, and once other harts are stopped or stuck, such as the panic
+    /// handler.
+    pub unsafe fn force_lock(&self) -> GlobalGuard<'_> {
+        GlobalGuard { inner: self.inner.force_lock() }
     }
 }
-unsafe impl Sync for Global {}
 struct GlobalInner {
-    // lock: Spinlock,
     device: Option<&'static dyn Serial>,
     input: CircularBuffer,
     output: CircularBuffer,
+    /// Whether the device interrupts are routed to [`interrupt`].
+    interrupts: bool,
+    /// The last line read ended with a carriage return.
+    skip_line_feed: bool,
+}
+// Safety: the device is only used under the lock
+unsafe impl Send for GlobalInner {}
+impl GlobalInner {
+    /// Move as many bytes as the device will accept from the output buffer.
+    ///
+    /// Returns true if the output buffer was drained.
+    fn transmit(&mut self) -> bool {
+        let Some(serial) = self.device else {
+            return self.output.is_empty();
+        };
+        while let Some(byte) = self.output.peek() {
+            if serial.write_byte(byte).is_err() {
+                return false;
+            }
+            self.output.pop();
+        }
+        true
+    }
+    /// Move all bytes the device has ready into the input buffer.
+    ///
+    /// Bytes are dropped once the input buffer is full.
+    fn receive(&mut self) {
+        let Some(serial) = self.device else {
+            return;
+        };
+        while let Ok(byte) = serial.read_byte() {
+            let _ = self.input.push(byte);
+        }
+    }
+    /// Enable the transmit interrupt only while there is output waiting.
+    fn update_interrupts(&self) {
+        let Some(serial) = self.device else {
+            return;
+        };
+        if self.interrupts {
+            serial.set_interrupts(true, !self.output.is_empty());
+        }
+    }
+}
+pub struct GlobalGuard<'a> {
+    inner: IrqSafeSpinLockGuard<'a, GlobalInner>,
 }
-pub struct GlobalGuard<'a>(&'a mut GlobalInner);
 impl<'a> GlobalGuard<'a> {
     #[inline]
     pub fn device(&self) -> Option<&'static dyn Serial> {
-        self.0.device
+        self.inner.device
+    }
+    /// Use the device [`init`] would without a device tree, unless there is
+    /// already one.
+    ///
+    /// For reporting before [`init`] may have run, without replacing the
+    /// device it chose.
+    pub fn ensure_device(&mut self) {
+        if self.inner.device.is_none() {
+            self.inner.device = device(None);
+        }
     }
     #[inline]
     pub fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> fmt::Result {
         <Self as fmt::Write>::write_fmt(self, args)
     }
+    /// Queue `bytes` for output, sending as many as the device is ready for.
+    ///
+    /// Blocks only while the output buffer is full.
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if self.inner.device.is_none() {
+            return Err(Error::NoDevice);
+        }
+        for &byte in bytes {
+            while self.inner.output.is_full() {
+                if !self.inner.transmit() {
+                    core::hint::spin_loop();
+                }
+            }
+            let _ = self.inner.output.push(byte);
+        }
+        self.inner.transmit();
+        self.inner.update_interrupts();
+        Ok(())
+    }
+    /// Block until all queued output has been sent to the device.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if self.inner.device.is_none() {
+            return Err(Error::NoDevice);
+        }
+        while !self.inner.transmit() {
+            core::hint::spin_loop();
+        }
+        Ok(())
+    }
+    /// Read a single byte if one has been received.
+    pub fn read_byte(&mut self) -> Option<u8> {
+        self.inner.receive();
+        self.inner.input.pop()
+    }
+    /// Read received bytes into `buffer` without blocking.
+    ///
+    /// Returns the number of bytes read.
+    pub fn read<B: AsUninitBuffer + ?Sized>(&mut self, buffer: &mut B) -> usize {
+        self.inner.receive();
+        let (ptr, len) = buffer.as_uninit_buffer();
+        let mut i = 0;
+        while i < len {
+            let Some(byte) = self.inner.input.pop() else {
+                break;
+            };
+            unsafe { ptr.add(i).write(byte) };
+            i += 1;
+        }
+        i
+    }
+    /// Read a line into `buffer`, echoing it back, blocking until the line is
+    /// complete.
+    ///
+    /// Holds the lock for the whole line, so prefer [`crate::read_line`]
+    /// unless other output must be held back.
+    pub fn read_line<'b>(&mut self, buffer: &'b mut [u8]) -> Result<&'b str, ReadLineError> {
+        if self.inner.device.is_none() {
+            return Err(ReadLineError::NoDevice);
+        }
+        let mut editor = LineEditor::new(buffer);
+        loop {
+            if let Some(result) = editor.edit(self) {
+                return result.map(|()| editor.into_str());
+            }
+            core::hint::spin_loop();
+        }
+    }
+    #[inline]
+    pub(crate) fn interrupt_driven(&self) -> bool {
+        self.inner.interrupts
+    }
+    #[inline]
+    pub(crate) fn skip_line_feed(&mut self) -> &mut bool {
+        &mut self.inner.skip_line_feed
+    }
 }
 impl<'a> fmt::Write for GlobalGuard<'a> {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        let Some(serial) = self.0.device else {
-            return Err(fmt::Error)
-        };
-        for byte in s.as_bytes() {
-            if let Err(_) = serial.write_byte(*byte) {
-                return Err(fmt::Error);
-            };
-        }
-        Ok(())
+        self.write(s.as_bytes()).map_err(|_| fmt::Error)
     }
 }
 
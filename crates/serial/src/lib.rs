@@ -3,7 +3,9 @@
 #![feature(allow_internal_unstable)]
 
 mod global;
-pub use global::{global, init, print_fmt};
+mod line;
+pub use global::{enable_interrupts, global, init, interrupt, print_fmt, AsUninitBuffer};
+pub use line::{read, read_line, ReadLineError};
 
 pub mod prelude {
     pub use crate::{print, println};
@@ -27,9 +29,12 @@ macro_rules! println {
     }};
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Error {
     /// The serial device is not ready to send or recieve more data.
     Busy,
+    /// There is no serial device to use.
+    NoDevice,
 }
 
 pub trait Serial {
@@ -70,17 +75,55 @@ pub trait Serial {
         }
         (i, Ok(()))
     }
+
+    /// The platform interrupt number raised by the device, if it has one.
+    #[inline]
+    fn irq(&self) -> Option<u32> {
+        None
+    }
+    /// Enable or disable the receive and transmit interrupts.
+    ///
+    /// The receive interrupt is raised while data is ready to read, and the
+    /// transmit interrupt while the device can accept more data.
+    #[inline]
+    fn set_interrupts(&self, _receive: bool, _transmit: bool) {}
 }
 
-#[cfg(target_device = "sifive_uart")]
+/// Find a driver for the device tree node of a serial device.
+pub fn probe(node: &fdt::Node) -> Option<&'static dyn Serial> {
+    if !node.is_enabled() {
+        return None;
+    }
+    let address = node.reg().next()?.address as usize;
+    let irq = node.interrupts().next();
+    // Safety: the device tree describes a compatible device at `address`
+    node.compatible().find_map(|compatible| unsafe {
+        match compatible {
+            "sifive,uart0" => sifive_uart_at(address, irq),
+            "ns16550a" | "ns16550" => uart16550_at(address, irq),
+            _ => None,
+        }
+    })
+}
+
+// Both drivers are always built for devices found in the device tree; the
+// configured `target_device` only picks the fallback without one.
 pub mod sifive_uart;
+pub use sifive_uart::sifive_uart_at;
 #[cfg(target_device = "sifive_uart")]
 pub use sifive_uart::sifive_uart;
 #[cfg(not(target_device = "sifive_uart"))]
 pub fn sifive_uart(_: usize) -> Option<&'static dyn Serial> { None }
 
-#[cfg(target_device = "uart16550")]
+#[cfg(target_boot = "sbi")]
+pub mod sbi_console;
+#[cfg(target_boot = "sbi")]
+pub use sbi_console::sbi_console;
+#[cfg(not(target_boot = "sbi"))]
+pub fn sbi_console() -> Option<&'static dyn Serial> { None }
+
 pub mod uart16550;
+pub use uart16550::uart16550_at;
 #[cfg(target_device = "uart16550")]
 pub use uart16550::uart16550;
 #[cfg(not(target_device = "uart16550"))]
@@ -0,0 +1,153 @@
+//! Line input from the console with echo and basic editing.
+
+use crate::global::GlobalGuard;
+
+const CTRL_C: u8 = 0x03;
+const BACKSPACE: u8 = 0x08;
+const CTRL_U: u8 = 0x15;
+const ESCAPE: u8 = 0x1b;
+const DELETE: u8 = 0x7f;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadLineError {
+    /// The user cancelled the line with Ctrl-C.
+    Interrupted,
+    /// There is no serial device to read from.
+    NoDevice,
+}
+
+/// The state of a line being read into a buffer.
+pub(crate) struct LineEditor<'b> {
+    buffer: &'b mut [u8],
+    len: usize,
+    /// Within an escape sequence, which is ignored.
+    escape: Escape,
+}
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// After `ESC`.
+    Start,
+    /// After `ESC [`, until the final byte.
+    Csi,
+}
+impl<'b> LineEditor<'b> {
+    pub(crate) fn new(buffer: &'b mut [u8]) -> Self {
+        Self {
+            buffer,
+            len: 0,
+            escape: Escape::None,
+        }
+    }
+    /// Process all received input.
+    ///
+    /// Returns `None` if the line is not yet complete.
+    pub(crate) fn edit(&mut self, serial: &mut GlobalGuard) -> Option<Result<(), ReadLineError>> {
+        while let Some(byte) = serial.read_byte() {
+            // a line feed directly after a carriage return ends no line
+            if core::mem::take(serial.skip_line_feed()) && byte == b'\n' {
+                continue;
+            }
+            match (self.escape, byte) {
+                (Escape::None, ESCAPE) => self.escape = Escape::Start,
+                (Escape::Start, b'[') => self.escape = Escape::Csi,
+                (Escape::Start, _) => self.escape = Escape::None,
+                (Escape::Csi, 0x40..=0x7e) => self.escape = Escape::None,
+                (Escape::Csi, _) => (),
+                (Escape::None, b'\r' | b'\n') => {
+                    *serial.skip_line_feed() = byte == b'\r';
+                    let _ = serial.write(b"\r\n");
+                    return Some(Ok(()));
+                },
+                (Escape::None, CTRL_C) => {
+                    let _ = serial.write(b"^C\r\n");
+                    self.len = 0;
+                    return Some(Err(ReadLineError::Interrupted));
+                },
+                (Escape::None, BACKSPACE | DELETE) => if self.len > 0 {
+                    self.len -= 1;
+                    let _ = serial.write(b"\x08 \x08");
+                },
+                (Escape::None, CTRL_U) => while self.len > 0 {
+                    self.len -= 1;
+                    let _ = serial.write(b"\x08 \x08");
+                },
+                (Escape::None, b' '..=b'~') => if self.len < self.buffer.len() {
+                    self.buffer[self.len] = byte;
+                    self.len += 1;
+                    let _ = serial.write(&[byte]);
+                },
+                (Escape::None, _) => (),
+            }
+        }
+        None
+    }
+    pub(crate) fn into_str(self) -> &'b str {
+        let line = &self.buffer[..self.len];
+        // Safety: only printable ASCII is accepted into the buffer
+        unsafe { core::str::from_utf8_unchecked(line) }
+    }
+}
+
+/// Read a line from the console into `buffer`, echoing it back.
+///
+/// The global serial device is only locked while processing input, and the
+/// hart waits for an interrupt between keystrokes when the device is
+/// interrupt-driven. Input beyond the length of `buffer` is discarded.
+pub fn read_line(buffer: &mut [u8]) -> Result<&str, ReadLineError> {
+    let mut editor = LineEditor::new(buffer);
+    loop {
+        let interrupts = ::sync::irq::disable();
+        let mut serial = crate::global().lock();
+        let result = match serial.device() {
+            Some(_) => editor.edit(&mut serial),
+            None => Some(Err(ReadLineError::NoDevice)),
+        };
+        let interrupt_driven = serial.interrupt_driven();
+        drop(serial);
+        if let Some(result) = result {
+            ::sync::irq::restore(interrupts);
+            return result.map(|()| editor.into_str());
+        }
+        // with interrupts masked, a keystroke arriving now still wakes the hart
+        if interrupts && interrupt_driven {
+            ::sync::irq::wait();
+        } else {
+            core::hint::spin_loop();
+        }
+        ::sync::irq::restore(interrupts);
+    }
+}
+
+/// Read at least one byte of console input into `buffer` without echoing it,
+/// waiting for input as [`read_line`] does.
+///
+/// Returns the number of bytes read, which is only zero for an empty buffer.
+pub fn read(buffer: &mut [u8]) -> Result<usize, crate::Error> {
+    if buffer.is_empty() {
+        return Ok(0);
+    }
+    loop {
+        let interrupts = ::sync::irq::disable();
+        let mut serial = crate::global().lock();
+        if serial.device().is_none() {
+            drop(serial);
+            ::sync::irq::restore(interrupts);
+            return Err(crate::Error::NoDevice);
+        }
+        let read = serial.read(&mut *buffer);
+        let interrupt_driven = serial.interrupt_driven();
+        drop(serial);
+        if read > 0 {
+            ::sync::irq::restore(interrupts);
+            return Ok(read);
+        }
+        // with interrupts masked, a keystroke arriving now still wakes the hart
+        if interrupts && interrupt_driven {
+            ::sync::irq::wait();
+        } else {
+            core::hint::spin_loop();
+        }
+        ::sync::irq::restore(interrupts);
+    }
+}
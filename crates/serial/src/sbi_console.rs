@@ -0,0 +1,34 @@
+//! The SBI debug console, for when the kernel has no driver for the UART.
+
+use crate::{Error, Serial};
+
+static CONSOLE: SbiConsole = SbiConsole;
+
+/// Use the debug console of the SBI firmware, if it has one.
+pub fn sbi_console() -> Option<&'static dyn Serial> {
+    sbi::base::probe_extension(sbi::dbcn::EID).then_some(&CONSOLE)
+}
+
+struct SbiConsole;
+impl Serial for SbiConsole {
+    fn read_byte(&self) -> Result<u8, Error> {
+        let mut byte = 0;
+        // Safety: kernel memory is mapped to itself, so the address of `byte`
+        // is also its physical address
+        match unsafe { sbi::dbcn::console_read(&mut byte as *mut u8 as usize, 1) } {
+            Ok(1) => Ok(byte),
+            Ok(_) => Err(Error::Busy),
+            Err(_) => Err(Error::NoDevice),
+        }
+    }
+    fn write_byte(&self, byte: u8) -> Result<(), Error> {
+        sbi::dbcn::console_write_byte(byte).map_err(|_| Error::NoDevice)
+    }
+    fn write(&self, bytes: &[u8]) -> (usize, Result<(), Error>) {
+        match sbi::dbcn::console_write(bytes.as_ptr() as usize, bytes.len()) {
+            Ok(written) if written == bytes.len() => (written, Ok(())),
+            Ok(written) => (written, Err(Error::Busy)),
+            Err(_) => (0, Err(Error::NoDevice)),
+        }
+    }
+}
@@ -1,10 +1,16 @@
+use core::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
 use crate::Serial;
 
-// Safety: this module is only enabled if the `sifive_uart` device is enabled,
-// which always has the UART at these addresses.
-const UART0: Uart = unsafe { Uart::at_address(0x10010000) };
-const UART1: Uart = unsafe { Uart::at_address(0x10011000) };
+// Safety: these are only built if the `sifive_uart` device is enabled, which
+// always has the UART at these addresses.
+#[cfg(target_device = "sifive_uart")]
+static UART0: Uart = Uart::new(0x10010000 as *mut u32, 4);
+#[cfg(target_device = "sifive_uart")]
+static UART1: Uart = Uart::new(0x10011000 as *mut u32, 5);
+/// A UART found at runtime.
+static PROBED: Uart = Uart::new(core::ptr::null_mut(), 0);
 
+#[cfg(target_device = "sifive_uart")]
 pub fn sifive_uart(num: usize) -> Option<&'static dyn Serial> {
     // Safety: the target machine has been configured to have a compatible
     // UART at the correct addresses.
@@ -15,17 +21,38 @@ pub fn sifive_uart(num: usize) -> Option<&'static dyn Serial> {
     }
 }
 
-struct Uart(*mut u32);
+/// Use the UART at `address` that raises PLIC interrupt `irq`.
+///
+/// # Safety
+/// There must be a SiFive UART at `address`.
+pub unsafe fn sifive_uart_at(address: usize, irq: Option<u32>) -> Option<&'static dyn Serial> {
+    PROBED.base.store(address as *mut u32, Ordering::Relaxed);
+    PROBED.irq.store(irq.unwrap_or(0), Ordering::Relaxed);
+    Some(&PROBED)
+}
+
+struct Uart {
+    base: AtomicPtr<u32>,
+    /// The PLIC interrupt raised by the UART, or 0 if it has none.
+    irq: AtomicU32,
+}
 impl Uart {
     #[inline]
-    const unsafe fn at_address(address: usize) -> Self {
-        Uart(address as *mut u32)
+    const fn new(base: *mut u32, irq: u32) -> Self {
+        Uart {
+            base: AtomicPtr::new(base),
+            irq: AtomicU32::new(irq),
+        }
+    }
+    #[inline]
+    fn base(&self) -> *mut u32 {
+        self.base.load(Ordering::Relaxed)
     }
 }
 impl Serial for Uart {
     fn read_byte(&self) -> Result<u8, crate::Error> {
         unsafe {
-            let rxdata = self.0.add(1);
+            let rxdata = self.base().add(1);
             let data = rxdata.read_volatile();
             if data & 0x8000_0000 != 0 {
                 return Err(crate::Error::Busy);
@@ -35,7 +62,7 @@ impl Serial for Uart {
     }
     fn write_byte(&self, byte: u8) -> Result<(), crate::Error> {
         unsafe {
-            let txdata = self.0;
+            let txdata = self.base();
             if txdata.read_volatile() & 0x8000_0000 != 0 {
                 return Err(crate::Error::Busy);
             }
@@ -43,4 +70,21 @@ impl Serial for Uart {
             Ok(())
         }
     }
+    fn irq(&self) -> Option<u32> {
+        match self.irq.load(Ordering::Relaxed) {
+            0 => None,
+            irq => Some(irq),
+        }
+    }
+    fn set_interrupts(&self, receive: bool, transmit: bool) {
+        unsafe {
+            let base = self.base();
+            // txctrl: enable, watermark when the FIFO is empty
+            base.add(2).write_volatile(1 << 16 | 1);
+            // rxctrl: enable, watermark (rxcnt of 0) when the FIFO is not empty
+            base.add(3).write_volatile(1);
+            // ie: txwm, rxwm
+            base.add(4).write_volatile((transmit as u32) | (receive as u32) << 1);
+        }
+    }
 }
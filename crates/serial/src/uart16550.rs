@@ -1,7 +1,13 @@
+use core::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
 use crate::Serial;
 
-const UART0: Uart = unsafe { Uart::at_address(0x1000_0000) };
+/// The UART on the `qemu-virt` machine.
+#[cfg(target_device = "uart16550")]
+static UART0: Uart = Uart::new(0x1000_0000 as *mut u8, 10);
+/// A UART found at runtime.
+static PROBED: Uart = Uart::new(core::ptr::null_mut(), 0);
 
+#[cfg(target_device = "uart16550")]
 pub fn uart16550(num: usize) -> Option<&'static dyn Serial> {
     match num {
         0 => Some(unsafe { UART0.init() }),
@@ -9,31 +15,53 @@ pub fn uart16550(num: usize) -> Option<&'static dyn Serial> {
     }
 }
 
-struct Uart(*mut u8);
+/// Use the UART at `address` that raises PLIC interrupt `irq`.
+///
+/// # Safety
+/// There must be a 16550 compatible UART at `address`.
+pub unsafe fn uart16550_at(address: usize, irq: Option<u32>) -> Option<&'static dyn Serial> {
+    PROBED.base.store(address as *mut u8, Ordering::Relaxed);
+    PROBED.irq.store(irq.unwrap_or(0), Ordering::Relaxed);
+    Some(PROBED.init())
+}
+
+struct Uart {
+    base: AtomicPtr<u8>,
+    /// The PLIC interrupt raised by the UART, or 0 if it has none.
+    irq: AtomicU32,
+}
 impl Uart {
     #[inline]
-    const unsafe fn at_address(address: usize) -> Self {
-        Uart(address as *mut u8)
+    const fn new(base: *mut u8, irq: u32) -> Self {
+        Uart {
+            base: AtomicPtr::new(base),
+            irq: AtomicU32::new(irq),
+        }
+    }
+    #[inline]
+    fn base(&self) -> *mut u8 {
+        self.base.load(Ordering::Relaxed)
     }
     unsafe fn init(&self) -> &Self {
+        let base = self.base();
         // set LCR - line control register
         let lcr = 0b0000_0011;
-        self.0.add(3).write_volatile(lcr);
+        base.add(3).write_volatile(lcr);
         // set FCR - FIFO control register
-        self.0.add(2).write_volatile(0b0000_0001);
+        base.add(2).write_volatile(0b0000_0001);
         // set IER - interrupt enable register
-        self.0.add(1).write_volatile(0b0000_0001);
+        base.add(1).write_volatile(0b0000_0001);
 
         // set divisor latch access
-        self.0.add(3).write_volatile(lcr | 0b1000_0000);
+        base.add(3).write_volatile(lcr | 0b1000_0000);
 
         let [div_low, div_high] = 592u16.to_ne_bytes();
         // write divisor
-        self.0.add(0).write_volatile(div_low);
-        self.0.add(1).write_volatile(div_high);
+        base.add(0).write_volatile(div_low);
+        base.add(1).write_volatile(div_high);
 
         // unset divisor latch access
-        self.0.add(3).write_volatile(lcr);
+        base.add(3).write_volatile(lcr);
 
         self
     }
@@ -41,18 +69,34 @@ impl Uart {
 impl Serial for Uart {
     fn read_byte(&self) -> Result<u8, crate::Error> {
         unsafe {
+            let base = self.base();
             // data ready bit unset
-            if self.0.add(5).read_volatile() & 1 == 0 {
+            if base.add(5).read_volatile() & 1 == 0 {
                 return Err(crate::Error::Busy);
             }
-            Ok(self.0.add(0).read_volatile() as u8)
+            Ok(base.add(0).read_volatile())
         }
     }
     fn write_byte(&self, byte: u8) -> Result<(), crate::Error> {
         unsafe {
-            self.0.add(0).write_volatile(byte);
+            let base = self.base();
+            // transmit holding register empty bit unset
+            if base.add(5).read_volatile() & 0b10_0000 == 0 {
+                return Err(crate::Error::Busy);
+            }
+            base.add(0).write_volatile(byte);
             Ok(())
         }
     }
+    fn irq(&self) -> Option<u32> {
+        match self.irq.load(Ordering::Relaxed) {
+            0 => None,
+            irq => Some(irq),
+        }
+    }
+    fn set_interrupts(&self, receive: bool, transmit: bool) {
+        // set IER - interrupt enable register
+        let ier = (receive as u8) | (transmit as u8) << 1;
+        unsafe { self.base().add(1).write_volatile(ier) }
+    }
 }
-
@@ -0,0 +1,48 @@
+//! Masking supervisor interrupts on the current hart.
+
+/// Mask interrupts, returning whether they were previously enabled.
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+#[inline]
+pub fn disable() -> bool {
+    let sstatus: usize;
+    unsafe { core::arch::asm!("csrrci {}, sstatus, 0b10", out(reg) sstatus) };
+    sstatus & 0b10 != 0
+}
+/// Unmask interrupts if `enabled`, as returned by [`disable`].
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+#[inline]
+pub fn restore(enabled: bool) {
+    if enabled {
+        unsafe { core::arch::asm!("csrsi sstatus, 0b10") };
+    }
+}
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+#[inline]
+pub fn are_enabled() -> bool {
+    let sstatus: usize;
+    unsafe { core::arch::asm!("csrr {}, sstatus", out(reg) sstatus) };
+    sstatus & 0b10 != 0
+}
+/// Idle the hart until an interrupt is pending, even if it is masked.
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+#[inline]
+pub fn wait() {
+    unsafe { core::arch::asm!("wfi") };
+}
+
+#[cfg(not(any(target_arch = "riscv64", target_arch = "riscv32")))]
+pub fn disable() -> bool { false }
+#[cfg(not(any(target_arch = "riscv64", target_arch = "riscv32")))]
+pub fn restore(_: bool) {}
+#[cfg(not(any(target_arch = "riscv64", target_arch = "riscv32")))]
+pub fn are_enabled() -> bool { false }
+#[cfg(not(any(target_arch = "riscv64", target_arch = "riscv32")))]
+pub fn wait() {}
+
+/// Run `f` with interrupts masked.
+pub fn without<R>(f: impl FnOnce() -> R) -> R {
+    let enabled = disable();
+    let result = f();
+    restore(enabled);
+    result
+}
@@ -0,0 +1,144 @@
+//! A ticket spinlock that masks interrupts while held.
+//!
+//! Tracks the owning hart so that re-entry from the same hart can be detected
+//! rather than deadlocking.
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use crate::irq;
+
+/// The lock is not owned by any hart.
+const UNOWNED: usize = usize::MAX;
+/// How many times [`IrqSafeSpinLock::force_lock`] checks for another hart to
+/// let go of the lock before taking it over.
+const TAKEOVER_SPINS: u32 = 1 << 24;
+
+/// Grants access in the order harts asked for it, with interrupts masked on
+/// the holding hart so that its interrupt handlers cannot deadlock on it.
+pub struct IrqSafeSpinLock<T: ?Sized> {
+    /// The next ticket to hand out.
+    next: AtomicU32,
+    /// The ticket currently allowed to hold the lock.
+    serving: AtomicU32,
+    /// The hart holding the lock, or [`UNOWNED`].
+    owner: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+unsafe impl<T: ?Sized + Send> Sync for IrqSafeSpinLock<T> {}
+unsafe impl<T: ?Sized + Send> Send for IrqSafeSpinLock<T> {}
+impl<T> IrqSafeSpinLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            next: AtomicU32::new(0),
+            serving: AtomicU32::new(0),
+            owner: AtomicUsize::new(UNOWNED),
+            value: UnsafeCell::new(value),
+        }
+    }
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+impl<T: ?Sized> IrqSafeSpinLock<T> {
+    /// Acquire the lock, spinning until it is available.
+    ///
+    /// # Panics
+    /// Panics if the lock is already held by the current hart.
+    pub fn lock(&self) -> IrqSafeSpinLockGuard<'_, T> {
+        let interrupts = irq::disable();
+        let hart = hart::id();
+        if self.owner.load(Ordering::Relaxed) == hart {
+            irq::restore(interrupts);
+            panic!("spinlock re-entered on hart {hart}");
+        }
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        while self.serving.load(Ordering::Acquire) != ticket {
+            core::hint::spin_loop();
+        }
+        self.owner.store(hart, Ordering::Relaxed);
+        IrqSafeSpinLockGuard { lock: self, held: true, interrupts }
+    }
+    /// Acquire the lock only if no other hart holds or is waiting on it.
+    pub fn try_lock(&self) -> Option<IrqSafeSpinLockGuard<'_, T>> {
+        let interrupts = irq::disable();
+        let serving = self.serving.load(Ordering::Relaxed);
+        match self.next.compare_exchange(serving, serving.wrapping_add(1), Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => {
+                self.owner.store(hart::id(), Ordering::Relaxed);
+                Some(IrqSafeSpinLockGuard { lock: self, held: true, interrupts })
+            },
+            Err(_) => {
+                irq::restore(interrupts);
+                None
+            },
+        }
+    }
+    /// Acquire the lock, or take it over if the current hart already holds
+    /// it, or another hart does not let go of it after spinning for a while.
+    ///
+    /// Harts waiting for the lock when it is taken over from another hart
+    /// never get it.
+    ///
+    /// # Safety
+    /// The original holder must never run again while the returned guard is
+    /// in use, such as when the current hart is panicking and has stopped the
+    /// others, or they are stuck with interrupts masked.
+    pub unsafe fn force_lock(&self) -> IrqSafeSpinLockGuard<'_, T> {
+        let interrupts = irq::disable();
+        let hart = hart::id();
+        if self.owner.load(Ordering::Relaxed) == hart {
+            return IrqSafeSpinLockGuard { lock: self, held: false, interrupts };
+        }
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut spins = 0;
+        while self.serving.load(Ordering::Acquire) != ticket {
+            if spins == TAKEOVER_SPINS {
+                // skip the holder and the harts waiting before this one
+                self.serving.swap(ticket, Ordering::AcqRel);
+                break;
+            }
+            spins += 1;
+            core::hint::spin_loop();
+        }
+        self.owner.store(hart, Ordering::Relaxed);
+        IrqSafeSpinLockGuard { lock: self, held: true, interrupts }
+    }
+    /// Whether the current hart holds the lock.
+    pub fn is_held(&self) -> bool {
+        self.owner.load(Ordering::Relaxed) == hart::id()
+    }
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+pub struct IrqSafeSpinLockGuard<'a, T: ?Sized> {
+    lock: &'a IrqSafeSpinLock<T>,
+    /// Whether this guard acquired the lock, rather than taking it over with
+    /// [`IrqSafeSpinLock::force_lock`].
+    held: bool,
+    /// Whether interrupts were enabled before the lock was taken.
+    interrupts: bool,
+}
+impl<T: ?Sized> Deref for IrqSafeSpinLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+impl<T: ?Sized> DerefMut for IrqSafeSpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+impl<T: ?Sized> Drop for IrqSafeSpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.held {
+            self.lock.owner.store(UNOWNED, Ordering::Relaxed);
+            self.lock.serving.fetch_add(1, Ordering::Release);
+        }
+        irq::restore(self.interrupts);
+    }
+}
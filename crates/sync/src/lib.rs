@@ -0,0 +1,24 @@
+#![no_std]
+//! Kernel synchronisation primitives.
+//!
+//! [`SpinLock`] and [`RwLock`] spin for short critical sections, and
+//! [`IrqSafeSpinLock`] also masks interrupts for state shared with interrupt
+//! handlers. [`Mutex`] blocks on a [`WaitQueue`], in the scheduler installed
+//! with [`set_scheduler`], or by spinning until then.
+
+extern crate alloc;
+
+pub mod irq;
+mod irq_safe;
+mod mutex;
+mod once;
+mod rwlock;
+mod spin;
+mod wait;
+
+pub use irq_safe::{IrqSafeSpinLock, IrqSafeSpinLockGuard};
+pub use mutex::{Mutex, MutexGuard};
+pub use once::{Lazy, Once};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use spin::{SpinLock, SpinLockGuard};
+pub use wait::{set_scheduler, Scheduler, WaitQueue};
@@ -0,0 +1,106 @@
+//! One-time initialisation.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+const INCOMPLETE: u8 = 0;
+const RUNNING: u8 = 1;
+const COMPLETE: u8 = 2;
+
+/// A value initialised by the first caller of [`Once::call_once`], which
+/// every other caller waits for.
+pub struct Once<T> {
+    state: AtomicU8,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+unsafe impl<T: Send> Send for Once<T> {}
+impl<T> Once<T> {
+    pub const fn new() -> Self {
+        Self { state: AtomicU8::new(INCOMPLETE), value: UnsafeCell::new(MaybeUninit::uninit()) }
+    }
+    /// The value, initialised with `f` if no other caller has.
+    ///
+    /// If `f` panics, the next caller initialises the value instead. `f` must
+    /// not call `call_once` on the same `Once`, which would wait for itself
+    /// forever.
+    pub fn call_once(&self, f: impl FnOnce() -> T) -> &T {
+        loop {
+            match self.state.compare_exchange(INCOMPLETE, RUNNING, Ordering::Acquire, Ordering::Acquire) {
+                Ok(_) => {
+                    let reset = Reset(&self.state);
+                    let value = f();
+                    // Safety: only the caller that moved the state to running
+                    // writes the value
+                    unsafe { (*self.value.get()).write(value) };
+                    core::mem::forget(reset);
+                    self.state.store(COMPLETE, Ordering::Release);
+                    break;
+                },
+                Err(COMPLETE) => break,
+                Err(_) => core::hint::spin_loop(),
+            }
+        }
+        // Safety: the value is written before the state completes
+        unsafe { (*self.value.get()).assume_init_ref() }
+    }
+    pub fn get(&self) -> Option<&T> {
+        // Safety: as above
+        self.is_completed().then(|| unsafe { (*self.value.get()).assume_init_ref() })
+    }
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+}
+impl<T> Default for Once<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T> Drop for Once<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == COMPLETE {
+            // Safety: the value was written
+            unsafe { self.value.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+/// Returns a [`Once`] to incomplete if its initialiser unwinds.
+struct Reset<'a>(&'a AtomicU8);
+impl Drop for Reset<'_> {
+    fn drop(&mut self) {
+        self.0.store(INCOMPLETE, Ordering::Release);
+    }
+}
+
+/// A value initialised on first use.
+pub struct Lazy<T, F = fn() -> T> {
+    once: Once<T>,
+    init: UnsafeCell<Option<F>>,
+}
+unsafe impl<T: Send + Sync, F: Send> Sync for Lazy<T, F> {}
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    pub const fn new(init: F) -> Self {
+        Self { once: Once::new(), init: UnsafeCell::new(Some(init)) }
+    }
+    /// The value, initialising it if this is the first use.
+    ///
+    /// # Panics
+    /// Panics if an earlier initialisation panicked.
+    pub fn force(this: &Self) -> &T {
+        this.once.call_once(|| {
+            // Safety: only the one caller initialising the value takes `init`
+            let init = unsafe { (*this.init.get()).take() };
+            init.expect("Lazy initialiser already taken")()
+        })
+    }
+}
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        Self::force(self)
+    }
+}
@@ -0,0 +1,81 @@
+//! Blocking until a condition holds.
+
+use alloc::collections::VecDeque;
+use core::task::Waker;
+
+use crate::{IrqSafeSpinLock, Once};
+
+/// How a [`WaitQueue`] blocks in a scheduler rather than spinning.
+pub trait Scheduler: Sync {
+    /// Wakes the running thread, or None if it cannot block, such as in an
+    /// interrupt handler or before the hart runs threads.
+    fn waker(&self) -> Option<Waker>;
+    /// Block the running thread until it is woken, possibly returning
+    /// spuriously.
+    fn park(&self);
+}
+
+static SCHEDULER: Once<&'static dyn Scheduler> = Once::new();
+
+/// Install the scheduler that [`WaitQueue`]s block in. Only the first
+/// scheduler installed is used.
+pub fn set_scheduler(scheduler: &'static dyn Scheduler) {
+    SCHEDULER.call_once(|| scheduler);
+}
+
+/// Callers waiting for a condition, woken when it may have changed.
+///
+/// Without a scheduler, waiters spin checking the condition instead, as
+/// notifications from other harts raise no interrupt that could wake an idle
+/// hart.
+pub struct WaitQueue {
+    waiters: IrqSafeSpinLock<VecDeque<Waker>>,
+}
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self { waiters: IrqSafeSpinLock::new(VecDeque::new()) }
+    }
+    /// Block until `condition` holds, checking it again whenever the queue is
+    /// notified.
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        let mut queued = None;
+        while !condition() {
+            let scheduler = SCHEDULER.get();
+            let Some((scheduler, waker)) = scheduler.and_then(|s| Some((s, s.waker()?))) else {
+                core::hint::spin_loop();
+                continue;
+            };
+            let mut waiters = self.waiters.lock();
+            if !waiters.iter().any(|waiter| waiter.will_wake(&waker)) {
+                waiters.push_back(waker.clone());
+            }
+            drop(waiters);
+            queued = Some(waker);
+            // a notification since the last check went to another waiter
+            if condition() {
+                break;
+            }
+            scheduler.park();
+        }
+        // a waker left behind would take a notification meant for others
+        if let Some(waker) = queued {
+            self.waiters.lock().retain(|waiter| !waiter.will_wake(&waker));
+        }
+    }
+    /// Wake the longest waiting caller, returning whether there was one.
+    pub fn notify_one(&self) -> bool {
+        let waker = self.waiters.lock().pop_front();
+        waker.map(Waker::wake).is_some()
+    }
+    pub fn notify_all(&self) {
+        let waiters = core::mem::take(&mut *self.waiters.lock());
+        for waker in waiters {
+            waker.wake();
+        }
+    }
+}
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
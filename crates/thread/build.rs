@@ -0,0 +1,7 @@
+fn main() {
+    configure::Config::load()
+        .cfg()
+        .library("switch", &[
+            "src/switch.s",
+        ]);
+}
@@ -0,0 +1,408 @@
+#![no_std]
+//! Preemptive kernel threads.
+//!
+//! Each thread has its own stack, with an unmapped guard page below it, and
+//! runs on the hart it was spawned on, from that hart's run queue. The highest
+//! priority ready thread runs, sharing the hart round-robin with threads of the
+//! same priority for a time slice each. Every hart runs its idle thread, on the
+//! hart's boot stack, when nothing else is ready.
+
+extern crate alloc;
+
+mod scheduler;
+
+use alloc::{boxed::Box, string::String, sync::Arc, task::Wake, vec::Vec};
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::panic::AssertUnwindSafe;
+use core::task::Waker;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+
+use memory::paging::{self, Flags};
+use memory::{frame, FrameSize, FRAME_SIZE};
+use sync::IrqSafeSpinLock;
+use time::{Duration, Instant};
+
+pub use scheduler::{idle, init_hart, preempt, yield_now};
+
+/// The stack size of threads spawned without one.
+pub const DEFAULT_STACK_SIZE: usize = 0x10000;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+/// Every thread that has not finished, including the idle threads.
+static THREADS: IrqSafeSpinLock<Vec<Thread>> = IrqSafeSpinLock::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(u64);
+impl fmt::Display for ThreadId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Ready threads of a higher priority always run first.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+impl Priority {
+    const COUNT: usize = 3;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum State {
+    /// Waiting in a run queue.
+    Ready,
+    Running,
+    /// Parked until another thread or an interrupt unparks it.
+    Blocked,
+    Finished,
+}
+impl State {
+    fn from_raw(raw: u8) -> Self {
+        match raw {
+            0 => Self::Ready,
+            1 => Self::Running,
+            2 => Self::Blocked,
+            _ => Self::Finished,
+        }
+    }
+}
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ready => write!(f, "ready"),
+            Self::Running => write!(f, "running"),
+            Self::Blocked => write!(f, "blocked"),
+            Self::Finished => write!(f, "finished"),
+        }
+    }
+}
+
+/// The callee-saved registers of a thread that is not running, kept in sync
+/// with `_switch_context`.
+#[repr(C)]
+#[derive(Default)]
+struct Context {
+    ra: usize,
+    sp: usize,
+    s: [usize; 12],
+}
+
+struct Inner {
+    id: ThreadId,
+    name: Option<String>,
+    priority: Priority,
+    /// The hart whose run queue the thread is on.
+    hart: usize,
+    /// A [`State`], only changed under the lock of the thread's run queue.
+    state: AtomicU8,
+    /// Set by [`Thread::unpark`] so that the next [`park`] returns at once.
+    token: AtomicBool,
+    /// Only used by the scheduler of the thread's hart, while switching.
+    context: UnsafeCell<Context>,
+    /// None for an idle thread, which runs on its hart's boot stack, or once
+    /// the thread has finished.
+    stack: UnsafeCell<Option<Stack>>,
+    /// The code to run, taken once the thread starts.
+    entry: UnsafeCell<Option<Box<dyn FnOnce() + Send>>>,
+    /// The thread waiting to join this one.
+    joiner: IrqSafeSpinLock<Option<Thread>>,
+}
+// Safety: the cells are only accessed by the scheduler of the thread's hart,
+// or by the thread itself
+unsafe impl Send for Inner {}
+unsafe impl Sync for Inner {}
+impl Wake for Inner {
+    fn wake(self: Arc<Self>) {
+        Thread(self).unpark();
+    }
+}
+
+/// A handle to a thread.
+#[derive(Clone)]
+pub struct Thread(Arc<Inner>);
+impl Thread {
+    fn new(name: Option<String>, priority: Priority, hart: usize, stack: Option<Stack>) -> Self {
+        Self(Arc::new(Inner {
+            id: ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed)),
+            name,
+            priority,
+            hart,
+            state: AtomicU8::new(State::Ready as u8),
+            token: AtomicBool::new(false),
+            context: UnsafeCell::new(Context::default()),
+            stack: UnsafeCell::new(stack),
+            entry: UnsafeCell::new(None),
+            joiner: IrqSafeSpinLock::new(None),
+        }))
+    }
+    pub fn id(&self) -> ThreadId {
+        self.0.id
+    }
+    pub fn name(&self) -> Option<&str> {
+        self.0.name.as_deref()
+    }
+    pub fn priority(&self) -> Priority {
+        self.0.priority
+    }
+    /// The hart the thread runs on.
+    pub fn hart(&self) -> usize {
+        self.0.hart
+    }
+    pub fn state(&self) -> State {
+        State::from_raw(self.0.state.load(Ordering::Acquire))
+    }
+    fn set_state(&self, state: State) {
+        self.0.state.store(state as u8, Ordering::Release);
+    }
+    fn is(&self, other: &Thread) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+    /// The lowest address of the thread's stack, above its guard page.
+    fn stack_limit(&self) -> usize {
+        // Safety: the stack is only taken once the thread has finished
+        match unsafe { &*self.0.stack.get() } {
+            Some(stack) => stack.base + FRAME_SIZE,
+            None => hart::stack(self.hart()).start,
+        }
+    }
+    /// Make the thread ready if it is parked, or else make its next [`park`]
+    /// return at once.
+    pub fn unpark(&self) {
+        scheduler::unpark(self);
+    }
+}
+impl fmt::Debug for Thread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Thread")
+            .field("id", &self.id())
+            .field("name", &self.name())
+            .field("hart", &self.hart())
+            .finish()
+    }
+}
+
+/// Every thread that has not finished.
+pub fn threads() -> Vec<Thread> {
+    THREADS.lock().clone()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The hart is not running threads.
+    InvalidHart(usize),
+    /// No hart is running threads.
+    NoHarts,
+    /// There is not enough memory for the stack of the thread.
+    OutOfMemory,
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidHart(hart) => write!(f, "hart {hart} is not running threads"),
+            Self::NoHarts => write!(f, "no hart is running threads"),
+            Self::OutOfMemory => write!(f, "out of memory for the thread's stack"),
+        }
+    }
+}
+
+/// Configures a thread before spawning it.
+#[derive(Debug, Default)]
+pub struct Builder {
+    name: Option<String>,
+    priority: Priority,
+    hart: Option<usize>,
+    stack_size: Option<usize>,
+}
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+    /// Run the thread on `hart_id`, rather than the least busy hart.
+    pub fn hart(mut self, hart_id: usize) -> Self {
+        self.hart = Some(hart_id);
+        self
+    }
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = Some(size);
+        self
+    }
+    /// Start a thread running `f`, whose result is returned by
+    /// [`JoinHandle::join`].
+    ///
+    /// A panic in `f` unwinds to the start of the thread and ends it.
+    pub fn spawn<F, T>(self, f: F) -> Result<JoinHandle<T>, Error>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let hart = match self.hart {
+            Some(hart) if scheduler::is_running(hart) => hart,
+            Some(hart) => return Err(Error::InvalidHart(hart)),
+            None => scheduler::least_busy().ok_or(Error::NoHarts)?,
+        };
+        let stack = Stack::new(self.stack_size.unwrap_or(DEFAULT_STACK_SIZE)).ok_or(Error::OutOfMemory)?;
+        let top = stack.top();
+        let thread = Thread::new(self.name, self.priority, hart, Some(stack));
+
+        let result = Arc::new(IrqSafeSpinLock::new(None));
+        let packet = result.clone();
+        let entry: Box<dyn FnOnce() + Send> = Box::new(move || {
+            *packet.lock() = Some(::panic::catch_unwind(AssertUnwindSafe(f)));
+        });
+        // Safety: the thread has not started, so nothing else uses its cells
+        unsafe {
+            *thread.0.entry.get() = Some(entry);
+            *thread.0.context.get() = scheduler::initial_context(&thread, top);
+        }
+        THREADS.lock().push(thread.clone());
+        scheduler::enqueue(thread.clone());
+        Ok(JoinHandle { thread, result })
+    }
+}
+
+/// The stack of a thread, in frames of its own above an unmapped guard page,
+/// so that overflowing it faults rather than overwriting other memory.
+struct Stack {
+    /// The guard page, followed by the stack.
+    base: usize,
+    /// The frames of the guard page and the stack.
+    frames: usize,
+}
+impl Stack {
+    fn new(size: usize) -> Option<Self> {
+        let frames = size.div_ceil(FRAME_SIZE) + 1;
+        let base = frame::allocate_contiguous(frames)?;
+        // Safety: nothing uses the frames yet
+        let guarded = paging::split(base, FrameSize::Size4K).and_then(|()| unsafe { paging::unmap(base) });
+        if guarded.is_err() {
+            // Safety: as above
+            unsafe { frame::free_contiguous(base, frames) };
+            return None;
+        }
+        Some(Self { base, frames })
+    }
+    /// The end of the stack, where it starts from.
+    fn top(&self) -> usize {
+        self.base + self.frames * FRAME_SIZE
+    }
+}
+/// Maps the guard page back before freeing the frames, or leaks them if it
+/// cannot.
+impl Drop for Stack {
+    fn drop(&mut self) {
+        if paging::map(self.base, self.base, FrameSize::Size4K, Flags::READ | Flags::WRITE).is_ok() {
+            // Safety: the stack is only dropped once its thread has finished
+            // and the hart has left it
+            unsafe { frame::free_contiguous(self.base, self.frames) };
+        }
+    }
+}
+
+/// Start a thread running `f` on the least busy hart.
+///
+/// # Panics
+/// Panics if no hart is running threads.
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    Builder::new().spawn(f).expect("failed to spawn thread")
+}
+
+/// Owns a thread's result, which [`JoinHandle::join`] waits for.
+pub struct JoinHandle<T> {
+    thread: Thread,
+    result: Arc<IrqSafeSpinLock<Option<Result<T, String>>>>,
+}
+impl<T> JoinHandle<T> {
+    pub fn thread(&self) -> &Thread {
+        &self.thread
+    }
+    pub fn is_finished(&self) -> bool {
+        self.thread.state() == State::Finished
+    }
+    /// Wait for the thread to finish, returning its result, or the message of
+    /// the panic that ended it.
+    pub fn join(self) -> Result<T, String> {
+        loop {
+            *self.thread.0.joiner.lock() = Some(current());
+            // the thread wakes its joiner after finishing, so this cannot miss
+            // the wakeup
+            if self.is_finished() {
+                break;
+            }
+            park();
+        }
+        self.result.lock().take().expect("finished thread has no result")
+    }
+}
+
+/// The running thread.
+///
+/// # Panics
+/// Panics if the current hart is not running threads.
+pub fn current() -> Thread {
+    scheduler::current().expect("no thread is running on this hart")
+}
+
+/// Block the current thread until [`Thread::unpark`] is called, returning at
+/// once if it already was. It may also return spuriously.
+pub fn park() {
+    scheduler::park();
+}
+
+/// Blocks threads waiting in a [`sync::WaitQueue`], such as for a
+/// [`sync::Mutex`].
+struct ThreadScheduler;
+impl sync::Scheduler for ThreadScheduler {
+    fn waker(&self) -> Option<Waker> {
+        scheduler::blockable().map(|thread| Waker::from(thread.0))
+    }
+    fn park(&self) {
+        scheduler::park();
+    }
+}
+
+/// Block the current thread for at least `duration`.
+pub fn sleep(duration: Duration) {
+    sleep_until(Instant::now() + duration);
+}
+/// Block the current thread until `deadline`, letting others run meanwhile.
+pub fn sleep_until(deadline: Instant) {
+    if time::backend() == time::Backend::None {
+        while Instant::now() < deadline {
+            yield_now();
+        }
+        return;
+    }
+    let thread = current();
+    let timer = time::at(deadline, move || thread.unpark());
+    while Instant::now() < deadline {
+        park();
+    }
+    time::cancel(timer);
+}
+
+/// Finish the current thread. Its joiner is woken once the hart has
+/// switched away from it.
+fn exit() -> ! {
+    let thread = current();
+    THREADS.lock().retain(|other| !other.is(&thread));
+    drop(thread);
+    scheduler::exit()
+}
@@ -0,0 +1,227 @@
+//! Per-hart run queues and switching between threads.
+
+use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use hart::{PerHart, MAX_HARTS};
+use sync::{irq, IrqSafeSpinLock};
+use time::Duration;
+
+use crate::{Context, Inner, Priority, State, Thread};
+
+/// How long a thread runs before others of the same priority get a turn.
+const TIME_SLICE: Duration = Duration::from_millis(10);
+
+struct RunQueue {
+    /// Ready threads, in round-robin order for each priority.
+    ready: [VecDeque<Thread>; Priority::COUNT],
+    /// None until [`init_hart`].
+    current: Option<Thread>,
+    idle: Option<Thread>,
+    /// The thread switched away from, released once the switch completes.
+    previous: Option<Thread>,
+}
+impl RunQueue {
+    fn push(&mut self, thread: Thread) {
+        self.ready[thread.priority() as usize].push_back(thread);
+    }
+    /// Take the next thread of the highest priority.
+    fn pop(&mut self) -> Option<Thread> {
+        self.ready.iter_mut().rev().find_map(VecDeque::pop_front)
+    }
+}
+
+static QUEUES: PerHart<IrqSafeSpinLock<RunQueue>> = PerHart::new([const {
+    IrqSafeSpinLock::new(RunQueue {
+        ready: [const { VecDeque::new() }; Priority::COUNT],
+        current: None,
+        idle: None,
+        previous: None,
+    })
+}; MAX_HARTS]);
+/// Whether each hart should switch threads on return from an interrupt.
+static RESCHEDULE: PerHart<AtomicBool> = PerHart::new([const { AtomicBool::new(false) }; MAX_HARTS]);
+/// How many threads run on each hart, not counting its idle thread.
+static LOAD: PerHart<AtomicUsize> = PerHart::new([const { AtomicUsize::new(0) }; MAX_HARTS]);
+
+extern "C" {
+    fn _switch_context(old: *mut Context, new: *const Context);
+    fn _thread_start();
+}
+
+/// Make the running code the idle thread of the current hart, so that
+/// threads can run on it, and start its time slices.
+pub fn init_hart() {
+    let hart = hart::id();
+    let idle = Thread::new(Some(alloc::format!("idle {hart}")), Priority::Low, hart, None);
+    idle.set_state(State::Running);
+    crate::THREADS.lock().push(idle.clone());
+    let mut queue = QUEUES.get().lock();
+    queue.current = Some(idle.clone());
+    queue.idle = Some(idle);
+    drop(queue);
+    sync::set_scheduler(&crate::ThreadScheduler);
+    time::every(TIME_SLICE, || RESCHEDULE.get().store(true, Ordering::Relaxed));
+}
+
+/// Run threads on the current hart forever, idling it while none are ready.
+///
+/// Called by the code that called [`init_hart`], as its idle thread.
+pub fn idle() -> ! {
+    loop {
+        yield_now();
+        irq::wait();
+    }
+}
+
+/// Let other ready threads of the same or a higher priority run.
+pub fn yield_now() {
+    switch(State::Ready);
+}
+/// Switch threads if the time slice of the current one is over or another
+/// became ready, called on return from an interrupt.
+pub fn preempt() {
+    if RESCHEDULE.get().swap(false, Ordering::Relaxed) {
+        switch(State::Ready);
+    }
+}
+pub(crate) fn park() {
+    switch(State::Blocked);
+}
+pub(crate) fn exit() -> ! {
+    LOAD.get().fetch_sub(1, Ordering::Relaxed);
+    switch(State::Finished);
+    unreachable!("a finished thread was resumed");
+}
+
+pub(crate) fn current() -> Option<Thread> {
+    QUEUES.get().lock().current.clone()
+}
+/// The running thread if it can block, which it cannot from an interrupt
+/// handler or as the idle thread.
+pub(crate) fn blockable() -> Option<Thread> {
+    if !irq::are_enabled() {
+        return None;
+    }
+    let queue = QUEUES.get().lock();
+    let current = queue.current.clone()?;
+    let idle = queue.idle.as_ref().is_some_and(|idle| idle.is(&current));
+    (!idle).then_some(current)
+}
+pub(crate) fn is_running(hart_id: usize) -> bool {
+    hart_id < MAX_HARTS && QUEUES.of(hart_id).lock().idle.is_some()
+}
+/// The hart running threads with the fewest of them.
+pub(crate) fn least_busy() -> Option<usize> {
+    hart::online()
+        .filter(|&hart| is_running(hart))
+        .min_by_key(|&hart| LOAD.of(hart).load(Ordering::Relaxed))
+}
+
+/// The context that starts `thread` at `_thread_start` on the stack below
+/// `top`.
+pub(crate) fn initial_context(thread: &Thread, top: usize) -> Context {
+    let mut context = Context { ra: _thread_start as *const () as usize, sp: top, s: [0; 12] };
+    context.s[0] = alloc::sync::Arc::as_ptr(&thread.0) as usize;
+    context
+}
+/// Add a new thread to the run queue of its hart.
+pub(crate) fn enqueue(thread: Thread) {
+    let hart = thread.hart();
+    LOAD.of(hart).fetch_add(1, Ordering::Relaxed);
+    QUEUES.of(hart).lock().push(thread);
+    reschedule(hart);
+}
+pub(crate) fn unpark(thread: &Thread) {
+    let hart = thread.hart();
+    let mut queue = QUEUES.of(hart).lock();
+    if thread.state() != State::Blocked {
+        // checked by `switch` under the same lock
+        thread.0.token.store(true, Ordering::Release);
+        return;
+    }
+    thread.set_state(State::Ready);
+    queue.push(thread.clone());
+    drop(queue);
+    reschedule(hart);
+}
+/// Have `hart_id` pick a thread to run again.
+fn reschedule(hart_id: usize) {
+    RESCHEDULE.of(hart_id).store(true, Ordering::Relaxed);
+    if hart_id != hart::id() {
+        let _ = ipi::send(1 << hart_id);
+    }
+}
+
+/// Switch from the current thread, which becomes `state`, to the next ready
+/// thread, returning once the current thread runs again.
+fn switch(state: State) {
+    // the switch must not be interrupted, and each thread restores its own
+    // interrupt state once it resumes
+    let interrupts = irq::disable();
+    let mut queue = QUEUES.get().lock();
+    let Some(current) = queue.current.clone() else {
+        drop(queue);
+        irq::restore(interrupts);
+        return;
+    };
+    let idle = queue.idle.as_ref().is_some_and(|idle| idle.is(&current));
+    match state {
+        // already unparked
+        State::Blocked if current.0.token.swap(false, Ordering::Acquire) => {
+            drop(queue);
+            irq::restore(interrupts);
+            return;
+        },
+        State::Ready if !idle => queue.push(current.clone()),
+        _ => {},
+    }
+    current.set_state(state);
+    let next = queue.pop().or_else(|| queue.idle.clone()).expect("hart has no idle thread");
+    next.set_state(State::Running);
+    if next.is(&current) {
+        drop(queue);
+        irq::restore(interrupts);
+        return;
+    }
+
+    let old = current.0.context.get();
+    let new = next.0.context.get().cast_const();
+    hart::set_stack_limit(next.stack_limit());
+    queue.current = Some(next);
+    // keeps `old` alive until the switch completes
+    queue.previous = Some(current);
+    drop(queue);
+    // Safety: each context is only used by this hart's scheduler, and `new`
+    // was saved by a switch away from its thread or set up to start it
+    unsafe { _switch_context(old, new) };
+    finish_switch();
+    irq::restore(interrupts);
+}
+/// Release the thread switched away from, and the stack of a finished one,
+/// waking its joiner.
+fn finish_switch() {
+    let previous = QUEUES.get().lock().previous.take();
+    if let Some(previous) = previous.filter(|previous| previous.state() == State::Finished) {
+        // Safety: the thread will never run again, and the hart has left its
+        // stack
+        drop(unsafe { (*previous.0.stack.get()).take() });
+        let joiner = previous.0.joiner.lock().take();
+        if let Some(joiner) = joiner {
+            joiner.unpark();
+        }
+    }
+}
+
+/// The first Rust code a thread runs, from `_thread_start`.
+#[no_mangle]
+extern "C" fn thread_start(thread: *const Inner) -> ! {
+    finish_switch();
+    irq::restore(true);
+    // Safety: the run queue keeps the running thread alive, and only the
+    // thread itself takes its entry
+    if let Some(entry) = unsafe { (*(*thread).entry.get()).take() } {
+        entry();
+    }
+    crate::exit()
+}
@@ -0,0 +1,44 @@
+.section .text, "ax", %progbits
+
+// Save the callee-saved registers of the current thread in the `Context` at
+// a0, then resume the thread whose `Context` is at a1.
+// The kernel runs with the FPU off, so there are no float registers to save.
+.global _switch_context
+_switch_context:
+    sd ra, 0 * 8(a0)
+    sd sp, 1 * 8(a0)
+    sd s0, 2 * 8(a0)
+    sd s1, 3 * 8(a0)
+    sd s2, 4 * 8(a0)
+    sd s3, 5 * 8(a0)
+    sd s4, 6 * 8(a0)
+    sd s5, 7 * 8(a0)
+    sd s6, 8 * 8(a0)
+    sd s7, 9 * 8(a0)
+    sd s8, 10 * 8(a0)
+    sd s9, 11 * 8(a0)
+    sd s10, 12 * 8(a0)
+    sd s11, 13 * 8(a0)
+
+    ld ra, 0 * 8(a1)
+    ld sp, 1 * 8(a1)
+    ld s0, 2 * 8(a1)
+    ld s1, 3 * 8(a1)
+    ld s2, 4 * 8(a1)
+    ld s3, 5 * 8(a1)
+    ld s4, 6 * 8(a1)
+    ld s5, 7 * 8(a1)
+    ld s6, 8 * 8(a1)
+    ld s7, 9 * 8(a1)
+    ld s8, 10 * 8(a1)
+    ld s9, 11 * 8(a1)
+    ld s10, 12 * 8(a1)
+    ld s11, 13 * 8(a1)
+    ret
+
+// The first code a thread runs, switched to with its `Thread` in s0.
+.global _thread_start
+_thread_start:
+    // thread_start(thread: a0) -> !
+    mv a0, s0
+    j thread_start
@@ -0,0 +1,71 @@
+//! Core-local interruptor, the machine-mode timer and software interrupts.
+//!
+//! Supports the SiFive CLINT, with every register in one region, and the split
+//! ACLINT MTIMER and MSWI devices. Its registers can only be reached from
+//! machine mode once PMP is configured, so the kernel reaches it through SBI.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Both supported machines place a SiFive CLINT here.
+const DEFAULT_BASE: usize = 0x200_0000;
+/// Offsets of the registers in a SiFive CLINT.
+const CLINT_MSIP: usize = 0x0;
+const CLINT_MTIMECMP: usize = 0x4000;
+const CLINT_MTIME: usize = 0xbff8;
+
+static MSIP: AtomicUsize = AtomicUsize::new(DEFAULT_BASE + CLINT_MSIP);
+static MTIMECMP: AtomicUsize = AtomicUsize::new(DEFAULT_BASE + CLINT_MTIMECMP);
+static MTIME: AtomicUsize = AtomicUsize::new(DEFAULT_BASE + CLINT_MTIME);
+
+/// Find the CLINT, or the ACLINT devices, in the device tree.
+///
+/// Without a device tree, or either kind of device in it, the QEMU layout is
+/// assumed.
+pub fn init(fdt: Option<&fdt::Fdt>) {
+    let Some(fdt) = fdt else {
+        return;
+    };
+    let clint = fdt.find_compatible("riscv,clint0").next()
+        .or_else(|| fdt.find_compatible("sifive,clint0").next())
+        .and_then(|clint| clint.reg().next());
+    if let Some(clint) = clint {
+        let base = clint.address as usize;
+        MSIP.store(base + CLINT_MSIP, Ordering::Relaxed);
+        MTIMECMP.store(base + CLINT_MTIMECMP, Ordering::Relaxed);
+        MTIME.store(base + CLINT_MTIME, Ordering::Relaxed);
+        return;
+    }
+    // the MTIMER lists `mtime` then the `mtimecmp` array
+    if let Some(mtimer) = fdt.find_compatible("riscv,aclint-mtimer").next() {
+        let mut reg = mtimer.reg();
+        if let (Some(mtime), Some(mtimecmp)) = (reg.next(), reg.next()) {
+            MTIME.store(mtime.address as usize, Ordering::Relaxed);
+            MTIMECMP.store(mtimecmp.address as usize, Ordering::Relaxed);
+        }
+    }
+    if let Some(mswi) = fdt.find_compatible("riscv,aclint-mswi").next().and_then(|mswi| mswi.reg().next()) {
+        MSIP.store(mswi.address as usize, Ordering::Relaxed);
+    }
+}
+
+/// The current value of the machine timer.
+pub fn mtime() -> u64 {
+    let mtime = MTIME.load(Ordering::Relaxed) as *const u64;
+    unsafe { mtime.read_volatile() }
+}
+/// Raise a machine timer interrupt on `hart` once `mtime` reaches `deadline`.
+///
+/// # Safety
+/// Must be called from machine mode.
+pub unsafe fn set_mtimecmp(hart: usize, deadline: u64) {
+    let mtimecmp = MTIMECMP.load(Ordering::Relaxed) as *mut u64;
+    mtimecmp.add(hart).write_volatile(deadline);
+}
+/// Raise or clear a machine software interrupt on `hart`.
+///
+/// # Safety
+/// Must be called from machine mode.
+pub unsafe fn set_msip(hart: usize, pending: bool) {
+    let msip = MSIP.load(Ordering::Relaxed) as *mut u32;
+    msip.add(hart).write_volatile(pending as u32);
+}
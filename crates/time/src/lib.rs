@@ -0,0 +1,242 @@
+#![no_std]
+//! Kernel time: a monotonic clock, timer callbacks and sleeping.
+//!
+//! The clock is the `time` CSR, counting at the timebase frequency from the
+//! device tree. Timer interrupts are raised with the `stimecmp` CSR where the
+//! hart has the Sstc extension, or through the SBI timer extension otherwise.
+
+extern crate alloc;
+
+pub mod clint;
+pub mod timer;
+
+use core::fmt;
+use core::ops::{Add, AddAssign, Sub};
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+pub use core::time::Duration;
+pub use timer::{after, at, cancel, every, TimerId};
+
+/// The QEMU `virt` timebase, assumed without a device tree.
+const DEFAULT_FREQUENCY: u64 = 10_000_000;
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// Ticks of the `time` CSR per second.
+static FREQUENCY: AtomicU64 = AtomicU64::new(DEFAULT_FREQUENCY);
+/// The value of `time` when [`init`] was called.
+static BOOT: AtomicU64 = AtomicU64::new(0);
+/// How timer interrupts are raised, as a [`Backend`].
+static BACKEND: AtomicU8 = AtomicU8::new(Backend::None as u8);
+
+/// How timer interrupts are raised.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Backend {
+    /// Timer interrupts are unavailable, so sleeping spins.
+    None,
+    /// The `stimecmp` CSR of the Sstc extension.
+    Sstc,
+    /// The SBI timer extension.
+    Sbi,
+}
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Sstc => write!(f, "Sstc"),
+            Self::Sbi => write!(f, "SBI"),
+        }
+    }
+}
+
+/// Read the timebase frequency and choose how to raise timer interrupts on
+/// `hart_id`, then enable them.
+pub fn init(hart_id: usize, fdt: Option<&fdt::Fdt>) {
+    BOOT.store(read_time(), Ordering::Relaxed);
+    if let Some(frequency) = fdt.and_then(timebase_frequency) {
+        FREQUENCY.store(frequency, Ordering::Relaxed);
+    }
+    let backend = if fdt.is_some_and(|fdt| has_sstc(fdt, hart_id)) {
+        Backend::Sstc
+    } else if sbi::base::probe_extension(sbi::time::EID) {
+        Backend::Sbi
+    } else {
+        Backend::None
+    };
+    BACKEND.store(backend as u8, Ordering::Relaxed);
+    init_hart();
+}
+/// Enable timer interrupts on a hart started after [`init`], which raises
+/// them the same way as the boot hart.
+pub fn init_hart() {
+    if backend() != Backend::None {
+        set_deadline(None);
+        enable_interrupt();
+    }
+}
+
+/// Ticks of the `time` CSR per second.
+pub fn frequency() -> u64 {
+    FREQUENCY.load(Ordering::Relaxed)
+}
+pub fn backend() -> Backend {
+    match BACKEND.load(Ordering::Relaxed) {
+        1 => Backend::Sstc,
+        2 => Backend::Sbi,
+        _ => Backend::None,
+    }
+}
+/// The time since [`init`].
+pub fn uptime() -> Duration {
+    Instant::now().duration_since(Instant(BOOT.load(Ordering::Relaxed)))
+}
+
+/// The `timebase-frequency` of `/cpus`, or of the first cpu listing it.
+pub fn timebase_frequency(fdt: &fdt::Fdt) -> Option<u64> {
+    let cpus = fdt.find_node("/cpus")?;
+    let frequency = |node: fdt::Node| {
+        let property = node.property("timebase-frequency")?;
+        property.as_u32().map(u64::from).or_else(|| property.as_u64())
+    };
+    frequency(cpus).or_else(|| cpus.children().find_map(frequency))
+}
+/// Whether the cpu node of `hart_id` lists the Sstc extension, in either the
+/// `riscv,isa` string or the `riscv,isa-extensions` list.
+pub fn has_sstc(fdt: &fdt::Fdt, hart_id: usize) -> bool {
+    let Some(cpu) = fdt.find_node("/cpus").and_then(|cpus| {
+        cpus.children()
+            .find(|cpu| cpu.reg().next().is_some_and(|reg| reg.address == hart_id as u64))
+    }) else {
+        return false;
+    };
+    let isa = cpu.property("riscv,isa").and_then(|isa| isa.as_str())
+        .is_some_and(|isa| isa.split('_').any(|extension| extension.eq_ignore_ascii_case("sstc")));
+    let extensions = cpu.property("riscv,isa-extensions")
+        .is_some_and(|extensions| extensions.as_strings().any(|extension| extension == "sstc"));
+    isa || extensions
+}
+
+/// A point on the monotonic clock, in ticks of the `time` CSR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+impl Instant {
+    pub fn now() -> Self {
+        Self(read_time())
+    }
+    pub const fn from_ticks(ticks: u64) -> Self {
+        Self(ticks)
+    }
+    pub const fn ticks(self) -> u64 {
+        self.0
+    }
+    /// The time from `earlier` to `self`, or zero if `earlier` is later.
+    pub fn duration_since(self, earlier: Self) -> Duration {
+        ticks_to_duration(self.0.saturating_sub(earlier.0))
+    }
+    pub fn checked_duration_since(self, earlier: Self) -> Option<Duration> {
+        self.0.checked_sub(earlier.0).map(ticks_to_duration)
+    }
+    pub fn elapsed(self) -> Duration {
+        Self::now().duration_since(self)
+    }
+    pub fn checked_add(self, duration: Duration) -> Option<Self> {
+        self.0.checked_add(duration_to_ticks(duration)).map(Self)
+    }
+}
+impl Add<Duration> for Instant {
+    type Output = Self;
+    /// Saturates at the end of time rather than overflowing.
+    fn add(self, duration: Duration) -> Self {
+        Self(self.0.saturating_add(duration_to_ticks(duration)))
+    }
+}
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+impl Sub for Instant {
+    type Output = Duration;
+    fn sub(self, earlier: Self) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+fn ticks_to_duration(ticks: u64) -> Duration {
+    let nanos = ticks as u128 * NANOS_PER_SEC / frequency() as u128;
+    Duration::new((nanos / NANOS_PER_SEC) as u64, (nanos % NANOS_PER_SEC) as u32)
+}
+/// Round up so that a deadline is never early.
+fn duration_to_ticks(duration: Duration) -> u64 {
+    let ticks = (duration.as_nanos() * frequency() as u128).div_ceil(NANOS_PER_SEC);
+    ticks.try_into().unwrap_or(u64::MAX)
+}
+
+/// Wait for at least `duration`.
+pub fn sleep(duration: Duration) {
+    sleep_until(Instant::now() + duration)
+}
+/// Wait until `deadline`, idling the hart if a timer interrupt can wake it.
+pub fn sleep_until(deadline: Instant) {
+    if backend() == Backend::None {
+        while Instant::now() < deadline {
+            core::hint::spin_loop();
+        }
+        return;
+    }
+    // the interrupt only needs to wake the hart
+    let timer = at(deadline, || {});
+    loop {
+        // with interrupts masked, the timer firing after the check still
+        // wakes the hart
+        let interrupts = sync::irq::disable();
+        if Instant::now() >= deadline {
+            sync::irq::restore(interrupts);
+            break;
+        }
+        sync::irq::wait();
+        sync::irq::restore(interrupts);
+    }
+    cancel(timer);
+}
+
+/// Handle a supervisor timer interrupt.
+pub fn interrupt() {
+    timer::run();
+}
+
+/// Raise a timer interrupt at `deadline`, or never, clearing any pending one.
+fn set_deadline(deadline: Option<Instant>) {
+    let ticks = deadline.map_or(u64::MAX, Instant::ticks);
+    match backend() {
+        Backend::Sstc => write_stimecmp(ticks),
+        Backend::Sbi => {
+            let _ = sbi::time::set_timer(ticks);
+        },
+        Backend::None => {},
+    }
+}
+
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+fn read_time() -> u64 {
+    let time: u64;
+    unsafe { core::arch::asm!("rdtime {}", out(reg) time) };
+    time
+}
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+fn write_stimecmp(ticks: u64) {
+    // stimecmp
+    unsafe { core::arch::asm!("csrw 0x14d, {}", in(reg) ticks) };
+}
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+fn enable_interrupt() {
+    const STIE: usize = 1 << 5;
+    unsafe { core::arch::asm!("csrs sie, {}", in(reg) STIE) };
+}
+
+#[cfg(not(any(target_arch = "riscv64", target_arch = "riscv32")))]
+fn read_time() -> u64 { 0 }
+#[cfg(not(any(target_arch = "riscv64", target_arch = "riscv32")))]
+fn write_stimecmp(_: u64) {}
+#[cfg(not(any(target_arch = "riscv64", target_arch = "riscv32")))]
+fn enable_interrupt() {}
@@ -0,0 +1,127 @@
+//! One-shot and periodic timer callbacks.
+//!
+//! Each hart has its own queue of timers, whose callbacks run on that hart
+//! from the timer interrupt, with interrupts masked, and must not block. The
+//! hardware timer of each hart is always armed for its earliest deadline.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use hart::{PerHart, MAX_HARTS};
+use sync::IrqSafeSpinLock;
+
+use crate::{Duration, Instant};
+
+/// Identifies a timer so that it can be cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimerId {
+    /// The hart whose queue holds the timer.
+    hart: usize,
+    id: u64,
+}
+
+struct Timer {
+    id: TimerId,
+    deadline: Instant,
+    /// The interval between runs of a periodic timer.
+    period: Option<Duration>,
+    callback: Box<dyn FnMut() + Send>,
+}
+
+struct Queue {
+    /// Pending timers, latest deadline first.
+    timers: Vec<Timer>,
+    /// The periodic timer whose callback is running, cleared if it is
+    /// cancelled meanwhile.
+    running: Option<TimerId>,
+}
+
+static QUEUES: PerHart<IrqSafeSpinLock<Queue>> = PerHart::new([const {
+    IrqSafeSpinLock::new(Queue { timers: Vec::new(), running: None })
+}; MAX_HARTS]);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Run `callback` on the current hart once, at `deadline`.
+pub fn at(deadline: Instant, callback: impl FnMut() + Send + 'static) -> TimerId {
+    insert(deadline, None, Box::new(callback))
+}
+/// Run `callback` on the current hart once, after `delay`.
+pub fn after(delay: Duration, callback: impl FnMut() + Send + 'static) -> TimerId {
+    insert(Instant::now() + delay, None, Box::new(callback))
+}
+/// Run `callback` on the current hart every `period`, starting one `period`
+/// from now.
+///
+/// # Panics
+/// Panics if `period` is zero.
+pub fn every(period: Duration, callback: impl FnMut() + Send + 'static) -> TimerId {
+    assert!(!period.is_zero(), "timer period must not be zero");
+    insert(Instant::now() + period, Some(period), Box::new(callback))
+}
+/// Stop a timer from running again, returning whether it was pending.
+pub fn cancel(id: TimerId) -> bool {
+    let mut queue = QUEUES.of(id.hart).lock();
+    if queue.running == Some(id) {
+        queue.running = None;
+        return true;
+    }
+    let Some(index) = queue.timers.iter().position(|timer| timer.id == id) else {
+        return false;
+    };
+    let timer = queue.timers.remove(index);
+    // another hart's timer only fires early, finding nothing to run
+    if id.hart == hart::id() {
+        rearm(&queue);
+    }
+    // drop the callback outside the lock
+    drop(queue);
+    drop(timer);
+    true
+}
+
+fn insert(deadline: Instant, period: Option<Duration>, callback: Box<dyn FnMut() + Send>) -> TimerId {
+    let id = TimerId { hart: hart::id(), id: NEXT_ID.fetch_add(1, Ordering::Relaxed) };
+    let mut queue = QUEUES.get().lock();
+    push(&mut queue, Timer { id, deadline, period, callback });
+    rearm(&queue);
+    id
+}
+fn push(queue: &mut Queue, timer: Timer) {
+    let index = queue.timers.partition_point(|queued| queued.deadline > timer.deadline);
+    queue.timers.insert(index, timer);
+}
+/// Arm the hardware timer for the earliest deadline.
+fn rearm(queue: &Queue) {
+    crate::set_deadline(queue.timers.last().map(|timer| timer.deadline));
+}
+
+/// Run every callback on the current hart whose deadline has passed, then
+/// rearm.
+pub(crate) fn run() {
+    let local = QUEUES.get();
+    loop {
+        let mut queue = local.lock();
+        let now = Instant::now();
+        let Some(mut timer) = queue.timers.pop_if(|timer| timer.deadline <= now) else {
+            rearm(&queue);
+            return;
+        };
+        queue.running = timer.period.map(|_| timer.id);
+        drop(queue);
+
+        (timer.callback)();
+
+        let Some(period) = timer.period else {
+            continue;
+        };
+        let mut queue = local.lock();
+        if queue.running.take() == Some(timer.id) {
+            // skip missed periods rather than running them back to back
+            timer.deadline += period;
+            if timer.deadline <= now {
+                timer.deadline = now + period;
+            }
+            push(&mut queue, timer);
+        }
+    }
+}